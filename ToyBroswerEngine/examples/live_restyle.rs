@@ -0,0 +1,90 @@
+// Edits a document the way a live preview would, restyling incrementally after each change, and
+// checks every time that the result is the same as styling the document from scratch.
+//
+//     cargo run --example live_restyle
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use ToyBroswerEngine::*;
+use ToyBroswerEngine::restyle::Document;
+
+const CSS: &str = "
+    body { margin: 8px; }
+    .section { padding: 4px; }
+    .theme-dark p { color: white; background: black; }
+    li.active { font-weight: bold; }
+    li.selected + li { margin-top: 4px; }
+    li:last-child { margin-bottom: 8px; }
+    #main .note { color: #336699; }
+    li::before { content: attr(title); }
+";
+
+fn main() {
+    let mut html = String::from("<html><body id=\"main\">");
+    for i in 0..50 {
+        write!(html, "<div class=\"section\"><p>Section {}</p><ul>", i).unwrap();
+        for j in 0..10 {
+            write!(html, "<li title=\"{}\">Item {}</li>", j, j).unwrap();
+        }
+        html.push_str("</ul></div>");
+    }
+    html.push_str("</body></html>");
+
+    let mut stylesheet = css::parse(CSS.to_string());
+    let mut document = Document::new(html::parse(html));
+    let media = media::MediaEnvironment::screen(800.0, 600.0);
+    restyle(&mut document, &stylesheet, &media, "initial style");
+
+    // The paths are child indices from <html>: body, section, list, item.
+    document.set_attribute(&[0, 3, 1, 2], "class", "active");
+    restyle(&mut document, &stylesheet, &media, "add a class to an item");
+
+    document.set_attribute(&[0, 3, 1, 2], "class", "active selected");
+    restyle(&mut document, &stylesheet, &media, "add a class that a sibling selector uses");
+
+    document.set_attribute(&[0, 7], "class", "section theme-dark");
+    restyle(&mut document, &stylesheet, &media, "add a class that a descendant selector uses");
+
+    document.set_attribute(&[0, 7, 0], "title", "Seven");
+    restyle(&mut document, &stylesheet, &media, "set an attribute no selector uses");
+
+    document.set_attribute(&[0, 3, 1, 4], "title", "Four");
+    restyle(&mut document, &stylesheet, &media, "set an attribute that generated content shows");
+
+    let item = dom::elem("li".to_string(), HashMap::new(), vec![dom::text("New".to_string())]);
+    document.insert_child(&[0, 12, 1], 10, item);
+    restyle(&mut document, &stylesheet, &media, "append an item, changing :last-child");
+
+    document.remove_child(&[0, 12, 1], 0);
+    restyle(&mut document, &stylesheet, &media, "remove an item");
+
+    document.set_text(&[0, 20, 0, 0], "Renamed");
+    restyle(&mut document, &stylesheet, &media, "change some text");
+
+    let edited = css::parse(CSS.replace("font-weight: bold;", "font-weight: bold; color: red;"));
+    document.stylesheet_changed(&stylesheet, &edited);
+    stylesheet = edited;
+    restyle(&mut document, &stylesheet, &media, "edit the rule for active items");
+
+    let edited = css::parse(format!("{} .section > p {{ font-size: 20px; }}", CSS));
+    document.stylesheet_changed(&stylesheet, &edited);
+    stylesheet = edited;
+    restyle(&mut document, &stylesheet, &media, "add a rule for paragraphs");
+
+    let edited = css::parse(CSS.replace("padding: 4px", "padding: 6px"));
+    document.stylesheet_changed(&stylesheet, &edited);
+    stylesheet = edited;
+    restyle(&mut document, &stylesheet, &media, "edit the rule for sections");
+
+    restyle(&mut document, &stylesheet, &media, "nothing changed");
+}
+
+fn restyle(document: &mut Document, stylesheet: &css::Stylesheet, media: &media::MediaEnvironment, change: &str) {
+    let cascade = style::Cascade::author(stylesheet);
+    let damage = document.restyle(&cascade, media);
+    println!("{:<48} restyled {:>4} elements, damage {:?}", change, document.stats().elements, damage);
+    let expected = style::style_tree(document.root(), &cascade, media);
+    assert!(document.style_tree() == expected, "Incremental restyle after \"{}\" differs from a full restyle", change);
+}
@@ -0,0 +1,74 @@
+// Styles a wide document serially and in parallel, checks that both give the same style tree,
+// and times them.
+//
+//     cargo run --release --features parallel --example parallel_style_benchmark [SECTIONS] [RUNS]
+#![allow(non_snake_case)]
+
+use std::env;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use ToyBroswerEngine::*;
+
+fn main() {
+    let args: Vec<usize> = env::args().skip(1)
+        .map(|arg| arg.parse().unwrap_or_else(|_| panic!("Invalid count {}", arg)))
+        .collect();
+    let sections = args.first().copied().unwrap_or(2000);
+    let runs = args.get(1).copied().unwrap_or(5);
+
+    let stylesheet = css::parse(generate_css());
+    let root = html::parse(generate_html(sections));
+    let cascade = style::Cascade::author(&stylesheet);
+    let media = media::MediaEnvironment::screen(800.0, 600.0);
+
+    let (mut serial, mut parallel) = (Duration::ZERO, Duration::ZERO);
+    for run in 0..runs {
+        let start = Instant::now();
+        let (serial_tree, serial_stats) = style::style_tree_with_stats(&root, &cascade, &media);
+        serial += start.elapsed();
+
+        let start = Instant::now();
+        let (parallel_tree, parallel_stats) = style::parallel_style_tree_with_stats(&root, &cascade, &media);
+        parallel += start.elapsed();
+
+        // The trees must match on every run, however the work was split between threads.
+        assert!(serial_tree == parallel_tree, "Run {}: the parallel style tree differs from the serial one", run);
+        assert_eq!(serial_stats.elements, parallel_stats.elements);
+        if run == 0 {
+            println!("{} elements, {} threads", serial_stats.elements, rayon::current_num_threads());
+        }
+    }
+    println!("serial:   {:?} per run", serial / runs as u32);
+    println!("parallel: {:?} per run", parallel / runs as u32);
+}
+
+/// Rules that depend on classes, ancestors, siblings and position, plus generated content whose
+/// counters depend on document order.
+fn generate_css() -> String {
+    let mut css = String::from("body { counter-reset: section; }\n");
+    css.push_str(".section { counter-increment: section; margin: 4px; }\n");
+    css.push_str(".section::before { content: counter(section) \". \"; }\n");
+    css.push_str(".section > h2 { font-size: 1.5em; }\n");
+    css.push_str(".section:nth-child(odd) { background: #eeeeee; }\n");
+    css.push_str("li + li { margin-top: 2px; }\n");
+    css.push_str("li:last-child { color: red; }\n");
+    for i in 0..100 {
+        writeln!(css, ".section .tag{} {{ padding-left: {}px; }}", i, i % 7).unwrap();
+    }
+    css
+}
+
+/// Many sections side by side under the body, each with a heading and a short list.
+fn generate_html(sections: usize) -> String {
+    let mut html = String::from("<html><body>");
+    for i in 0..sections {
+        write!(html, "<div class=\"section\"><h2>Section {}</h2><ul>", i).unwrap();
+        for j in 0..5 {
+            write!(html, "<li class=\"tag{}\">Item {}</li>", (i + j) % 100, j).unwrap();
+        }
+        html.push_str("</ul></div>");
+    }
+    html.push_str("</body></html>");
+    html
+}
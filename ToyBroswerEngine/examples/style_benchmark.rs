@@ -0,0 +1,94 @@
+// Times parsing and styling a generated document with thousands of rules and elements.
+//
+//     cargo run --release --example style_benchmark [RULES] [ELEMENTS]
+#![allow(non_snake_case)]
+
+use std::env;
+use std::fmt::Write;
+use std::time::Instant;
+
+use ToyBroswerEngine::*;
+
+fn main() {
+    let args: Vec<usize> = env::args().skip(1)
+        .map(|arg| arg.parse().unwrap_or_else(|_| panic!("Invalid count {}", arg)))
+        .collect();
+    let rule_count = args.first().copied().unwrap_or(5000);
+    let element_count = args.get(1).copied().unwrap_or(5000);
+
+    let css = generate_css(rule_count);
+    let html = generate_html(element_count);
+
+    let start = Instant::now();
+    let stylesheet = css::parse(css);
+    let parse_css = start.elapsed();
+
+    let start = Instant::now();
+    let root = html::parse(html);
+    let parse_html = start.elapsed();
+
+    let start = Instant::now();
+    let cascade = style::Cascade::author(&stylesheet);
+    let media = media::MediaEnvironment::screen(800.0, 600.0);
+    let (_, stats) = style::style_tree_with_stats(&root, &cascade, &media);
+    let style = start.elapsed();
+
+    println!("{} rules, {} elements", rule_count, count_elements(&root));
+    println!("parse CSS:  {:?}", parse_css);
+    println!("parse HTML: {:?}", parse_html);
+    println!("style:      {:?}", style);
+    println!("style sharing: {} of {} candidates ({:.1}%)", stats.shared, stats.sharing_candidates,
+             stats.hit_rate() * 100.0);
+}
+
+/// A mix of the selector shapes found in real style sheets: ids, classes, tags, descendant and
+/// child combinators, and universal rules.
+fn generate_css(count: usize) -> String {
+    let mut css = String::new();
+    for i in 0..count {
+        let selector = match i % 8 {
+            0 => format!("#item{}", i),
+            1 => format!(".class{}", i % 200),
+            2 => format!("div.class{} > p", i % 200),
+            3 => format!(".section{} .class{}", i % 20, i % 200),
+            4 => format!("ul li.class{}", i % 200),
+            5 => format!("#item{} span", i),
+            6 => format!("article .missing{} p", i),
+            _ => ":first-child".to_string(),
+        };
+        writeln!(css, "{} {{ margin-left: {}px; color: #{:06x}; }}", selector, i % 10, i * 2654435761 % 0x1000000).unwrap();
+    }
+    css
+}
+
+/// Sections of nested divs, paragraphs and lists, with ids and classes that some rules match,
+/// each ending with a list of identical items.
+fn generate_html(count: usize) -> String {
+    let mut html = String::from("<html><body>");
+    let mut i = 0;
+    while i < count {
+        write!(html, "<div class=\"section{}\">", i % 20).unwrap();
+        for _ in 0..10 {
+            write!(html, "<div id=\"item{}\" class=\"class{}\"><p>Text<span>more</span></p>", i, i % 200).unwrap();
+            write!(html, "<ul><li class=\"class{}\">One</li><li>Two</li></ul></div>", (i + 1) % 200).unwrap();
+            i += 7;
+        }
+        html.push_str("<ul class=\"list\">");
+        for _ in 0..10 {
+            html.push_str("<li class=\"item\">Item</li>");
+        }
+        html.push_str("</ul>");
+        html.push_str("</div>");
+        i += 1;
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn count_elements(node: &dom::Node) -> usize {
+    let own = match node.node_type {
+        dom::NodeType::Element(_) => 1,
+        dom::NodeType::Text(_) => 0,
+    };
+    own + node.children.iter().map(count_elements).sum::<usize>()
+}
@@ -0,0 +1,803 @@
+use std::collections::HashMap;
+use crate::css::{Color, ContentItem, MathExpr, Unit, Value};
+
+/// The computed style of a node, with typed fields for everything layout and painting use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedStyle {
+    pub display: Display,
+    pub position: Position,
+    pub float: Float,
+    pub clear: Clear,
+    /// The `top`, `right`, `bottom` and `left` properties.
+    pub inset: Sides<LengthPercentageOrAuto>,
+    pub z_index: Option<i32>,
+    pub overflow: Overflow,
+    pub visibility: Visibility,
+    pub opacity: f32,
+
+    pub box_sizing: BoxSizing,
+    pub width: LengthPercentageOrAuto,
+    pub height: LengthPercentageOrAuto,
+    pub min_width: LengthPercentageOrAuto,
+    pub min_height: LengthPercentageOrAuto,
+    /// `None` for `max-width: none`.
+    pub max_width: Option<LengthPercentage>,
+    pub max_height: Option<LengthPercentage>,
+    pub margin: Sides<LengthPercentageOrAuto>,
+    pub padding: Sides<LengthPercentage>,
+    /// Border widths in px. A side whose style is `none` or `hidden` has no width.
+    pub border_width: Sides<f32>,
+    pub border_style: Sides<BorderStyle>,
+    pub border_color: Sides<Color>,
+
+    pub background_color: Color,
+
+    pub color: Color,
+    pub font_family: Vec<FontFamily>,
+    /// The font size in px.
+    pub font_size: f32,
+    pub font_style: FontStyle,
+    /// A weight from 1 to 1000, where 400 is normal and 700 is bold.
+    pub font_weight: u16,
+    pub line_height: LineHeight,
+    pub text_align: TextAlign,
+    pub text_indent: LengthPercentage,
+    pub text_transform: TextTransform,
+    pub text_decoration: TextDecoration,
+    /// Extra space between letters and words, in px.
+    pub letter_spacing: f32,
+    pub word_spacing: f32,
+    pub white_space: WhiteSpace,
+    pub vertical_align: VerticalAlign,
+    pub direction: Direction,
+
+    /// A counter style name, or `none`.
+    pub list_style_type: String,
+    pub list_style_position: ListStylePosition,
+    pub content: Content,
+    pub counter_reset: Vec<(String, i32)>,
+    pub counter_increment: Vec<(String, i32)>,
+    pub counter_set: Vec<(String, i32)>,
+}
+
+/// A value for each side of a box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sides<T> {
+    pub top: T,
+    pub right: T,
+    pub bottom: T,
+    pub left: T,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentage {
+    Px(f32),
+    Percent(f32),
+    /// A math expression with percentages in it. Its other lengths are in px.
+    Calc(Box<MathExpr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentageOrAuto {
+    Auto,
+    LengthPercentage(LengthPercentage),
+}
+
+/// How an element's box takes part in its parent's layout, and how it lays out its children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Display {
+    /// The element and its descendants generate no boxes.
+    None,
+    /// The element generates no box, and its children's boxes take its place.
+    Contents,
+    /// A box with an outer and an inner display type, and optionally a list marker.
+    Box { outside: DisplayOutside, inside: DisplayInside, list_item: bool },
+    /// A part of a table, which only takes part in table layout.
+    Internal(DisplayInternal),
+}
+
+/// Whether a box is laid out as a block or on a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayOutside {
+    Block,
+    Inline,
+}
+
+/// The formatting context a box lays out its children in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayInside {
+    /// Block and inline layout, in the parent's formatting context where it can be.
+    Flow,
+    /// Block and inline layout, in a new block formatting context.
+    FlowRoot,
+    Table,
+    Flex,
+    Grid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayInternal {
+    TableRowGroup,
+    TableHeaderGroup,
+    TableFooterGroup,
+    TableRow,
+    TableCell,
+    TableColumnGroup,
+    TableColumn,
+    TableCaption,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Position {
+    Static,
+    Relative,
+    Absolute,
+    Fixed,
+    Sticky,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Float {
+    None,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clear {
+    None,
+    Left,
+    Right,
+    Both,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+    Clip,
+    Scroll,
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Visible,
+    Hidden,
+    Collapse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxSizing {
+    ContentBox,
+    BorderBox,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorderStyle {
+    None,
+    Hidden,
+    Dotted,
+    Dashed,
+    Solid,
+    Double,
+    Groove,
+    Ridge,
+    Inset,
+    Outset,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FontFamily {
+    Generic(GenericFamily),
+    Named(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GenericFamily {
+    Serif,
+    SansSerif,
+    Monospace,
+    Cursive,
+    Fantasy,
+    SystemUi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineHeight {
+    Normal,
+    /// A multiple of the font size.
+    Number(f32),
+    Px(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+    Start,
+    End,
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextTransform {
+    None,
+    Uppercase,
+    Lowercase,
+    Capitalize,
+}
+
+/// The lines drawn by `text-decoration`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+    BreakSpaces,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerticalAlign {
+    Baseline,
+    Sub,
+    Super,
+    TextTop,
+    TextBottom,
+    Middle,
+    Top,
+    Bottom,
+    LengthPercentage(LengthPercentage),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListStylePosition {
+    Outside,
+    Inside,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Content {
+    Normal,
+    None,
+    Items(Vec<ContentItem>),
+}
+
+impl LengthPercentage {
+    /// The length in px, with percentages relative to `percent_basis`.
+    pub fn resolve(&self, percent_basis: f32) -> f32 {
+        match self {
+            LengthPercentage::Px(px) => *px,
+            LengthPercentage::Percent(percent) => percent / 100.0 * percent_basis,
+            LengthPercentage::Calc(expr) => expr.resolve(percent_basis),
+        }
+    }
+}
+
+impl LengthPercentageOrAuto {
+    /// The length in px, or `None` for `auto`.
+    pub fn resolve(&self, percent_basis: f32) -> Option<f32> {
+        match self {
+            LengthPercentageOrAuto::Auto => None,
+            LengthPercentageOrAuto::LengthPercentage(length) => Some(length.resolve(percent_basis)),
+        }
+    }
+}
+
+impl Display {
+    pub const BLOCK: Display = Display::Box { outside: DisplayOutside::Block, inside: DisplayInside::Flow, list_item: false };
+    pub const INLINE: Display = Display::Box { outside: DisplayOutside::Inline, inside: DisplayInside::Flow, list_item: false };
+
+    /// The outer display type, if the element generates a box that isn't part of a table.
+    pub fn outside(self) -> Option<DisplayOutside> {
+        match self {
+            Display::Box { outside, .. } => Some(outside),
+            _ => None
+        }
+    }
+
+    /// The inner display type, if the element generates a box that isn't part of a table.
+    pub fn inside(self) -> Option<DisplayInside> {
+        match self {
+            Display::Box { inside, .. } => Some(inside),
+            _ => None
+        }
+    }
+
+    pub fn is_inline_level(self) -> bool {
+        self.outside() == Some(DisplayOutside::Inline)
+    }
+
+    /// The display of a box that has to be block-level, such as a float or a flex item.
+    /// Inline-level boxes become block-level, with `inline-block` becoming `block`, and parts of
+    /// tables become blocks.
+    pub fn blockify(self) -> Display {
+        match self {
+            Display::Box { outside: DisplayOutside::Inline, inside, list_item } => {
+                let inside = if inside == DisplayInside::FlowRoot { DisplayInside::Flow } else { inside };
+                Display::Box { outside: DisplayOutside::Block, inside, list_item }
+            }
+            Display::Internal(_) => Display::BLOCK,
+            display => display
+        }
+    }
+
+    /// The computed value, in its shortest form.
+    pub(crate) fn to_value(self) -> Value {
+        let keyword = |keyword: &str| Value::Keyword(keyword.to_string());
+        let (outside, inside, list_item) = match self {
+            Display::None => return keyword("none"),
+            Display::Contents => return keyword("contents"),
+            Display::Internal(internal) => return keyword(match internal {
+                DisplayInternal::TableRowGroup => "table-row-group",
+                DisplayInternal::TableHeaderGroup => "table-header-group",
+                DisplayInternal::TableFooterGroup => "table-footer-group",
+                DisplayInternal::TableRow => "table-row",
+                DisplayInternal::TableCell => "table-cell",
+                DisplayInternal::TableColumnGroup => "table-column-group",
+                DisplayInternal::TableColumn => "table-column",
+                DisplayInternal::TableCaption => "table-caption",
+            }),
+            Display::Box { outside, inside, list_item } => (outside, inside, list_item),
+        };
+        if list_item {
+            let mut keywords = Vec::new();
+            if outside == DisplayOutside::Inline {
+                keywords.push(keyword("inline"));
+            }
+            if inside == DisplayInside::FlowRoot {
+                keywords.push(keyword("flow-root"));
+            }
+            keywords.push(keyword("list-item"));
+            return if keywords.len() == 1 { keywords.remove(0) } else { Value::List(keywords) };
+        }
+        keyword(match (outside, inside) {
+            (DisplayOutside::Block, DisplayInside::Flow) => "block",
+            (DisplayOutside::Inline, DisplayInside::Flow) => "inline",
+            (DisplayOutside::Block, DisplayInside::FlowRoot) => "flow-root",
+            (DisplayOutside::Inline, DisplayInside::FlowRoot) => "inline-block",
+            (DisplayOutside::Block, DisplayInside::Table) => "table",
+            (DisplayOutside::Inline, DisplayInside::Table) => "inline-table",
+            (DisplayOutside::Block, DisplayInside::Flex) => "flex",
+            (DisplayOutside::Inline, DisplayInside::Flex) => "inline-flex",
+            (DisplayOutside::Block, DisplayInside::Grid) => "grid",
+            (DisplayOutside::Inline, DisplayInside::Grid) => "inline-grid",
+        })
+    }
+}
+
+impl ComputedStyle {
+    /// Convert computed values, which have lengths in px and `currentcolor` resolved, into typed
+    /// fields. Values that aren't supported fall back to the property's initial value.
+    pub(crate) fn from_values(values: &HashMap<String, Value>) -> ComputedStyle {
+        let get = |name: &str| values.get(name);
+        let length_or_auto = |value: Option<&Value>| value.and_then(length_percentage_or_auto)
+            .unwrap_or(LengthPercentageOrAuto::Auto);
+        let length_or_zero = |value: Option<&Value>| value.and_then(length_percentage)
+            .unwrap_or(LengthPercentage::Px(0.0));
+        let px = |value: Option<&Value>| match value.and_then(length_percentage) {
+            Some(LengthPercentage::Px(px)) => px,
+            _ => 0.0
+        };
+
+        let color = get("color").and_then(color).unwrap_or(BLACK);
+        let border_style = sides(values, "border-", "-style", |value| {
+            value.and_then(keyword).and_then(border_style).unwrap_or(BorderStyle::None)
+        });
+        ComputedStyle {
+            display: get("display").and_then(display).unwrap_or(Display::INLINE),
+            position: typed(values, "position", position).unwrap_or(Position::Static),
+            float: typed(values, "float", float).unwrap_or(Float::None),
+            clear: typed(values, "clear", clear).unwrap_or(Clear::None),
+            inset: sides(values, "", "", length_or_auto),
+            z_index: match get("z-index") {
+                Some(&Value::Number(n)) => Some(n as i32),
+                _ => None
+            },
+            overflow: typed(values, "overflow", overflow).unwrap_or(Overflow::Visible),
+            visibility: typed(values, "visibility", visibility).unwrap_or(Visibility::Visible),
+            opacity: match get("opacity") {
+                Some(&Value::Number(n)) => n.clamp(0.0, 1.0),
+                Some(&Value::Length(f, Unit::Percent)) => (f / 100.0).clamp(0.0, 1.0),
+                _ => 1.0
+            },
+
+            box_sizing: typed(values, "box-sizing", box_sizing).unwrap_or(BoxSizing::ContentBox),
+            width: length_or_auto(get("width")),
+            height: length_or_auto(get("height")),
+            min_width: length_or_auto(get("min-width")),
+            min_height: length_or_auto(get("min-height")),
+            max_width: get("max-width").and_then(length_percentage),
+            max_height: get("max-height").and_then(length_percentage),
+            margin: sides(values, "margin-", "", length_or_auto),
+            padding: sides(values, "padding-", "", length_or_zero),
+            border_width: sides(values, "border-", "-width", px),
+            border_style,
+            border_color: sides(values, "border-", "-color", |value| value.and_then(self::color).unwrap_or(color)),
+
+            background_color: get("background-color").and_then(self::color).unwrap_or(TRANSPARENT),
+
+            color,
+            font_family: match get("font-family") {
+                Some(Value::CommaList(families)) => families.iter().filter_map(font_family).collect(),
+                _ => vec![FontFamily::Generic(GenericFamily::Serif)]
+            },
+            font_size: px(get("font-size")),
+            font_style: typed(values, "font-style", font_style).unwrap_or(FontStyle::Normal),
+            font_weight: match get("font-weight") {
+                Some(&Value::Number(weight)) => weight.clamp(1.0, 1000.0) as u16,
+                _ => 400
+            },
+            line_height: match get("line-height") {
+                Some(&Value::Number(n)) => LineHeight::Number(n),
+                Some(&Value::Length(px, Unit::Px)) => LineHeight::Px(px),
+                _ => LineHeight::Normal
+            },
+            text_align: typed(values, "text-align", text_align).unwrap_or(TextAlign::Start),
+            text_indent: length_or_zero(get("text-indent")),
+            text_transform: typed(values, "text-transform", text_transform).unwrap_or(TextTransform::None),
+            text_decoration: get("text-decoration").map(text_decoration).unwrap_or_default(),
+            letter_spacing: px(get("letter-spacing")),
+            word_spacing: px(get("word-spacing")),
+            white_space: typed(values, "white-space", white_space).unwrap_or(WhiteSpace::Normal),
+            vertical_align: match get("vertical-align") {
+                Some(value @ Value::Length(..)) => length_percentage(value)
+                    .map_or(VerticalAlign::Baseline, VerticalAlign::LengthPercentage),
+                _ => typed(values, "vertical-align", vertical_align).unwrap_or(VerticalAlign::Baseline)
+            },
+            direction: typed(values, "direction", direction).unwrap_or(Direction::Ltr),
+
+            list_style_type: match get("list-style-type") {
+                Some(Value::Keyword(name)) => name.clone(),
+                _ => "disc".to_string()
+            },
+            list_style_position: typed(values, "list-style-position", list_style_position)
+                .unwrap_or(ListStylePosition::Outside),
+            content: match get("content") {
+                Some(Value::Content(items)) => Content::Items(items.clone()),
+                Some(Value::Keyword(k)) if k == "none" => Content::None,
+                _ => Content::Normal
+            },
+            counter_reset: counters(get("counter-reset")),
+            counter_increment: counters(get("counter-increment")),
+            counter_set: counters(get("counter-set")),
+        }
+    }
+}
+
+/// Whether the computed style understands a keyword value of a property, for `@supports`.
+/// Properties whose keywords aren't converted to a type here accept any keyword.
+pub(crate) fn supports_keyword(name: &str, keyword: &str) -> bool {
+    match name {
+        "display" => display(&Value::Keyword(keyword.to_string())).is_some(),
+        "position" => position(keyword).is_some(),
+        "float" => float(keyword).is_some(),
+        "clear" => clear(keyword).is_some(),
+        "overflow" => overflow(keyword).is_some(),
+        "visibility" => visibility(keyword).is_some(),
+        "box-sizing" => box_sizing(keyword).is_some(),
+        "font-style" => font_style(keyword).is_some(),
+        "text-align" => text_align(keyword).is_some(),
+        "text-transform" => text_transform(keyword).is_some(),
+        "white-space" => white_space(keyword).is_some(),
+        "vertical-align" => vertical_align(keyword).is_some(),
+        "direction" => direction(keyword).is_some(),
+        "list-style-position" => list_style_position(keyword).is_some(),
+        _ if name.starts_with("border-") && name.ends_with("-style") => border_style(keyword).is_some(),
+        "top" | "right" | "bottom" | "left" | "width" | "height" | "min-width" | "min-height"
+        | "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => keyword == "auto",
+        "max-width" | "max-height" => keyword == "none",
+        _ if name.starts_with("padding-") => false,
+        _ => true
+    }
+}
+
+/// The keyword value of a property, converted with `convert`.
+fn typed<T>(values: &HashMap<String, Value>, name: &str, convert: fn(&str) -> Option<T>) -> Option<T> {
+    values.get(name).and_then(keyword).and_then(convert)
+}
+
+/// The four properties `<prefix><side><suffix>`, converted with `convert`.
+fn sides<T>(values: &HashMap<String, Value>, prefix: &str, suffix: &str, convert: impl Fn(Option<&Value>) -> T) -> Sides<T> {
+    let side = |side: &str| convert(values.get(&format!("{}{}{}", prefix, side, suffix)));
+    Sides { top: side("top"), right: side("right"), bottom: side("bottom"), left: side("left") }
+}
+
+const BLACK: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+const TRANSPARENT: Color = Color { r: 0, g: 0, b: 0, a: 0 };
+
+fn keyword(value: &Value) -> Option<&str> {
+    match value {
+        Value::Keyword(keyword) => Some(keyword),
+        _ => None
+    }
+}
+
+fn length_percentage(value: &Value) -> Option<LengthPercentage> {
+    match *value {
+        Value::Length(px, Unit::Px) => Some(LengthPercentage::Px(px)),
+        Value::Length(percent, Unit::Percent) => Some(LengthPercentage::Percent(percent)),
+        // A unitless zero is a valid length.
+        Value::Number(0.0) => Some(LengthPercentage::Px(0.0)),
+        Value::Math(ref expr) if !expr.is_number() => Some(LengthPercentage::Calc(Box::new(expr.clone()))),
+        _ => None
+    }
+}
+
+fn length_percentage_or_auto(value: &Value) -> Option<LengthPercentageOrAuto> {
+    match value {
+        Value::Keyword(k) if k == "auto" => Some(LengthPercentageOrAuto::Auto),
+        value => length_percentage(value).map(LengthPercentageOrAuto::LengthPercentage),
+    }
+}
+
+fn color(value: &Value) -> Option<Color> {
+    match *value {
+        Value::ColorValue(color) => Some(color),
+        _ => None
+    }
+}
+
+fn counters(value: Option<&Value>) -> Vec<(String, i32)> {
+    match value {
+        Some(Value::Counters(counters)) => counters.clone(),
+        _ => Vec::new()
+    }
+}
+
+fn font_family(value: &Value) -> Option<FontFamily> {
+    match value {
+        Value::String(name) => Some(FontFamily::Named(name.clone())),
+        Value::Keyword(name) => Some(FontFamily::Generic(match &**name {
+            "serif" => GenericFamily::Serif,
+            "sans-serif" => GenericFamily::SansSerif,
+            "monospace" => GenericFamily::Monospace,
+            "cursive" => GenericFamily::Cursive,
+            "fantasy" => GenericFamily::Fantasy,
+            "system-ui" => GenericFamily::SystemUi,
+            _ => return None
+        })),
+        _ => None
+    }
+}
+
+fn text_decoration(value: &Value) -> TextDecoration {
+    let mut decoration = TextDecoration::default();
+    let lines = match value {
+        Value::List(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    for line in lines.into_iter().filter_map(keyword) {
+        match line {
+            "underline" => decoration.underline = true,
+            "overline" => decoration.overline = true,
+            "line-through" => decoration.line_through = true,
+            _ => {}
+        }
+    }
+    decoration
+}
+
+/// Parse `display`, either as a single keyword like `inline-block`, or as the outer and inner
+/// display types and `list-item` in any order, like `inline flow-root list-item`.
+pub(crate) fn display(value: &Value) -> Option<Display> {
+    let keywords: Vec<&str> = match value {
+        Value::Keyword(keyword) => vec![keyword],
+        Value::List(values) => values.iter().map(keyword).collect::<Option<_>>()?,
+        _ => return None
+    };
+    let internal = |internal| Some(Display::Internal(internal));
+    let pair = |outside, inside| Some(Display::Box { outside, inside, list_item: false });
+    let single = match keywords[..] {
+        ["none"] => Some(Display::None),
+        ["contents"] => Some(Display::Contents),
+        ["inline-block"] => pair(DisplayOutside::Inline, DisplayInside::FlowRoot),
+        ["inline-table"] => pair(DisplayOutside::Inline, DisplayInside::Table),
+        ["inline-flex"] => pair(DisplayOutside::Inline, DisplayInside::Flex),
+        ["inline-grid"] => pair(DisplayOutside::Inline, DisplayInside::Grid),
+        ["table-row-group"] => internal(DisplayInternal::TableRowGroup),
+        ["table-header-group"] => internal(DisplayInternal::TableHeaderGroup),
+        ["table-footer-group"] => internal(DisplayInternal::TableFooterGroup),
+        ["table-row"] => internal(DisplayInternal::TableRow),
+        ["table-cell"] => internal(DisplayInternal::TableCell),
+        ["table-column-group"] => internal(DisplayInternal::TableColumnGroup),
+        ["table-column"] => internal(DisplayInternal::TableColumn),
+        ["table-caption"] => internal(DisplayInternal::TableCaption),
+        _ => None
+    };
+    if single.is_some() {
+        return single;
+    }
+
+    // A missing outer type is `block`, and a missing inner type `flow`.
+    let (mut outside, mut inside, mut list_item) = (None, None, false);
+    for keyword in keywords {
+        match keyword {
+            "block" if outside.is_none() => outside = Some(DisplayOutside::Block),
+            "inline" if outside.is_none() => outside = Some(DisplayOutside::Inline),
+            "flow" if inside.is_none() => inside = Some(DisplayInside::Flow),
+            "flow-root" if inside.is_none() => inside = Some(DisplayInside::FlowRoot),
+            "table" if inside.is_none() => inside = Some(DisplayInside::Table),
+            "flex" if inside.is_none() => inside = Some(DisplayInside::Flex),
+            "grid" if inside.is_none() => inside = Some(DisplayInside::Grid),
+            "list-item" if !list_item => list_item = true,
+            _ => return None
+        }
+    }
+    let inside = inside.unwrap_or(DisplayInside::Flow);
+    // Only block containers can have list markers.
+    if list_item && !matches!(inside, DisplayInside::Flow | DisplayInside::FlowRoot) {
+        return None;
+    }
+    Some(Display::Box { outside: outside.unwrap_or(DisplayOutside::Block), inside, list_item })
+}
+
+fn position(keyword: &str) -> Option<Position> {
+    Some(match keyword {
+        "static" => Position::Static,
+        "relative" => Position::Relative,
+        "absolute" => Position::Absolute,
+        "fixed" => Position::Fixed,
+        "sticky" => Position::Sticky,
+        _ => return None
+    })
+}
+
+fn float(keyword: &str) -> Option<Float> {
+    Some(match keyword {
+        "none" => Float::None,
+        "left" => Float::Left,
+        "right" => Float::Right,
+        _ => return None
+    })
+}
+
+fn clear(keyword: &str) -> Option<Clear> {
+    Some(match keyword {
+        "none" => Clear::None,
+        "left" => Clear::Left,
+        "right" => Clear::Right,
+        "both" => Clear::Both,
+        _ => return None
+    })
+}
+
+fn overflow(keyword: &str) -> Option<Overflow> {
+    Some(match keyword {
+        "visible" => Overflow::Visible,
+        "hidden" => Overflow::Hidden,
+        "clip" => Overflow::Clip,
+        "scroll" => Overflow::Scroll,
+        "auto" => Overflow::Auto,
+        _ => return None
+    })
+}
+
+fn visibility(keyword: &str) -> Option<Visibility> {
+    Some(match keyword {
+        "visible" => Visibility::Visible,
+        "hidden" => Visibility::Hidden,
+        "collapse" => Visibility::Collapse,
+        _ => return None
+    })
+}
+
+fn box_sizing(keyword: &str) -> Option<BoxSizing> {
+    Some(match keyword {
+        "content-box" => BoxSizing::ContentBox,
+        "border-box" => BoxSizing::BorderBox,
+        _ => return None
+    })
+}
+
+fn border_style(keyword: &str) -> Option<BorderStyle> {
+    Some(match keyword {
+        "none" => BorderStyle::None,
+        "hidden" => BorderStyle::Hidden,
+        "dotted" => BorderStyle::Dotted,
+        "dashed" => BorderStyle::Dashed,
+        "solid" => BorderStyle::Solid,
+        "double" => BorderStyle::Double,
+        "groove" => BorderStyle::Groove,
+        "ridge" => BorderStyle::Ridge,
+        "inset" => BorderStyle::Inset,
+        "outset" => BorderStyle::Outset,
+        _ => return None
+    })
+}
+
+fn font_style(keyword: &str) -> Option<FontStyle> {
+    Some(match keyword {
+        "normal" => FontStyle::Normal,
+        "italic" => FontStyle::Italic,
+        "oblique" => FontStyle::Oblique,
+        _ => return None
+    })
+}
+
+fn text_align(keyword: &str) -> Option<TextAlign> {
+    Some(match keyword {
+        "start" => TextAlign::Start,
+        "end" => TextAlign::End,
+        "left" => TextAlign::Left,
+        "right" => TextAlign::Right,
+        "center" => TextAlign::Center,
+        "justify" => TextAlign::Justify,
+        _ => return None
+    })
+}
+
+fn text_transform(keyword: &str) -> Option<TextTransform> {
+    Some(match keyword {
+        "none" => TextTransform::None,
+        "uppercase" => TextTransform::Uppercase,
+        "lowercase" => TextTransform::Lowercase,
+        "capitalize" => TextTransform::Capitalize,
+        _ => return None
+    })
+}
+
+fn white_space(keyword: &str) -> Option<WhiteSpace> {
+    Some(match keyword {
+        "normal" => WhiteSpace::Normal,
+        "pre" => WhiteSpace::Pre,
+        "nowrap" => WhiteSpace::Nowrap,
+        "pre-wrap" => WhiteSpace::PreWrap,
+        "pre-line" => WhiteSpace::PreLine,
+        "break-spaces" => WhiteSpace::BreakSpaces,
+        _ => return None
+    })
+}
+
+fn vertical_align(keyword: &str) -> Option<VerticalAlign> {
+    Some(match keyword {
+        "baseline" => VerticalAlign::Baseline,
+        "sub" => VerticalAlign::Sub,
+        "super" => VerticalAlign::Super,
+        "text-top" => VerticalAlign::TextTop,
+        "text-bottom" => VerticalAlign::TextBottom,
+        "middle" => VerticalAlign::Middle,
+        "top" => VerticalAlign::Top,
+        "bottom" => VerticalAlign::Bottom,
+        _ => return None
+    })
+}
+
+fn direction(keyword: &str) -> Option<Direction> {
+    Some(match keyword {
+        "ltr" => Direction::Ltr,
+        "rtl" => Direction::Rtl,
+        _ => return None
+    })
+}
+
+fn list_style_position(keyword: &str) -> Option<ListStylePosition> {
+    Some(match keyword {
+        "outside" => ListStylePosition::Outside,
+        "inside" => ListStylePosition::Inside,
+        _ => return None
+    })
+}
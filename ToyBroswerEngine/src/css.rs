@@ -1,9 +1,99 @@
 use std::str::FromStr;
-use num_traits::Num;
+use crate::color::{hsl_to_rgb, hwb_to_rgb, named_color};
+use crate::media::{Comparison, MediaCondition, MediaFeature, MediaQuery};
+use crate::properties;
 
 #[derive(Debug)]
 pub struct Stylesheet {
-    pub rules: Vec<Rule>,
+    pub rules: Vec<CssRule>,
+    /// Problems that made the parser drop part of the style sheet, such as unknown properties.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum CssRule {
+    Style(Rule),
+    Media(MediaRule),
+    Import(ImportRule),
+    Supports(SupportsRule),
+    Layer(LayerRule),
+    /// `@layer a, b;`, which sets the order of layers without adding rules to them.
+    LayerStatement(Vec<String>),
+    FontFace(FontFaceRule),
+}
+
+/// An `@media` rule: rules that only apply when one of the queries matches.
+#[derive(Debug)]
+pub struct MediaRule {
+    pub queries: Vec<MediaQuery>,
+    pub rules: Vec<CssRule>,
+}
+
+/// An `@supports` rule: rules that only apply if the engine supports a feature.
+#[derive(Debug)]
+pub struct SupportsRule {
+    pub condition: SupportsCondition,
+    /// Whether the condition holds, which is decided when the rule is parsed.
+    pub supported: bool,
+    pub rules: Vec<CssRule>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SupportsCondition {
+    /// `(name: value)`: the property is supported and the value is valid for it.
+    Declaration(String, String),
+    /// `selector(...)`: the selector list is valid.
+    Selector(String),
+    Not(Box<SupportsCondition>),
+    And(Vec<SupportsCondition>),
+    Or(Vec<SupportsCondition>),
+    /// Anything else in parentheses, which is never supported.
+    Unknown(String),
+}
+
+/// An `@layer` block, which puts its rules in a cascade layer.
+#[derive(Debug)]
+pub struct LayerRule {
+    /// The layer's name, which may be a dotted path to a nested layer. Empty for an anonymous layer.
+    pub name: String,
+    pub rules: Vec<CssRule>,
+}
+
+/// An `@font-face` rule, which makes a font available under a family name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontFaceRule {
+    pub family: Option<String>,
+    /// Where to get the font, in order of preference.
+    pub sources: Vec<FontSource>,
+    /// The range of weights the face covers.
+    pub weight: (u16, u16),
+    /// `normal`, `italic` or `oblique`.
+    pub style: String,
+    /// The code points the face covers, as inclusive ranges. Empty if it covers all of them.
+    pub unicode_range: Vec<(u32, u32)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FontSource {
+    /// `url()`, with the format given by `format()`, if any. The loader resolves the URL
+    /// against the style sheet's URL.
+    Url(String, Option<String>),
+    /// `local()`: the full name or PostScript name of an installed font.
+    Local(String),
+}
+
+/// An `@import` rule. Parsing only records the URL; the imported style sheet is fetched
+/// afterwards by [`crate::loader::load_imports`].
+#[derive(Debug)]
+pub struct ImportRule {
+    /// The URL as written, relative to the importing style sheet.
+    pub url: String,
+    /// The cascade layer to put the imported rules in. `Some("")` for an anonymous `layer`.
+    pub layer: Option<String>,
+    /// The import only applies when one of these queries matches.
+    pub queries: Vec<MediaQuery>,
+    /// The imported style sheet, once loaded. Stays `None` if it couldn't be loaded.
+    pub stylesheet: Option<Box<Stylesheet>>,
 }
 
 #[derive(Debug)]
@@ -12,34 +102,178 @@ pub struct Rule {
     pub declarations: Vec<Declaration>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// `<selector> <combinator> <simple selector>`, e.g. `ul > li.item`. The rightmost simple
+    /// selector is the one matched against the element itself.
+    Complex(Box<Selector>, Combinator, SimpleSelector),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    /// Only allowed on the rightmost simple selector of a selector.
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Combinator {
+    Descendant,        // `a b`
+    Child,             // `a > b`
+    NextSibling,       // `a + b`
+    SubsequentSibling, // `a ~ b`
+}
+
+#[derive(Clone, Debug)]
+pub enum PseudoClass {
+    Root,
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    /// `:nth-child(An+B [of S])`; an empty selector list means no `of` filter.
+    NthChild(Nth, Vec<Selector>),
+    NthLastChild(Nth, Vec<Selector>),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    Where(Vec<Selector>),
+    Has(Vec<RelativeSelector>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PseudoElement {
+    Before,
+    After,
+    /// The bullet or number of a list item.
+    Marker,
+}
+
+/// The `An+B` argument of the `:nth-*` pseudo-classes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+/// A selector anchored at the element being matched, as used by `:has(> img)`.
+#[derive(Clone, Debug)]
+pub struct RelativeSelector {
+    pub combinator: Combinator,
+    pub selector: Selector,
 }
 
 #[derive(Debug)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Declared with `!important`.
+    pub important: bool,
 }
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
+    /// A unitless number, e.g. `line-height: 1.5`.
+    Number(f32),
     ColorValue(Color),
+    /// A quoted string, e.g. a font family name.
+    String(String),
+    /// Space-separated components, e.g. `10px auto`.
+    List(Vec<Value>),
+    /// Comma-separated components, e.g. `"Helvetica Neue", Arial, sans-serif`.
+    CommaList(Vec<Value>),
+    /// The items of a `content` property, e.g. `"Chapter " counter(chapter) ": "`.
+    Content(Vec<ContentItem>),
+    /// The `<name> <integer>` pairs of `counter-reset`, `counter-set` and `counter-increment`.
+    Counters(Vec<(String, i32)>),
+    /// Source text that can't be parsed until `var()` references in it are substituted, and the
+    /// value of a custom property, which can be any text.
+    Unparsed(String),
+    /// A longhand set by a shorthand whose value uses `var()`: the shorthand name and its source.
+    PendingSubstitution(String, String),
+    /// A `calc()`, `min()`, `max()` or `clamp()` expression.
+    Math(MathExpr),
+}
+
+/// The expression tree of a math function. Lengths in other units are converted to px when
+/// the value is computed, but percentages are kept until layout knows what they refer to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum MathExpr {
+    Length(f32, Unit),
+    Number(f32),
+    Add(Box<MathExpr>, Box<MathExpr>),
+    Sub(Box<MathExpr>, Box<MathExpr>),
+    Mul(Box<MathExpr>, Box<MathExpr>),
+    Div(Box<MathExpr>, Box<MathExpr>),
+    Min(Vec<MathExpr>),
+    Max(Vec<MathExpr>),
+    /// `clamp(min, value, max)`
+    Clamp(Box<MathExpr>, Box<MathExpr>, Box<MathExpr>),
 }
 
 #[derive(Clone, PartialEq, Debug)]
+pub enum ContentItem {
+    String(String),
+    /// `attr(name)`: the value of an attribute of the originating element.
+    Attr(String),
+    /// `counter(name, style)`
+    Counter(String, String),
+    /// `counters(name, separator, style)`
+    Counters(String, String, String),
+    OpenQuote,
+    CloseQuote,
+    NoOpenQuote,
+    NoCloseQuote,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Unit {
+    // Absolute lengths
     Px,
+    Cm,
+    Mm,
+    Q,
+    In,
+    Pt,
+    Pc,
+    // Font-relative lengths
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    // Viewport-percentage lengths
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    /// A percentage of some other value, depending on the property.
+    Percent,
+}
+
+impl Unit {
+    /// The size of one unit in px, for the absolute units.
+    pub fn absolute_px(self) -> Option<f32> {
+        match self {
+            Unit::Px => Some(1.0),
+            Unit::In => Some(96.0),
+            Unit::Cm => Some(96.0 / 2.54),
+            Unit::Mm => Some(96.0 / 25.4),
+            Unit::Q => Some(96.0 / 101.6),
+            Unit::Pt => Some(96.0 / 72.0),
+            Unit::Pc => Some(16.0),
+            _ => None
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Default, Debug)]
@@ -57,11 +291,76 @@ pub type Specificity = (usize, usize, usize);
 impl Selector {
     pub fn specificity(&self) -> Specificity {
         // http://www.w3.org/TR/selectors/#specificity
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().len();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().len();
-        (a, b, c)
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Complex(ref left, _, ref right) => {
+                add_specificity(left.specificity(), right.specificity())
+            }
+        }
+    }
+
+    /// The simple selector that is matched against the subject element.
+    pub fn rightmost(&self) -> &SimpleSelector {
+        match *self {
+            Selector::Simple(ref simple) | Selector::Complex(_, _, ref simple) => simple,
+        }
+    }
+
+    /// The pseudo-element this selector styles, if it doesn't style the element itself.
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        self.rightmost().pseudo_element
+    }
+}
+
+impl SimpleSelector {
+    pub fn specificity(&self) -> Specificity {
+        let a = self.id.iter().len();
+        let b = self.class.len();
+        let c = self.tag_name.iter().len() + self.pseudo_element.iter().len();
+        self.pseudo_classes.iter()
+            .fold((a, b, c), |acc, pseudo| add_specificity(acc, pseudo.specificity()))
+    }
+}
+
+impl PseudoClass {
+    pub fn specificity(&self) -> Specificity {
+        match *self {
+            // The specificity of :is(), :not() and :has() is that of their most specific argument.
+            PseudoClass::Not(ref list) | PseudoClass::Is(ref list) => max_specificity(list.iter()),
+            PseudoClass::Has(ref list) => max_specificity(list.iter().map(|rel| &rel.selector)),
+            // :where() never adds specificity.
+            PseudoClass::Where(_) => (0, 0, 0),
+            // :nth-child(An+B of S) counts as a pseudo-class plus its most specific argument.
+            PseudoClass::NthChild(_, ref of) | PseudoClass::NthLastChild(_, ref of) => {
+                add_specificity((0, 1, 0), max_specificity(of.iter()))
+            }
+            _ => (0, 1, 0),
+        }
+    }
+}
+
+impl Nth {
+    /// Does the 1-based position `index` match `An+B` for some n >= 0?
+    pub fn matches(&self, index: i32) -> bool {
+        if self.a == 0 {
+            return index == self.b;
+        }
+        let diff = index - self.b;
+        diff % self.a == 0 && diff / self.a >= 0
+    }
+}
+
+fn add_specificity(x: Specificity, y: Specificity) -> Specificity {
+    (x.0 + y.0, x.1 + y.1, x.2 + y.2)
+}
+
+fn max_specificity<'a, I: Iterator<Item=&'a Selector>>(selectors: I) -> Specificity {
+    selectors.map(|s| s.specificity()).max().unwrap_or((0, 0, 0))
+}
+
+impl Declaration {
+    pub fn new(name: String, value: Value) -> Declaration {
+        Declaration { name, value, important: false }
     }
 }
 
@@ -73,204 +372,2133 @@ impl Value {
             _ => 0.0
         }
     }
+
+    /// Return the size of a length in px, resolving percentages against `percent_basis`,
+    /// or zero for non-lengths.
+    pub fn resolve_px(&self, percent_basis: f32) -> f32 {
+        match *self {
+            Value::Length(f, Unit::Percent) => f / 100.0 * percent_basis,
+            _ => self.to_px()
+        }
+    }
+}
+
+impl MathExpr {
+    /// Whether the expression is a plain number rather than a length.
+    pub fn is_number(&self) -> bool {
+        match self {
+            MathExpr::Length(..) => false,
+            MathExpr::Number(_) => true,
+            MathExpr::Add(a, _) | MathExpr::Sub(a, _) | MathExpr::Div(a, _) => a.is_number(),
+            MathExpr::Mul(a, b) => a.is_number() && b.is_number(),
+            MathExpr::Min(args) | MathExpr::Max(args) => args[0].is_number(),
+            MathExpr::Clamp(_, value, _) => value.is_number(),
+        }
+    }
+
+    pub fn has_percentage(&self) -> bool {
+        match self {
+            MathExpr::Length(_, unit) => *unit == Unit::Percent,
+            MathExpr::Number(_) => false,
+            MathExpr::Add(a, b) | MathExpr::Sub(a, b) | MathExpr::Mul(a, b) | MathExpr::Div(a, b) => {
+                a.has_percentage() || b.has_percentage()
+            }
+            MathExpr::Min(args) | MathExpr::Max(args) => args.iter().any(MathExpr::has_percentage),
+            MathExpr::Clamp(min, value, max) => {
+                min.has_percentage() || value.has_percentage() || max.has_percentage()
+            }
+        }
+    }
+
+    /// Convert every length except percentages to px with `to_px`.
+    pub fn absolutize(&self, to_px: &dyn Fn(f32, Unit) -> f32) -> MathExpr {
+        let map = |expr: &MathExpr| Box::new(expr.absolutize(to_px));
+        match self {
+            MathExpr::Length(f, Unit::Percent) => MathExpr::Length(*f, Unit::Percent),
+            MathExpr::Length(f, unit) => MathExpr::Length(to_px(*f, *unit), Unit::Px),
+            MathExpr::Number(n) => MathExpr::Number(*n),
+            MathExpr::Add(a, b) => MathExpr::Add(map(a), map(b)),
+            MathExpr::Sub(a, b) => MathExpr::Sub(map(a), map(b)),
+            MathExpr::Mul(a, b) => MathExpr::Mul(map(a), map(b)),
+            MathExpr::Div(a, b) => MathExpr::Div(map(a), map(b)),
+            MathExpr::Min(args) => MathExpr::Min(args.iter().map(|arg| arg.absolutize(to_px)).collect()),
+            MathExpr::Max(args) => MathExpr::Max(args.iter().map(|arg| arg.absolutize(to_px)).collect()),
+            MathExpr::Clamp(min, value, max) => MathExpr::Clamp(map(min), map(value), map(max)),
+        }
+    }
+
+    /// Evaluate the expression, in px for lengths, with percentages relative to `percent_basis`.
+    /// Other lengths must have been converted to px by `absolutize`.
+    pub fn resolve(&self, percent_basis: f32) -> f32 {
+        let eval = |expr: &MathExpr| expr.resolve(percent_basis);
+        match self {
+            MathExpr::Length(f, Unit::Px) => *f,
+            MathExpr::Length(f, Unit::Percent) => f / 100.0 * percent_basis,
+            MathExpr::Length(_, unit) => panic!("{:?} must be converted to px before resolving", unit),
+            MathExpr::Number(n) => *n,
+            MathExpr::Add(a, b) => eval(a) + eval(b),
+            MathExpr::Sub(a, b) => eval(a) - eval(b),
+            MathExpr::Mul(a, b) => eval(a) * eval(b),
+            MathExpr::Div(a, b) => eval(a) / eval(b),
+            MathExpr::Min(args) => args.iter().map(eval).fold(f32::INFINITY, f32::min),
+            MathExpr::Max(args) => args.iter().map(eval).fold(f32::NEG_INFINITY, f32::max),
+            // If min is greater than max, min wins.
+            MathExpr::Clamp(min, value, max) => eval(value).min(eval(max)).max(eval(min)),
+        }
+    }
 }
 
 /// Parse a whole CSS stylesheet.
 pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser { pos: 0, input: source };
-    Stylesheet { rules: parser.parse_rules() }
+    let mut parser = Parser::new(&source);
+    let rules = parser.parse_rules(true);
+    Stylesheet { rules, warnings: parser.warnings }
+}
+
+/// Parse a comma-separated media query list, such as the `media` attribute of a `<link>`.
+pub fn parse_media_queries(source: &str) -> Vec<MediaQuery> {
+    let mut parser = Parser::new(source);
+    parser.parse_media_query_list()
 }
 
+/// Parse a comma-separated selector list, such as the argument of `querySelectorAll`.
+pub fn parse_selector_list(source: &str) -> Result<Vec<Selector>, String> {
+    let mut parser = Parser::new(source.trim());
+    let selectors = parser.parse_selector_list()?;
+    if !parser.eof() {
+        return Err(format!("Unexpected character {:?} in selector list", parser.next_char()));
+    }
+    Ok(selectors)
+}
+
+/// Parse the value of a declaration, expanding shorthands. Used once the `var()` references in a
+/// value have been substituted. Returns no declarations for unknown properties.
+pub fn parse_declaration(name: &str, value: &str) -> Vec<Declaration> {
+    let mut parser = Parser::new(&format!("{}: {};", name, value));
+    parser.parse_declaration()
+}
+
+/// Parse a single property value, such as the initial value of a property.
+pub fn parse_value(source: &str) -> Value {
+    let mut parser = Parser::new(source);
+    parser.consume_whitespace();
+    parser.parse_value_list().unwrap_or_else(|error| panic!("Invalid value {}: {}", source, error))
+}
+
+/// A syntax error, or something the parser doesn't support, described for a warning.
+type ParseResult<T> = Result<T, String>;
+
 struct Parser {
+    /// The index of the next character in `input`.
     pos: usize,
-    input: String,
+    input: Vec<char>,
+    warnings: Vec<String>,
 }
 
 impl Parser {
+    fn new(source: &str) -> Parser {
+        Parser { pos: 0, input: source.chars().collect(), warnings: Vec::new() }
+    }
+
     /// Parse a list of rule sets, separated by optional whitespace.
-    fn parse_rules(&mut self) -> Vec<Rule> {
+    /// A nested list ends at the `}` of its parent rule. `@import` is only allowed at the start
+    /// of a style sheet, so `allow_imports` is only set at the top level.
+    fn parse_rules(&mut self, mut allow_imports: bool) -> Vec<CssRule> {
         let mut rules = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.eof() { break }
-            rules.push(self.parse_rule());
-        }
-        return rules;
-    }
-
-    /// Parse a rule set: `<selectors> { <declarations> }`.
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+            let rule = match self.next_char() {
+                None | Some('}') => break,
+                Some('@') => match self.parse_at_rule() {
+                    Some(rule) => rule,
+                    None => continue,
+                },
+                Some(_) => match self.parse_rule() {
+                    Some(rule) => CssRule::Style(rule),
+                    None => continue,
+                },
+            };
+            match rule {
+                CssRule::Import(import) if !allow_imports => {
+                    self.warnings.push(format!("Ignored @import of {} after other rules", import.url));
+                }
+                rule @ (CssRule::Import(_) | CssRule::LayerStatement(_)) => rules.push(rule),
+                rule => {
+                    allow_imports = false;
+                    rules.push(rule);
+                }
+            }
         }
+        rules
     }
 
-    /// Parse a comma-separated list of selectors.
-    fn parse_selectors(&mut self) -> Vec<Selector> {
-        let mut selectors = Vec::new();
-        loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
-            self.consume_whitespace();
-            match self.next_char() {
-                Some(',') => { self.consume_char(); self.consume_whitespace(); }
-                Some('{') => break,
-                c   => panic!("Unexpected character {} in selector list", c.unwrap())
+    /// Parse an at-rule, starting at its `@`. Unsupported at-rules, and at-rules with an invalid
+    /// prelude, are skipped with a warning.
+    fn parse_at_rule(&mut self) -> Option<CssRule> {
+        let start = self.pos;
+        assert_eq!(self.consume_char(), Some('@'));
+        let name = self.parse_identifier().to_ascii_lowercase();
+        self.consume_whitespace();
+        match self.try_parse_at_rule(&name) {
+            Ok(rule) => rule,
+            Err(error) => {
+                self.pos = start;
+                self.skip_rule(true);
+                let source: String = self.input[start..self.pos].iter().collect();
+                let prelude = source.split(['{', ';']).next().unwrap_or_default().trim();
+                self.warnings.push(format!("Skipped rule {}: {}", prelude, error));
+                None
             }
         }
-        // Return selectors with highest specificity first, for use in matching.
-        selectors.sort_by(|a,b| b.specificity().cmp(&a.specificity()));
-        return selectors;
     }
 
-    /// Parse one simple selector, e.g.: `type#id.class1.class2.class3`
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
-        let mut selector = SimpleSelector { tag_name: None, id: None, class: Vec::new() };
-        while !self.eof() {
-            match self.next_char() {
-                Some('#') => {
-                    self.consume_char();
-                    selector.id = Some(self.parse_identifier());
+    fn try_parse_at_rule(&mut self, name: &str) -> ParseResult<Option<CssRule>> {
+        Ok(Some(match name {
+            "media" => {
+                let queries = self.parse_media_query_list();
+                CssRule::Media(MediaRule { queries, rules: self.parse_nested_rules()? })
+            }
+            "import" => {
+                let url = self.parse_url()?;
+                self.consume_whitespace();
+                let layer = match self.starts_with_keyword("layer") {
+                    true => Some(self.parse_import_layer()?),
+                    false => None,
+                };
+                let queries = self.parse_media_query_list();
+                match self.consume_char() {
+                    Some(';') | None => {}
+                    c => return Err(format!("Expected ';' after @import, found {:?}", c)),
                 }
-                Some('.') => {
-                    self.consume_char();
-                    selector.class.push(self.parse_identifier());
+                CssRule::Import(ImportRule { url, layer, queries, stylesheet: None })
+            }
+            "supports" => {
+                let condition = self.parse_supports_condition()?;
+                self.consume_whitespace();
+                let supported = condition.is_supported();
+                CssRule::Supports(SupportsRule { condition, supported, rules: self.parse_nested_rules()? })
+            }
+            "layer" => {
+                let mut names = Vec::new();
+                loop {
+                    self.consume_whitespace();
+                    match self.next_char() {
+                        Some('{') | Some(';') | None => break,
+                        Some(',') => { self.consume_char(); }
+                        Some(_) => names.push(self.parse_layer_name()?),
+                    }
                 }
-                Some('*') => {
-                    // universal selector
+                if self.next_char() == Some('{') {
+                    if names.len() > 1 {
+                        return Err("A @layer block can only have one name".to_string());
+                    }
+                    CssRule::Layer(LayerRule { name: names.pop().unwrap_or_default(), rules: self.parse_nested_rules()? })
+                } else {
                     self.consume_char();
+                    if names.is_empty() {
+                        return Err("Expected a layer name after @layer".to_string());
+                    }
+                    CssRule::LayerStatement(names)
                 }
-                c if valid_identifier_char(c.expect("REASON")) => {
-                    selector.tag_name = Some(self.parse_identifier());
+            }
+            "font-face" => CssRule::FontFace(self.parse_font_face()?),
+            // The encoding has already been decided by the time the text is parsed.
+            "charset" => { self.skip_rule(true); return Ok(None) }
+            _ => {
+                self.warnings.push(format!("Unsupported at-rule @{}", name));
+                self.skip_rule(true);
+                return Ok(None);
+            }
+        }))
+    }
+
+    /// Skip the rest of a rule: its prelude, then its `{ ... }` block. An at-rule can end at a
+    /// `;` instead.
+    fn skip_rule(&mut self, at_rule: bool) {
+        let mut depth = 0;
+        while let Some(c) = self.next_char() {
+            match c {
+                '"' | '\'' => { self.parse_string(); continue }
+                ';' if depth == 0 && at_rule => { self.consume_char(); break }
+                // The end of the enclosing block.
+                '}' if depth == 0 => break,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.consume_char();
+                        break;
+                    }
                 }
-                _ => break
+                _ => {}
             }
+            self.consume_char();
         }
-        return selector;
     }
 
-    /// Parse a list of declarations enclosed in `{ ... }`.
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert!(self.consume_char() == Option::from('{'));
-        let mut declarations = Vec::new();
-        loop {
+    /// Parse a `{ ... }` block of rules. A block that isn't closed ends at the end of the input.
+    fn parse_nested_rules(&mut self) -> ParseResult<Vec<CssRule>> {
+        self.expect_char('{', "at-rule")?;
+        let rules = self.parse_rules(false);
+        self.consume_char();
+        Ok(rules)
+    }
+
+    /// Parse the URL of an `@import`: a string or a `url()` function.
+    fn parse_url(&mut self) -> ParseResult<String> {
+        if let Some('"' | '\'') = self.next_char() {
+            return Ok(self.parse_string());
+        }
+        let name = self.parse_identifier();
+        if !name.eq_ignore_ascii_case("url") {
+            return Err(format!("Expected a URL, found {:?}", name));
+        }
+        self.expect_char('(', "url()")?;
+        self.consume_whitespace();
+        let url = match self.next_char() {
+            Some('"' | '\'') => self.parse_string(),
+            _ => self.consume_while(|c| c != ')' && !c.is_whitespace()),
+        };
+        self.consume_whitespace();
+        self.expect_char(')', "url()")?;
+        Ok(url)
+    }
+
+    /// Parse `layer` or `layer(<name>)` in an `@import`. Anonymous layers have an empty name.
+    fn parse_import_layer(&mut self) -> ParseResult<String> {
+        self.parse_identifier();
+        let mut name = String::new();
+        if self.next_char() == Some('(') {
+            self.consume_char();
             self.consume_whitespace();
-            if self.next_char() == Some('}') {
-                self.consume_char();
-                break;
-            }
-            declarations.push(self.parse_declaration());
+            name = self.parse_layer_name()?;
+            self.consume_whitespace();
+            self.expect_char(')', "layer()")?;
+        }
+        self.consume_whitespace();
+        Ok(name)
+    }
+
+    /// Parse a layer name, which may be a dotted path like `framework.base`.
+    fn parse_layer_name(&mut self) -> ParseResult<String> {
+        let name = self.consume_while(|c| valid_identifier_char(c) || c == '.');
+        if name.is_empty() || name.split('.').any(str::is_empty) {
+            return Err(format!("Invalid layer name {:?}", name));
+        }
+        Ok(name)
+    }
+
+    // Methods for parsing @supports conditions:
+
+    /// Parse `not <in-parens>`, or parenthesized conditions joined by `and` or by `or`.
+    fn parse_supports_condition(&mut self) -> ParseResult<SupportsCondition> {
+        self.consume_whitespace();
+        if self.consume_keyword("not") {
+            return Ok(SupportsCondition::Not(Box::new(self.parse_supports_in_parens()?)));
         }
-        return declarations;
+        let mut conditions = vec![self.parse_supports_in_parens()?];
+        self.consume_whitespace();
+        let and = self.starts_with_keyword("and");
+        let joiner = if and { "and" } else { "or" };
+        while self.consume_keyword(joiner) {
+            conditions.push(self.parse_supports_in_parens()?);
+            self.consume_whitespace();
+        }
+        Ok(match conditions.len() {
+            1 => conditions.pop().unwrap(),
+            _ if and => SupportsCondition::And(conditions),
+            _ => SupportsCondition::Or(conditions),
+        })
     }
 
-    /// Parse one `<property>: <value>;` declaration.
-    fn parse_declaration(&mut self) -> Declaration {
-        let property_name = self.parse_identifier();
+    /// Parse a parenthesized condition or declaration, or a function such as `selector()`.
+    fn parse_supports_in_parens(&mut self) -> ParseResult<SupportsCondition> {
         self.consume_whitespace();
-        assert!(self.consume_char() == Option::from(':'));
+        if self.next_char() != Some('(') {
+            let name = self.parse_identifier();
+            self.expect_char('(', "@supports condition")?;
+            let arguments = self.consume_until_close_paren();
+            self.expect_char(')', "@supports condition")?;
+            return Ok(match &*name.to_ascii_lowercase() {
+                "selector" => SupportsCondition::Selector(arguments.trim().to_string()),
+                _ => SupportsCondition::Unknown(format!("{}({})", name, arguments)),
+            });
+        }
+        self.consume_char();
         self.consume_whitespace();
-        let value = self.parse_value();
+        let condition = if self.next_char() == Some('(') || self.starts_with_keyword("not") && self.keyword_followed_by('(') {
+            self.parse_supports_condition()?
+        } else {
+            let text = self.consume_until_close_paren();
+            match text.split_once(':') {
+                Some((name, value)) if !name.trim().is_empty() && name.trim().chars().all(valid_identifier_char) => {
+                    SupportsCondition::Declaration(name.trim().to_string(), value.trim().to_string())
+                }
+                _ => SupportsCondition::Unknown(text.trim().to_string()),
+            }
+        };
         self.consume_whitespace();
-        assert!(self.consume_char() == Option::from(';'));
+        self.expect_char(')', "@supports condition")?;
+        Ok(condition)
+    }
 
-        Declaration {
-            name: property_name,
-            value: value,
+    /// Consume text up to the `)` that closes the current parentheses, without consuming it.
+    fn consume_until_close_paren(&mut self) -> String {
+        let mut depth = 0;
+        let mut text = String::new();
+        while let Some(c) = self.next_char() {
+            match c {
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                '(' => depth += 1,
+                _ => {}
+            }
+            text.push(c);
+            self.consume_char();
         }
+        text
     }
 
-    // Methods for parsing values:
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            Some(ch) => match ch {
-                '0'..='9' => self.parse_length(),
-                '#' => self.parse_color(),
-                _ => Value::Keyword(self.parse_identifier()),
-            },
-            None => {
-                println!("error: {}. Is not correct", self.next_char().unwrap());
-                Value::Keyword(self.parse_identifier())
+    // Methods for parsing @font-face rules:
+
+    /// Parse the descriptor block of an `@font-face` rule. Invalid descriptors are skipped with a
+    /// warning, and a block that isn't closed ends at the end of the input.
+    fn parse_font_face(&mut self) -> ParseResult<FontFaceRule> {
+        let mut font_face = FontFaceRule {
+            family: None,
+            sources: Vec::new(),
+            weight: (400, 400),
+            style: "normal".to_string(),
+            unicode_range: Vec::new(),
+        };
+        self.expect_char('{', "@font-face")?;
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                Some('}') => { self.consume_char(); break }
+                Some(';') => { self.consume_char(); continue }
+                None => break,
+                Some(_) => {}
+            }
+            let start = self.pos;
+            if let Err(error) = self.parse_font_face_descriptor(&mut font_face) {
+                self.pos = start;
+                self.skip_declaration();
+                let source: String = self.input[start..self.pos].iter().collect();
+                self.warnings.push(format!("Skipped @font-face descriptor {}: {}", source.trim_end_matches(';').trim(), error));
             }
         }
+        Ok(font_face)
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    /// Parse one `<descriptor>: <value>` of an `@font-face` rule into `font_face`.
+    fn parse_font_face_descriptor(&mut self, font_face: &mut FontFaceRule) -> ParseResult<()> {
+        let name = self.parse_identifier().to_ascii_lowercase();
+        self.consume_whitespace();
+        self.expect_char(':', &format!("@font-face descriptor {:?}", name))?;
+        let source = self.consume_unparsed_value();
+        if self.next_char() == Some('!') {
+            return Err("!important isn't allowed in @font-face".to_string());
+        }
+        let mut descriptor = Parser::new(&source);
+        match &*name {
+            "font-family" => font_face.family = Some(match descriptor.next_char() {
+                Some('"' | '\'') => descriptor.parse_string(),
+                _ => source.split_whitespace().collect::<Vec<_>>().join(" "),
+            }),
+            "src" => font_face.sources = descriptor.parse_font_sources()?,
+            "font-weight" => {
+                let weight = |word: &str| match word.to_ascii_lowercase().as_str() {
+                    "normal" => Ok(400),
+                    "bold" => Ok(700),
+                    word => word.parse::<f32>().map(|weight| weight as u16)
+                        .map_err(|_| format!("Invalid font-weight {}", word)),
+                };
+                let weights = source.split_whitespace().map(weight).collect::<ParseResult<Vec<u16>>>()?;
+                font_face.weight = match weights[..] {
+                    [weight] => (weight, weight),
+                    [min, max] => (min.min(max), min.max(max)),
+                    _ => return Err(format!("Invalid font-weight {}", source)),
+                };
+            }
+            "font-style" => font_face.style = descriptor.parse_identifier().to_ascii_lowercase(),
+            "unicode-range" => font_face.unicode_range = source.split(',').map(parse_unicode_range).collect::<ParseResult<_>>()?,
+            _ => self.warnings.push(format!("Unsupported @font-face descriptor {}", name)),
+        }
+        Ok(())
     }
 
-    fn parse_float(&mut self) -> f32 {
-        let s = self.consume_while(|c| match c {
-            '0'..='9' | '.' => true,
-            _ => false
-        });
-        let f: f32 = FromStr::from_str(&s).expect("Failed to parse string to float");
-        f
+    /// Parse the comma-separated list of an `src` descriptor.
+    fn parse_font_sources(&mut self) -> ParseResult<Vec<FontSource>> {
+        let mut sources = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                None => break,
+                Some(',') => { self.consume_char(); continue }
+                Some(_) => {}
+            }
+            if self.starts_with_keyword("local") {
+                self.parse_identifier();
+                self.expect_char('(', "local()")?;
+                let name = match self.next_char() {
+                    Some('"' | '\'') => self.parse_string(),
+                    _ => self.consume_until_close_paren().split_whitespace().collect::<Vec<_>>().join(" "),
+                };
+                self.expect_char(')', "local()")?;
+                sources.push(FontSource::Local(name));
+                continue;
+            }
+            let url = self.parse_url()?;
+            let mut format = None;
+            loop {
+                self.consume_whitespace();
+                if !self.next_char().is_some_and(valid_identifier_char) {
+                    break;
+                }
+                // `format()` is kept, `tech()` is ignored.
+                let hint = self.parse_identifier().to_ascii_lowercase();
+                self.expect_char('(', &format!("{}()", hint))?;
+                let argument = self.consume_until_close_paren();
+                self.expect_char(')', &format!("{}()", hint))?;
+                if hint == "format" {
+                    format = Some(argument.trim().trim_matches(['"', '\'']).to_string());
+                }
+            }
+            sources.push(FontSource::Url(url, format));
+        }
+        Ok(sources)
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match self.parse_identifier().to_ascii_lowercase() {
-            ref s if *s == String::from("px") => Unit::Px,
-            _ => panic!("unrecognized unit")
+    // Methods for parsing media queries:
+
+    /// Parse a comma-separated list of media queries, up to the `{` or `;` after it. An invalid
+    /// query is replaced by `not all`, which never matches, with a warning.
+    fn parse_media_query_list(&mut self) -> Vec<MediaQuery> {
+        let mut queries = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                Some('{') | Some(';') | None => break,
+                Some(',') => { self.consume_char(); continue }
+                Some(_) => {}
+            }
+            let start = self.pos;
+            let query = self.parse_media_query().and_then(|query| match self.next_char() {
+                Some(',' | '{' | ';') | None => Ok(query),
+                c => Err(format!("Unexpected character {:?} in media query", c)),
+            });
+            queries.push(query.unwrap_or_else(|error| {
+                self.pos = start;
+                self.skip_media_query();
+                let source: String = self.input[start..self.pos].iter().collect();
+                self.warnings.push(format!("Invalid media query {}: {}", source.trim(), error));
+                MediaQuery { negated: true, media_type: Some("all".to_string()), condition: None }
+            }));
         }
+        queries
     }
 
-    fn parse_color(&mut self) -> Value {
-        assert!(self.consume_char() == Option::from('#'));
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255 })
+    /// Skip the rest of a media query, up to the `,`, `{` or `;` after it.
+    fn skip_media_query(&mut self) {
+        let mut depth = 0;
+        while let Some(c) = self.next_char() {
+            match c {
+                ',' | '{' | ';' if depth == 0 => break,
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
+        }
     }
 
-    /// Parse two hexadecimal digits.
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos..self.pos + 2];
-        self.pos = self.pos + 2;
-        u8::from_str_radix(s, 16).unwrap()
+    /// Parse a media query: `[not | only] <type> [and <condition>]`, or just a condition.
+    fn parse_media_query(&mut self) -> ParseResult<MediaQuery> {
+        if self.next_char() == Some('(') || self.starts_with_keyword("not") && self.keyword_followed_by('(') {
+            let condition = self.parse_media_condition(true)?;
+            return Ok(MediaQuery { negated: false, media_type: None, condition: Some(condition) });
+        }
+        let negated = self.consume_keyword("not");
+        self.consume_keyword("only");
+        self.consume_whitespace();
+        let media_type = self.parse_identifier().to_ascii_lowercase();
+        if media_type.is_empty() {
+            return Err(format!("Expected a media type, found {:?}", self.next_char()));
+        }
+        self.consume_whitespace();
+        let condition = match self.consume_keyword("and") {
+            true => Some(self.parse_media_condition(false)?),
+            false => None,
+        };
+        Ok(MediaQuery { negated, media_type: Some(media_type), condition })
     }
 
-    /// Parse a property name or keyword.
-    fn parse_identifier(&mut self) -> String {
-        self.consume_while(valid_identifier_char)
+    /// Parse a condition: `not <in-parens>`, or parenthesized conditions and features joined by
+    /// `and`, or by `or` if `allow_or` is set.
+    fn parse_media_condition(&mut self, allow_or: bool) -> ParseResult<MediaCondition> {
+        self.consume_whitespace();
+        if self.consume_keyword("not") {
+            return Ok(MediaCondition::Not(Box::new(self.parse_media_in_parens()?)));
+        }
+        let mut conditions = vec![self.parse_media_in_parens()?];
+        self.consume_whitespace();
+        let and = self.starts_with_keyword("and");
+        let joiner = if and { "and" } else { "or" };
+        if !and && !allow_or {
+            return Ok(conditions.pop().unwrap());
+        }
+        while self.consume_keyword(joiner) {
+            conditions.push(self.parse_media_in_parens()?);
+            self.consume_whitespace();
+        }
+        Ok(match conditions.len() {
+            1 => conditions.pop().unwrap(),
+            _ if and => MediaCondition::And(conditions),
+            _ => MediaCondition::Or(conditions),
+        })
     }
 
-    /// Consume and discard zero or more whitespace characters.
-    fn consume_whitespace(&mut self) {
-        self.consume_while(|c| c.is_whitespace());
+    /// Parse a parenthesized condition or media feature.
+    fn parse_media_in_parens(&mut self) -> ParseResult<MediaCondition> {
+        self.consume_whitespace();
+        self.expect_char('(', "media query")?;
+        self.consume_whitespace();
+        let condition = if self.next_char() == Some('(') || self.starts_with_keyword("not") {
+            self.parse_media_condition(true)?
+        } else {
+            MediaCondition::Feature(self.parse_media_feature()?)
+        };
+        self.consume_whitespace();
+        self.expect_char(')', "media query")?;
+        Ok(condition)
     }
 
-    /// Consume characters until `test` returns false.
-    fn consume_while<F: Fn(char) -> bool>(&mut self, test: F) -> String {
-        let mut result = String::new();
-        while !self.eof() && test(self.next_char().unwrap()) {
-            result.push(self.consume_char().unwrap());
+    /// Parse a media feature inside parentheses: `name`, `name: value`, or a range like
+    /// `name >= value` or `value < name <= value`.
+    fn parse_media_feature(&mut self) -> ParseResult<MediaFeature> {
+        let leading_value = match self.next_char() {
+            Some('0'..='9' | '.') => true,
+            Some('-' | '+') => self.starts_with_number(),
+            _ => false,
+        };
+        let mut comparisons = Vec::new();
+        if leading_value {
+            // `value < name` is the same as `name > value`.
+            let value = self.parse_media_value()?;
+            self.consume_whitespace();
+            let comparison = match self.parse_comparison()? {
+                Comparison::Less => Comparison::Greater,
+                Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+                Comparison::Greater => Comparison::Less,
+                Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+                Comparison::Equal => Comparison::Equal,
+            };
+            comparisons.push((comparison, value));
+            self.consume_whitespace();
+        }
+        let mut name = self.parse_identifier().to_ascii_lowercase();
+        if name.is_empty() {
+            return Err(format!("Expected a media feature, found {:?}", self.next_char()));
+        }
+        self.consume_whitespace();
+        match self.next_char() {
+            Some(':') => {
+                self.consume_char();
+                self.consume_whitespace();
+                let value = self.parse_media_value()?;
+                let comparison = if let Some(feature) = name.strip_prefix("min-") {
+                    name = feature.to_string();
+                    Comparison::GreaterOrEqual
+                } else if let Some(feature) = name.strip_prefix("max-") {
+                    name = feature.to_string();
+                    Comparison::LessOrEqual
+                } else {
+                    Comparison::Equal
+                };
+                comparisons.push((comparison, value));
+            }
+            Some('<' | '>' | '=') => {
+                let comparison = self.parse_comparison()?;
+                self.consume_whitespace();
+                comparisons.push((comparison, self.parse_media_value()?));
+            }
+            _ => {}
         }
-        return result;
+        Ok(MediaFeature { name, comparisons })
     }
 
-    /// Return the current character, and advance self.pos to the next character.
-    fn consume_char(&mut self) -> Option<char> {
-        let ch = self.input.chars().nth(self.pos);
-        self.pos += 1;
-        return ch;
+    fn parse_comparison(&mut self) -> ParseResult<Comparison> {
+        let comparison = match self.consume_char() {
+            Some('<') => Comparison::Less,
+            Some('>') => Comparison::Greater,
+            Some('=') => return Ok(Comparison::Equal),
+            c => return Err(format!("Expected a comparison in media query, found {:?}", c)),
+        };
+        if self.next_char() != Some('=') {
+            return Ok(comparison);
+        }
+        self.consume_char();
+        Ok(match comparison {
+            Comparison::Less => Comparison::LessOrEqual,
+            _ => Comparison::GreaterOrEqual,
+        })
     }
 
-    /// Read the current character without consuming it.
-    fn next_char(&self) -> Option<char> {
-        self.input.chars().nth(self.pos)
+    /// Parse a media feature value: a length, a number, a ratio, a resolution or a keyword.
+    /// Ratios are converted to numbers and resolutions to dots per px.
+    fn parse_media_value(&mut self) -> ParseResult<Value> {
+        match self.next_char() {
+            Some('0'..='9' | '.' | '-' | '+') => {}
+            _ => {
+                let keyword = self.parse_identifier().to_ascii_lowercase();
+                if keyword.is_empty() {
+                    return Err(format!("Expected a media feature value, found {:?}", self.next_char()));
+                }
+                return Ok(Value::Keyword(keyword));
+            }
+        }
+        let number = self.parse_float()?;
+        let unit = self.parse_identifier().to_ascii_lowercase();
+        Ok(match &*unit {
+            "" => {
+                self.consume_whitespace();
+                if self.next_char() != Some('/') {
+                    return Ok(Value::Number(number));
+                }
+                self.consume_char();
+                self.consume_whitespace();
+                Value::Number(number / self.parse_float()?)
+            }
+            "dppx" | "x" => Value::Number(number),
+            "dpi" => Value::Number(number / 96.0),
+            "dpcm" => Value::Number(number * 2.54 / 96.0),
+            _ => {
+                self.pos -= unit.chars().count();
+                Value::Length(number, self.parse_unit()?)
+            }
+        })
     }
 
-    /// Return true if all input is consumed.
-    fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+    /// Does the remaining input start with the given keyword, in any case, as a whole word?
+    fn starts_with_keyword(&self, keyword: &str) -> bool {
+        let mut chars = self.rest().iter().copied();
+        let word: String = chars.by_ref().take(keyword.len()).collect();
+        word.eq_ignore_ascii_case(keyword) && !chars.next().is_some_and(valid_identifier_char)
     }
-}
 
-fn valid_identifier_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true, // TODO: Include U+00A0 and higher.
-        _ => false,
+    /// Consume a keyword and the whitespace after it, if the input starts with it.
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if !self.starts_with_keyword(keyword) {
+            return false;
+        }
+        self.pos += keyword.len();
+        self.consume_whitespace();
+        true
+    }
+
+    /// Is the keyword at the current position followed by the given character, after whitespace?
+    fn keyword_followed_by(&self, c: char) -> bool {
+        let mut rest = self.rest().iter().copied().skip_while(|&c| valid_identifier_char(c));
+        rest.find(|c| !c.is_whitespace()) == Some(c)
+    }
+
+    /// Parse a rule set: `<selectors> { <declarations> }`. If any of the selectors is invalid or
+    /// unsupported, the whole rule is skipped with a warning.
+    fn parse_rule(&mut self) -> Option<Rule> {
+        let start = self.pos;
+        let selectors = match self.parse_selectors() {
+            Ok(selectors) => selectors,
+            Err(error) => {
+                self.pos = start;
+                self.skip_rule(false);
+                let source: String = self.input[start..self.pos].iter().collect();
+                let selectors = source.split('{').next().unwrap_or_default().trim();
+                self.warnings.push(format!("Skipped rule with selector {}: {}", selectors, error));
+                return None;
+            }
+        };
+        Some(Rule { selectors, declarations: self.parse_declarations() })
     }
-}
\ No newline at end of file
+
+    /// Parse the selector list of a rule set, up to the opening `{`.
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
+        let mut selectors = self.parse_selector_list()?;
+        match self.next_char() {
+            Some('{') => {}
+            c => return Err(format!("Unexpected character {:?} in selector list", c)),
+        }
+        // Return selectors with highest specificity first, for use in matching.
+        selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+        Ok(selectors)
+    }
+
+    /// Parse a comma-separated list of selectors, stopping at the first character that can't
+    /// continue the list (`{` for a rule set, `)` for a pseudo-class argument).
+    fn parse_selector_list(&mut self) -> ParseResult<Vec<Selector>> {
+        let mut selectors = Vec::new();
+        loop {
+            selectors.push(self.parse_selector()?);
+            self.consume_whitespace();
+            if self.next_char() != Some(',') {
+                break;
+            }
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        Ok(selectors)
+    }
+
+    /// Parse simple selectors joined by combinators, e.g.: `ul > li.item a`
+    fn parse_selector(&mut self) -> ParseResult<Selector> {
+        let mut selector = Selector::Simple(self.parse_simple_selector()?);
+        loop {
+            let start = self.pos;
+            self.consume_whitespace();
+            let combinator = match self.next_char() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                Some(',') | Some('{') | Some(')') | None => break,
+                Some('[') => return Err("Attribute selectors aren't supported".to_string()),
+                _ if self.pos > start => Combinator::Descendant,
+                c => return Err(format!("Unexpected character {:?} in selector", c)),
+            };
+            if selector.pseudo_element().is_some() {
+                return Err("Selectors can't continue after a pseudo-element".to_string());
+            }
+            if combinator != Combinator::Descendant {
+                self.consume_char();
+                self.consume_whitespace();
+            }
+            selector = Selector::Complex(Box::new(selector), combinator, self.parse_simple_selector()?);
+        }
+        // Leave trailing whitespace for the caller to skip.
+        Ok(selector)
+    }
+
+    /// Parse one simple selector, e.g.: `type#id.class1.class2:first-child`. A pseudo-element
+    /// ends the simple selector.
+    fn parse_simple_selector(&mut self) -> ParseResult<SimpleSelector> {
+        let start = self.pos;
+        let mut selector = SimpleSelector {
+            tag_name: None,
+            id: None,
+            class: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
+        };
+        while let Some(c) = self.next_char() {
+            match c {
+                '#' => {
+                    self.consume_char();
+                    selector.id = Some(self.parse_selector_name("#")?);
+                }
+                '.' => {
+                    self.consume_char();
+                    selector.class.push(self.parse_selector_name(".")?);
+                }
+                ':' if self.starts_with("::") => {
+                    self.consume_char();
+                    self.consume_char();
+                    selector.pseudo_element = Some(self.parse_pseudo_element()?);
+                    break;
+                }
+                ':' => {
+                    self.consume_char();
+                    // CSS 2 allowed a single colon for the original pseudo-elements.
+                    match self.parse_legacy_pseudo_element() {
+                        Some(pseudo_element) => {
+                            selector.pseudo_element = Some(pseudo_element);
+                            break;
+                        }
+                        None => selector.pseudo_classes.push(self.parse_pseudo_class()?),
+                    }
+                }
+                '*' => {
+                    // universal selector
+                    self.consume_char();
+                }
+                c if valid_identifier_char(c) => {
+                    selector.tag_name = Some(self.parse_identifier());
+                }
+                _ => break
+            }
+        }
+        if self.pos == start {
+            return Err(format!("Expected a selector, found {:?}", self.next_char()));
+        }
+        Ok(selector)
+    }
+
+    /// Parse the name of an id or class selector following its `#` or `.`.
+    fn parse_selector_name(&mut self, prefix: &str) -> ParseResult<String> {
+        let name = self.parse_identifier();
+        if name.is_empty() {
+            return Err(format!("Expected a name after {}, found {:?}", prefix, self.next_char()));
+        }
+        Ok(name)
+    }
+
+    /// Parse a pseudo-element after its `::`, e.g. `before`.
+    fn parse_pseudo_element(&mut self) -> ParseResult<PseudoElement> {
+        match &*self.parse_identifier().to_ascii_lowercase() {
+            "before" => Ok(PseudoElement::Before),
+            "after" => Ok(PseudoElement::After),
+            "marker" => Ok(PseudoElement::Marker),
+            name => Err(format!("Unsupported pseudo-element ::{}", name)),
+        }
+    }
+
+    /// Parse `before` or `after` following a single colon, leaving any other pseudo-class alone.
+    fn parse_legacy_pseudo_element(&mut self) -> Option<PseudoElement> {
+        let start = self.pos;
+        let pseudo_element = match &*self.parse_identifier().to_ascii_lowercase() {
+            "before" => Some(PseudoElement::Before),
+            "after" => Some(PseudoElement::After),
+            _ => None,
+        };
+        if pseudo_element.is_none() {
+            self.pos = start;
+        }
+        pseudo_element
+    }
+
+    /// Parse a pseudo-class after its `:`, e.g. `first-child` or `nth-child(2n+1)`.
+    fn parse_pseudo_class(&mut self) -> ParseResult<PseudoClass> {
+        let name = self.parse_identifier().to_ascii_lowercase();
+        if self.next_char() != Some('(') {
+            return Ok(match &*name {
+                "root" => PseudoClass::Root,
+                "empty" => PseudoClass::Empty,
+                "first-child" => PseudoClass::FirstChild,
+                "last-child" => PseudoClass::LastChild,
+                "only-child" => PseudoClass::OnlyChild,
+                "first-of-type" => PseudoClass::FirstOfType,
+                "last-of-type" => PseudoClass::LastOfType,
+                "only-of-type" => PseudoClass::OnlyOfType,
+                _ => return Err(format!("Unsupported pseudo-class :{}", name)),
+            });
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let pseudo_class = match &*name {
+            "nth-child" => {
+                let nth = self.parse_nth()?;
+                PseudoClass::NthChild(nth, self.parse_nth_of()?)
+            }
+            "nth-last-child" => {
+                let nth = self.parse_nth()?;
+                PseudoClass::NthLastChild(nth, self.parse_nth_of()?)
+            }
+            "nth-of-type" => PseudoClass::NthOfType(self.parse_nth()?),
+            "nth-last-of-type" => PseudoClass::NthLastOfType(self.parse_nth()?),
+            "not" => PseudoClass::Not(self.parse_selector_list()?),
+            "is" | "matches" => PseudoClass::Is(self.parse_selector_list()?),
+            "where" => PseudoClass::Where(self.parse_selector_list()?),
+            "has" => PseudoClass::Has(self.parse_relative_selector_list()?),
+            _ => return Err(format!("Unsupported pseudo-class :{}()", name)),
+        };
+        self.consume_whitespace();
+        self.expect_char(')', &format!(":{}()", name))?;
+        Ok(pseudo_class)
+    }
+
+    /// Parse an `An+B` expression, or one of the keywords `odd` and `even`.
+    fn parse_nth(&mut self) -> ParseResult<Nth> {
+        let nth = match self.next_char() {
+            Some(c) if c.is_ascii_alphabetic() && c != 'n' && c != 'N' => {
+                match &*self.parse_identifier().to_ascii_lowercase() {
+                    "odd" => Nth { a: 2, b: 1 },
+                    "even" => Nth { a: 2, b: 0 },
+                    keyword => return Err(format!("Invalid An+B keyword {}", keyword)),
+                }
+            }
+            _ => {
+                let text = self.consume_while(|c| matches!(c, '0'..='9' | '+' | '-' | 'n' | 'N' | ' '));
+                let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+                parse_an_plus_b(&text.to_ascii_lowercase())?
+            }
+        };
+        self.consume_whitespace();
+        Ok(nth)
+    }
+
+    /// Parse the optional `of <selector list>` tail of `:nth-child()` and `:nth-last-child()`.
+    fn parse_nth_of(&mut self) -> ParseResult<Vec<Selector>> {
+        if self.next_char() == Some(')') {
+            return Ok(Vec::new());
+        }
+        let keyword = self.parse_identifier();
+        if !keyword.eq_ignore_ascii_case("of") {
+            return Err(format!("Expected of in :nth-child(), found {:?}", keyword));
+        }
+        self.consume_whitespace();
+        self.parse_selector_list()
+    }
+
+    /// Parse the argument of `:has()`, e.g. `> img, + p`.
+    fn parse_relative_selector_list(&mut self) -> ParseResult<Vec<RelativeSelector>> {
+        let mut selectors = Vec::new();
+        loop {
+            let combinator = match self.next_char() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                _ => Combinator::Descendant,
+            };
+            if combinator != Combinator::Descendant {
+                self.consume_char();
+                self.consume_whitespace();
+            }
+            selectors.push(RelativeSelector { combinator, selector: self.parse_selector()? });
+            self.consume_whitespace();
+            if self.next_char() != Some(',') {
+                break;
+            }
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        Ok(selectors)
+    }
+
+    /// Parse a list of declarations enclosed in `{ ... }`. A block that isn't closed ends at the
+    /// end of the input.
+    fn parse_declarations(&mut self) -> Vec<Declaration> {
+        assert!(self.consume_char() == Option::from('{'));
+        let mut declarations = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                Some('}') => { self.consume_char(); break }
+                None => break,
+                Some(_) => declarations.extend(self.parse_declaration()),
+            }
+        }
+        declarations
+    }
+
+    /// Parse one `<property>: <value>;` declaration. Shorthand properties are expanded into
+    /// the declarations of their longhands. An invalid declaration is skipped with a warning.
+    fn parse_declaration(&mut self) -> Vec<Declaration> {
+        let start = self.pos;
+        match self.try_parse_declaration() {
+            Ok(declarations) => declarations,
+            Err(error) => {
+                self.pos = start;
+                self.skip_declaration();
+                let source: String = self.input[start..self.pos].iter().collect();
+                self.warnings.push(format!("Skipped declaration {}: {}", source.trim_end_matches(';').trim(), error));
+                Vec::new()
+            }
+        }
+    }
+
+    fn try_parse_declaration(&mut self) -> ParseResult<Vec<Declaration>> {
+        // Custom property names are case-sensitive.
+        let mut property_name = self.parse_identifier();
+        if !is_custom_property(&property_name) {
+            property_name.make_ascii_lowercase();
+        }
+        self.consume_whitespace();
+        self.expect_char(':', &format!("declaration of {:?}", property_name))?;
+        self.consume_whitespace();
+
+        // Values that use var() are kept as source text until the variables are known.
+        let start = self.pos;
+        let source = self.consume_unparsed_value();
+        let uses_var = source.to_ascii_lowercase().contains("var(");
+        let value = match &*property_name {
+            _ if is_custom_property(&property_name) => match css_wide_keyword(&Value::Keyword(source.clone())) {
+                Some(keyword) => Value::Keyword(keyword.to_string()),
+                None => Value::Unparsed(source),
+            },
+            _ if uses_var => Value::Unparsed(source),
+            "content" => { self.pos = start; self.parse_content()? }
+            "counter-reset" | "counter-set" => { self.pos = start; self.parse_counters(0)? }
+            "counter-increment" => { self.pos = start; self.parse_counters(1)? }
+            _ => { self.pos = start; self.parse_value_list()? }
+        };
+        self.consume_whitespace();
+        let important = self.parse_important()?;
+        // The semicolon is optional after the last declaration in a block.
+        match self.next_char() {
+            Some(';') => { self.consume_char(); }
+            Some('}') | None => {}
+            c => return Err(format!("Unexpected character {:?} after value of {}", c, property_name)),
+        }
+
+        let mut declarations = match (value, longhands(&property_name)) {
+            (value, _) if is_custom_property(&property_name) => vec![Declaration::new(property_name, value)],
+            _ if properties::lookup(&property_name).is_none() && longhands(&property_name).is_none() => {
+                self.warnings.push(format!("Unknown property {}", property_name));
+                return Ok(Vec::new());
+            }
+            // A shorthand can't be expanded until its variables are substituted.
+            (Value::Unparsed(source), Some(longhands)) => longhands.into_iter()
+                .map(|longhand| Declaration::new(longhand, Value::PendingSubstitution(property_name.clone(), source.clone())))
+                .collect(),
+            (value, _) => expand_shorthand(&property_name, value)?,
+        };
+        let invalid = declarations.iter()
+            .find(|declaration| properties::lookup(&declaration.name).is_some_and(|property| !property.accepts(&declaration.value)));
+        if let Some(declaration) = invalid {
+            return Err(format!("Invalid value for {}", declaration.name));
+        }
+        for declaration in &mut declarations {
+            declaration.important = important;
+        }
+        Ok(declarations)
+    }
+
+    /// Skip to the end of the current declaration, past its `;` but not a `}` that closes the
+    /// block.
+    fn skip_declaration(&mut self) {
+        loop {
+            self.consume_unparsed_value();
+            match self.next_char() {
+                Some('!') => { self.consume_char(); }
+                Some(';') => { self.consume_char(); break }
+                _ => break,
+            }
+        }
+    }
+
+    /// Consume a declaration value as source text, up to the `;`, `}` or `!important` that ends
+    /// it. Brackets and strings are skipped over as a whole, and comments are dropped.
+    fn consume_unparsed_value(&mut self) -> String {
+        let mut source = String::new();
+        let mut closing = Vec::new();
+        loop {
+            if self.starts_with("/*") {
+                self.consume_whitespace();
+                source.push(' ');
+                continue;
+            }
+            match self.next_char() {
+                None => break,
+                Some(';') | Some('}') | Some('!') if closing.is_empty() => break,
+                Some(quote @ ('"' | '\'')) => {
+                    source.push(quote);
+                    self.consume_char();
+                    loop {
+                        match self.consume_char() {
+                            Some('\\') => { source.push('\\'); source.extend(self.consume_char()); }
+                            Some(c) if c == quote => { source.push(c); break }
+                            Some(c) => source.push(c),
+                            None => break,
+                        }
+                    }
+                }
+                Some(c) => {
+                    match c {
+                        '(' => closing.push(')'),
+                        '[' => closing.push(']'),
+                        '{' => closing.push('}'),
+                        c if closing.last() == Some(&c) => { closing.pop(); }
+                        _ => {}
+                    }
+                    source.push(c);
+                    self.consume_char();
+                }
+            }
+        }
+        source.trim().to_string()
+    }
+
+    /// Parse an optional `!important` annotation at the end of a declaration value.
+    fn parse_important(&mut self) -> ParseResult<bool> {
+        if self.next_char() != Some('!') {
+            return Ok(false);
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let keyword = self.parse_identifier();
+        if !keyword.eq_ignore_ascii_case("important") {
+            return Err(format!("Expected !important, found !{}", keyword));
+        }
+        self.consume_whitespace();
+        Ok(true)
+    }
+
+    // Methods for parsing values:
+
+    /// Parse a whole declaration value: components separated by whitespace, and optionally
+    /// grouped by commas.
+    fn parse_value_list(&mut self) -> ParseResult<Value> {
+        let mut groups = vec![Vec::new()];
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                Some(';') | Some('}') | Some('!') | None => break,
+                Some(',') => {
+                    self.consume_char();
+                    groups.push(Vec::new());
+                }
+                Some('/') => {
+                    // Separates e.g. font-size from line-height in the `font` shorthand.
+                    self.consume_char();
+                    groups.last_mut().unwrap().push(Value::Keyword("/".to_string()));
+                }
+                Some('"') | Some('\'') => {
+                    let string = self.parse_string();
+                    groups.last_mut().unwrap().push(Value::String(string));
+                }
+                Some(_) => {
+                    let value = self.parse_value()?;
+                    groups.last_mut().unwrap().push(value);
+                }
+            }
+        }
+        let mut groups: Vec<Value> = groups.into_iter().map(list).collect();
+        Ok(if groups.len() == 1 { groups.pop().unwrap() } else { Value::CommaList(groups) })
+    }
+
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        match self.next_char() {
+            Some('0'..='9' | '.') => self.parse_length(),
+            Some('-' | '+') if self.starts_with_number() => self.parse_length(),
+            Some('#') => self.parse_color(),
+            _ => {
+                let name = self.parse_identifier();
+                if self.next_char() == Some('(') {
+                    return self.parse_function(&name);
+                }
+                Ok(match named_color(&name) {
+                    _ if name.is_empty() => return Err(format!("Unexpected character {:?} in value", self.next_char())),
+                    Some(color) => Value::ColorValue(color),
+                    None if name.eq_ignore_ascii_case("currentcolor") => Value::Keyword("currentcolor".to_string()),
+                    None => Value::Keyword(name),
+                })
+            }
+        }
+    }
+
+    /// Parse the value of the `content` property: `normal`, `none`, or a list of strings,
+    /// `attr()`, `counter()`, `counters()` and quotes.
+    fn parse_content(&mut self) -> ParseResult<Value> {
+        let mut items = Vec::new();
+        loop {
+            match self.next_char() {
+                Some('"') | Some('\'') => items.push(ContentItem::String(self.parse_string())),
+                Some(c) if valid_identifier_char(c) => {
+                    let name = self.parse_identifier().to_ascii_lowercase();
+                    if self.next_char() != Some('(') {
+                        match &*name {
+                            "normal" | "none" if items.is_empty() => return Ok(Value::Keyword(name)),
+                            _ if items.is_empty() && CSS_WIDE_KEYWORDS.contains(&&*name) => return Ok(Value::Keyword(name)),
+                            "open-quote" => items.push(ContentItem::OpenQuote),
+                            "close-quote" => items.push(ContentItem::CloseQuote),
+                            "no-open-quote" => items.push(ContentItem::NoOpenQuote),
+                            "no-close-quote" => items.push(ContentItem::NoCloseQuote),
+                            _ => return Err(format!("Unexpected keyword {} in content", name)),
+                        }
+                        self.consume_whitespace();
+                        continue;
+                    }
+                    if !matches!(&*name, "attr" | "counter" | "counters") {
+                        return Err(format!("Unsupported function {}() in content", name));
+                    }
+                    self.consume_char();
+                    let args = self.parse_function_arguments(&name)?;
+                    let arg = |i: usize, default: &str| args.get(i).cloned().unwrap_or_else(|| default.to_string());
+                    items.push(match &*name {
+                        "attr" => ContentItem::Attr(arg(0, "")),
+                        "counter" => ContentItem::Counter(arg(0, ""), arg(1, "decimal")),
+                        _ => ContentItem::Counters(arg(0, ""), arg(1, ""), arg(2, "decimal")),
+                    });
+                }
+                _ => break
+            }
+            self.consume_whitespace();
+        }
+        Ok(Value::Content(items))
+    }
+
+    /// Parse the comma-separated identifiers and strings of a function call, after its `(`.
+    fn parse_function_arguments(&mut self, name: &str) -> ParseResult<Vec<String>> {
+        let mut args = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                Some(')') => { self.consume_char(); break; }
+                Some(',') => { self.consume_char(); }
+                Some('"') | Some('\'') => args.push(self.parse_string()),
+                Some(c) if valid_identifier_char(c) => args.push(self.parse_identifier()),
+                None => return Err(format!("Unterminated {}()", name)),
+                Some(c) => return Err(format!("Unexpected character {:?} in {}()", c, name)),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Parse `none` or a list of counter names, each optionally followed by an integer.
+    fn parse_counters(&mut self, default: i32) -> ParseResult<Value> {
+        let mut counters = Vec::new();
+        while self.next_char().is_some_and(valid_identifier_char) {
+            let name = self.parse_identifier();
+            let keyword = name.to_ascii_lowercase();
+            if counters.is_empty() && (keyword == "none" || CSS_WIDE_KEYWORDS.contains(&&*keyword)) {
+                return Ok(Value::Keyword(keyword));
+            }
+            self.consume_whitespace();
+            let value = match self.next_char() {
+                Some('0'..='9') | Some('-') | Some('+') => self.parse_integer()?,
+                _ => default,
+            };
+            counters.push((name, value));
+            self.consume_whitespace();
+        }
+        Ok(Value::Counters(counters))
+    }
+
+    fn parse_integer(&mut self) -> ParseResult<i32> {
+        let sign = match self.next_char() {
+            Some('-') => { self.consume_char(); -1 }
+            Some('+') => { self.consume_char(); 1 }
+            _ => 1
+        };
+        let digits = self.consume_while(|c| c.is_ascii_digit());
+        digits.parse::<i32>().map(|n| sign * n).map_err(|_| format!("Invalid integer {:?}", digits))
+    }
+
+    /// Parse a quoted string, handling backslash escapes.
+    fn parse_string(&mut self) -> String {
+        let quote = self.consume_char();
+        let mut result = String::new();
+        loop {
+            match self.consume_char() {
+                c if c == quote => break,
+                Some('\\') => {
+                    let hex = self.consume_while(|c| c.is_ascii_hexdigit());
+                    if hex.is_empty() {
+                        result.extend(self.consume_char());
+                    } else {
+                        // A hex escape may be terminated by a single space.
+                        if self.next_char() == Some(' ') {
+                            self.consume_char();
+                        }
+                        let code = u32::from_str_radix(&hex, 16).unwrap_or(0xFFFD);
+                        result.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                }
+                Some(c) => result.push(c),
+                // CSS closes a string that's still open at the end of the input.
+                None => break,
+            }
+        }
+        result
+    }
+
+    /// Parse a number followed by an optional unit, e.g. `12px`, `50%` or `1.5`.
+    fn parse_length(&mut self) -> ParseResult<Value> {
+        let number = self.parse_float()?;
+        Ok(match self.next_char() {
+            Some('%') => {
+                self.consume_char();
+                Value::Length(number, Unit::Percent)
+            }
+            Some(c) if c.is_ascii_alphabetic() => Value::Length(number, self.parse_unit()?),
+            _ => Value::Number(number)
+        })
+    }
+
+    fn parse_float(&mut self) -> ParseResult<f32> {
+        let mut s = String::new();
+        if let Some(sign @ ('-' | '+')) = self.next_char() {
+            self.consume_char();
+            s.push(sign);
+        }
+        s.push_str(&self.consume_while(|c| matches!(c, '0'..='9' | '.')));
+        f32::from_str(&s).map_err(|_| format!("Invalid number {:?}", s))
+    }
+
+    fn parse_unit(&mut self) -> ParseResult<Unit> {
+        Ok(match &*self.parse_identifier().to_ascii_lowercase() {
+            "px" => Unit::Px,
+            "cm" => Unit::Cm,
+            "mm" => Unit::Mm,
+            "q" => Unit::Q,
+            "in" => Unit::In,
+            "pt" => Unit::Pt,
+            "pc" => Unit::Pc,
+            "em" => Unit::Em,
+            "rem" => Unit::Rem,
+            "ex" => Unit::Ex,
+            "ch" => Unit::Ch,
+            "vw" => Unit::Vw,
+            "vh" => Unit::Vh,
+            "vmin" => Unit::Vmin,
+            "vmax" => Unit::Vmax,
+            unit => return Err(format!("Unknown unit {}", unit)),
+        })
+    }
+
+    /// Is the next character a sign that starts a number, as in `-12px` or `+.5`?
+    fn starts_with_number(&self) -> bool {
+        let mut chars = self.input.iter().copied().skip(self.pos + 1);
+        matches!(chars.next(), Some('0'..='9' | '.'))
+    }
+
+    /// Parse a hex color: `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`.
+    fn parse_color(&mut self) -> ParseResult<Value> {
+        assert!(self.consume_char() == Option::from('#'));
+        let hex = self.consume_while(|c| c.is_ascii_hexdigit());
+        if self.next_char().is_some_and(valid_identifier_char) {
+            return Err(format!("Invalid hex color #{}{}", hex, self.parse_identifier()));
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Value::ColorValue(match hex.len() {
+            3 | 4 => Color {
+                r: digit(0) * 17,
+                g: digit(1) * 17,
+                b: digit(2) * 17,
+                a: if hex.len() == 4 { digit(3) * 17 } else { 255 },
+            },
+            6 | 8 => Color {
+                r: pair(0),
+                g: pair(2),
+                b: pair(4),
+                a: if hex.len() == 8 { pair(6) } else { 255 },
+            },
+            _ => return Err(format!("Invalid hex color #{}", hex)),
+        }))
+    }
+
+    /// Parse a functional value after its name, e.g. `rgb(255 0 0 / 50%)`.
+    fn parse_function(&mut self, name: &str) -> ParseResult<Value> {
+        match &*name.to_ascii_lowercase() {
+            "rgb" | "rgba" | "hsl" | "hsla" | "hwb" => self.parse_color_function(name),
+            "calc" | "min" | "max" | "clamp" => Ok(Value::Math(self.parse_math_function(name)?)),
+            _ => Err(format!("Unsupported function {}()", name)),
+        }
+    }
+
+    /// Parse a `calc()`, `min()`, `max()` or `clamp()` function, starting at its `(`.
+    fn parse_math_function(&mut self, name: &str) -> ParseResult<MathExpr> {
+        assert_eq!(self.consume_char(), Some('('));
+        let mut arguments = Vec::new();
+        loop {
+            self.consume_whitespace();
+            arguments.push(self.parse_math_sum()?);
+            self.consume_whitespace();
+            match self.consume_char() {
+                Some(',') => {}
+                Some(')') => break,
+                c => return Err(format!("Unexpected character {:?} in {}()", c, name)),
+            }
+        }
+        if arguments.iter().any(|arg| arg.is_number() != arguments[0].is_number()) {
+            return Err(format!("Arguments of {}() mix numbers and lengths", name));
+        }
+        Ok(match (&*name.to_ascii_lowercase(), arguments.len()) {
+            ("calc", 1) => arguments.pop().unwrap(),
+            ("min", _) => MathExpr::Min(arguments),
+            ("max", _) => MathExpr::Max(arguments),
+            ("clamp", 3) => {
+                let max = arguments.pop().unwrap();
+                let value = arguments.pop().unwrap();
+                let min = arguments.pop().unwrap();
+                MathExpr::Clamp(Box::new(min), Box::new(value), Box::new(max))
+            }
+            _ => return Err(format!("Wrong number of arguments to {}()", name)),
+        })
+    }
+
+    /// Parse terms separated by `+` and `-`.
+    fn parse_math_sum(&mut self) -> ParseResult<MathExpr> {
+        let mut sum = self.parse_math_product()?;
+        loop {
+            self.consume_whitespace();
+            let op = match self.next_char() {
+                Some(op @ ('+' | '-')) => op,
+                _ => return Ok(sum)
+            };
+            self.consume_char();
+            self.consume_whitespace();
+            let term = self.parse_math_product()?;
+            if term.is_number() != sum.is_number() {
+                return Err("Can't add a number and a length in a math function".to_string());
+            }
+            sum = match op {
+                '+' => MathExpr::Add(Box::new(sum), Box::new(term)),
+                _ => MathExpr::Sub(Box::new(sum), Box::new(term)),
+            };
+        }
+    }
+
+    /// Parse factors separated by `*` and `/`.
+    fn parse_math_product(&mut self) -> ParseResult<MathExpr> {
+        let mut product = self.parse_math_value()?;
+        loop {
+            self.consume_whitespace();
+            let op = match self.next_char() {
+                Some(op @ ('*' | '/')) => op,
+                _ => return Ok(product)
+            };
+            self.consume_char();
+            self.consume_whitespace();
+            let factor = self.parse_math_value()?;
+            product = match op {
+                '*' if product.is_number() || factor.is_number() => MathExpr::Mul(Box::new(product), Box::new(factor)),
+                '/' if factor.is_number() => MathExpr::Div(Box::new(product), Box::new(factor)),
+                _ => return Err(format!("Can't {} two lengths in a math function",
+                                        if op == '*' { "multiply" } else { "divide" })),
+            };
+        }
+    }
+
+    /// Parse a number, a length, a parenthesized sum or a nested math function.
+    fn parse_math_value(&mut self) -> ParseResult<MathExpr> {
+        match self.next_char() {
+            Some('(') => {
+                self.consume_char();
+                self.consume_whitespace();
+                let sum = self.parse_math_sum()?;
+                self.consume_whitespace();
+                self.expect_char(')', "math function")?;
+                Ok(sum)
+            }
+            Some('0'..='9') | Some('.') => self.parse_math_number(),
+            Some('-') | Some('+') if self.starts_with_number() => self.parse_math_number(),
+            _ => {
+                let name = self.parse_identifier();
+                match &*name.to_ascii_lowercase() {
+                    "calc" | "min" | "max" | "clamp" if self.next_char() == Some('(') => self.parse_math_function(&name),
+                    _ => Err(format!("Unexpected {:?} in math function", name)),
+                }
+            }
+        }
+    }
+
+    fn parse_math_number(&mut self) -> ParseResult<MathExpr> {
+        Ok(match self.parse_length()? {
+            Value::Length(f, unit) => MathExpr::Length(f, unit),
+            Value::Number(n) => MathExpr::Number(n),
+            value => unreachable!("parse_length returned {:?}", value)
+        })
+    }
+
+    /// Parse the arguments of `rgb()`, `hsl()` or `hwb()`, in either the legacy comma-separated
+    /// syntax or the space-separated syntax with an optional `/ alpha`.
+    fn parse_color_function(&mut self, name: &str) -> ParseResult<Value> {
+        assert_eq!(self.consume_char(), Some('('));
+        let mut components = Vec::new();
+        let mut alpha = None;
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                Some(')') => { self.consume_char(); break; }
+                Some(',') => { self.consume_char(); }
+                Some('/') => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    alpha = Some(self.parse_color_component()?);
+                }
+                Some(_) => components.push(self.parse_color_component()?),
+                None => return Err(format!("Unterminated {}()", name)),
+            }
+        }
+        // The legacy syntax passes alpha as a fourth comma-separated argument.
+        if components.len() == 4 {
+            alpha = components.pop();
+        }
+        if components.len() != 3 {
+            return Err(format!("{}() takes three components", name));
+        }
+
+        // Percentages are 0-1 fractions from here on; numbers keep their own range.
+        let fraction = |(value, unit): &(f32, String), range: f32| match &**unit {
+            "%" => value / 100.0,
+            _ => value / range,
+        };
+        let hue = |(value, unit): &(f32, String)| match &**unit {
+            "rad" => value.to_degrees(),
+            "grad" => value * 0.9,
+            "turn" => value * 360.0,
+            _ => *value,
+        };
+        let (r, g, b) = match &*name.to_ascii_lowercase() {
+            "rgb" | "rgba" => (fraction(&components[0], 255.0), fraction(&components[1], 255.0),
+                               fraction(&components[2], 255.0)),
+            "hsl" | "hsla" => hsl_to_rgb(hue(&components[0]), fraction(&components[1], 100.0),
+                                         fraction(&components[2], 100.0)),
+            _ => hwb_to_rgb(hue(&components[0]), fraction(&components[1], 100.0),
+                            fraction(&components[2], 100.0)),
+        };
+        let alpha = alpha.map_or(1.0, |a| fraction(&a, 1.0));
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Ok(Value::ColorValue(Color { r: channel(r), g: channel(g), b: channel(b), a: channel(alpha) }))
+    }
+
+    /// Parse a number with an optional `%` or angle unit, or `none` (which means zero).
+    fn parse_color_component(&mut self) -> ParseResult<(f32, String)> {
+        if self.next_char().is_some_and(|c| c.is_ascii_alphabetic()) {
+            let keyword = self.parse_identifier();
+            if !keyword.eq_ignore_ascii_case("none") {
+                return Err(format!("Unexpected {} in color function", keyword));
+            }
+            return Ok((0.0, String::new()));
+        }
+        let number = self.parse_float()?;
+        let unit = match self.next_char() {
+            Some('%') => { self.consume_char(); "%".to_string() }
+            _ => self.parse_identifier().to_ascii_lowercase(),
+        };
+        Ok((number, unit))
+    }
+
+    /// Parse a property name or keyword.
+    fn parse_identifier(&mut self) -> String {
+        self.consume_while(valid_identifier_char)
+    }
+
+    /// Consume the given character, or fail if the input doesn't continue with it.
+    fn expect_char(&mut self, expected: char, context: &str) -> ParseResult<()> {
+        match self.consume_char() {
+            Some(c) if c == expected => Ok(()),
+            c => Err(format!("Expected {:?} in {}, found {:?}", expected, context, c)),
+        }
+    }
+
+    /// Does the remaining input start with the given string?
+    fn starts_with(&self, s: &str) -> bool {
+        self.rest().iter().copied().take(s.chars().count()).eq(s.chars())
+    }
+
+    /// Consume and discard zero or more whitespace characters and comments.
+    fn consume_whitespace(&mut self) {
+        loop {
+            self.consume_while(|c| c.is_whitespace());
+            if !self.starts_with("/*") {
+                break;
+            }
+            while !self.eof() && !self.starts_with("*/") {
+                self.consume_char();
+            }
+            self.consume_char();
+            self.consume_char();
+        }
+    }
+
+    /// Consume characters until `test` returns false.
+    fn consume_while<F: Fn(char) -> bool>(&mut self, test: F) -> String {
+        let mut result = String::new();
+        while !self.eof() && test(self.next_char().unwrap()) {
+            result.push(self.consume_char().unwrap());
+        }
+        result
+    }
+
+    /// Return the current character, and advance self.pos to the next character.
+    fn consume_char(&mut self) -> Option<char> {
+        let ch = self.input.get(self.pos).copied();
+        self.pos += 1;
+        ch
+    }
+
+    /// Read the current character without consuming it.
+    fn next_char(&self) -> Option<char> {
+        self.input.get(self.pos).copied()
+    }
+
+    /// The input that hasn't been consumed yet.
+    fn rest(&self) -> &[char] {
+        &self.input[self.pos.min(self.input.len())..]
+    }
+
+    /// Return true if all input is consumed.
+    fn eof(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+}
+
+/// The physical sides of a box, in the order the box-edge shorthands list them.
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+const BORDER_STYLES: [&str; 10] = ["none", "hidden", "dotted", "dashed", "solid", "double", "groove",
+    "ridge", "inset", "outset"];
+
+/// Expand a shorthand property into its longhands. Other properties are returned unchanged.
+/// Fails if the value isn't valid for the shorthand.
+fn expand_shorthand(name: &str, value: Value) -> ParseResult<Vec<Declaration>> {
+    // A CSS-wide keyword applies to every longhand of a shorthand.
+    if let Some(keyword) = css_wide_keyword(&value) {
+        let keyword = Value::Keyword(keyword.to_string());
+        return Ok(match longhands(name) {
+            Some(longhands) => longhands.into_iter()
+                .map(|longhand| Declaration::new(longhand, keyword.clone()))
+                .collect(),
+            None => vec![Declaration::new(name.to_string(), keyword)],
+        });
+    }
+    Ok(match name {
+        "margin" | "padding" => box_edges(value)?.into_iter().zip(SIDES)
+            .map(|(value, side)| Declaration::new(format!("{}-{}", name, side), value))
+            .collect(),
+        "border-width" | "border-style" | "border-color" => {
+            let kind = &name["border-".len()..];
+            box_edges(value)?.into_iter().zip(SIDES)
+                .map(|(value, side)| Declaration::new(format!("border-{}-{}", side, kind), value))
+                .collect()
+        }
+        "border" => {
+            let mut declarations = Vec::new();
+            for side in SIDES {
+                declarations.extend(expand_border(side, value.clone())?);
+            }
+            declarations
+        }
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            expand_border(&name["border-".len()..], value)?
+        }
+        "background" => expand_background(value)?,
+        "font" => expand_font(value)?,
+        "font-family" => vec![Declaration::new(name.to_string(), font_family_list(value)?)],
+        _ => vec![Declaration::new(name.to_string(), value)],
+    })
+}
+
+/// The keywords every property accepts, which refer to another value in the cascade instead of
+/// being a value themselves.
+pub const CSS_WIDE_KEYWORDS: [&str; 5] = ["inherit", "initial", "unset", "revert", "revert-layer"];
+
+/// The CSS-wide keyword a value consists of, if any. Keywords are case-insensitive.
+pub fn css_wide_keyword(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Keyword(keyword) => CSS_WIDE_KEYWORDS.iter().copied()
+            .find(|wide| keyword.eq_ignore_ascii_case(wide)),
+        _ => None
+    }
+}
+
+/// Whether a property name is a custom property, like `--brand-color`.
+pub fn is_custom_property(name: &str) -> bool {
+    name.starts_with("--")
+}
+
+/// The longhands a shorthand property sets, or `None` if it isn't a shorthand.
+pub fn longhands(name: &str) -> Option<Vec<String>> {
+    let border_side = |side: &str| ["width", "style", "color"].map(|kind| format!("border-{}-{}", side, kind));
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    Some(match name {
+        "margin" | "padding" => SIDES.iter().map(|side| format!("{}-{}", name, side)).collect(),
+        "border-width" | "border-style" | "border-color" => {
+            let kind = &name["border-".len()..];
+            SIDES.iter().map(|side| format!("border-{}-{}", side, kind)).collect()
+        }
+        "border" => SIDES.iter().flat_map(|side| border_side(side)).collect(),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            border_side(&name["border-".len()..]).to_vec()
+        }
+        "background" => names(&["background-color", "background-image", "background-repeat",
+            "background-attachment", "background-position"]),
+        "font" => names(&["font-style", "font-variant", "font-weight", "font-stretch", "font-size",
+            "line-height", "font-family"]),
+        _ => return None
+    })
+}
+
+/// A space-separated list of components, or the component itself if there's only one.
+fn list(mut components: Vec<Value>) -> Value {
+    if components.len() == 1 { components.pop().unwrap() } else { Value::List(components) }
+}
+
+/// The components of a space-separated value.
+fn components(value: Value) -> Vec<Value> {
+    match value {
+        Value::List(values) => values,
+        value => vec![value],
+    }
+}
+
+/// Apply the 1-4 value rule of the box-edge shorthands, returning top, right, bottom and left.
+fn box_edges(value: Value) -> ParseResult<[Value; 4]> {
+    Ok(match &*components(value) {
+        [all] => [all.clone(), all.clone(), all.clone(), all.clone()],
+        [vertical, horizontal] => [vertical.clone(), horizontal.clone(), vertical.clone(), horizontal.clone()],
+        [top, horizontal, bottom] => [top.clone(), horizontal.clone(), bottom.clone(), horizontal.clone()],
+        [top, right, bottom, left] => [top.clone(), right.clone(), bottom.clone(), left.clone()],
+        values => return Err(format!("Box edge shorthands take 1 to 4 values, not {}", values.len())),
+    })
+}
+
+/// Expand `border` or `border-<side>`: a width, style and color in any order, each at most once.
+fn expand_border(side: &str, value: Value) -> ParseResult<Vec<Declaration>> {
+    let (mut width, mut style, mut color) = (None, None, None);
+    for component in components(value) {
+        let longhand = match component {
+            Value::Length(..) | Value::Number(_) | Value::Math(_) => &mut width,
+            Value::Keyword(ref k) if matches!(&**k, "thin" | "medium" | "thick") => &mut width,
+            Value::Keyword(ref k) if BORDER_STYLES.contains(&&**k) => &mut style,
+            Value::ColorValue(_) => &mut color,
+            Value::Keyword(ref k) if k == "currentcolor" => &mut color,
+            _ => return Err(format!("Unexpected {:?} in border", component)),
+        };
+        if longhand.replace(component).is_some() {
+            return Err("A border can only have one width, style and color".to_string());
+        }
+    }
+    let keyword = |k: &str| Value::Keyword(k.to_string());
+    Ok(vec![
+        Declaration::new(format!("border-{}-width", side), width.unwrap_or_else(|| keyword("medium"))),
+        Declaration::new(format!("border-{}-style", side), style.unwrap_or_else(|| keyword("none"))),
+        Declaration::new(format!("border-{}-color", side), color.unwrap_or_else(|| keyword("currentcolor"))),
+    ])
+}
+
+/// Expand `background` into its longhands. Only a single layer is supported.
+fn expand_background(value: Value) -> ParseResult<Vec<Declaration>> {
+    let keyword = |k: &str| Value::Keyword(k.to_string());
+    let mut color = keyword("transparent");
+    let mut image = keyword("none");
+    let mut repeat = keyword("repeat");
+    let mut attachment = keyword("scroll");
+    let mut position = Vec::new();
+    for component in components(value) {
+        match component {
+            Value::ColorValue(_) => color = component,
+            Value::Keyword(ref k) => match &**k {
+                "none" => image = component,
+                "repeat" | "repeat-x" | "repeat-y" | "no-repeat" | "space" | "round" => repeat = component,
+                "scroll" | "fixed" | "local" => attachment = component,
+                "left" | "right" | "top" | "bottom" | "center" => position.push(component),
+                "currentcolor" => color = component,
+                _ => return Err(format!("Unsupported background component {}", k)),
+            },
+            Value::Length(..) | Value::Number(_) => position.push(component),
+            _ => return Err(format!("Unsupported background component {:?}", component)),
+        }
+    }
+    let position = match position.len() {
+        0 => Value::List(vec![Value::Length(0.0, Unit::Percent), Value::Length(0.0, Unit::Percent)]),
+        1 => position.pop().unwrap(),
+        _ => Value::List(position),
+    };
+    Ok(vec![
+        Declaration::new("background-color".to_string(), color),
+        Declaration::new("background-image".to_string(), image),
+        Declaration::new("background-repeat".to_string(), repeat),
+        Declaration::new("background-attachment".to_string(), attachment),
+        Declaration::new("background-position".to_string(), position),
+    ])
+}
+
+/// Expand `font`: optional style, variant, weight and stretch, then a size with an optional
+/// `/ line-height`, then the family list.
+fn expand_font(value: Value) -> ParseResult<Vec<Declaration>> {
+    let keyword = |k: &str| Value::Keyword(k.to_string());
+    let (mut style, mut variant, mut weight, mut stretch) =
+        (keyword("normal"), keyword("normal"), keyword("normal"), keyword("normal"));
+    let mut line_height = keyword("normal");
+
+    // The first comma-separated group holds everything up to the first family name.
+    let mut groups = match value {
+        Value::CommaList(groups) => groups,
+        value => vec![value],
+    };
+    let mut prefix = components(groups.remove(0)).into_iter().peekable();
+    let size = loop {
+        match prefix.next() {
+            Some(Value::Keyword(ref k)) if k == "normal" => {}
+            Some(Value::Keyword(ref k)) if matches!(&**k, "italic" | "oblique") => style = keyword(k),
+            Some(Value::Keyword(ref k)) if k == "small-caps" => variant = keyword(k),
+            Some(Value::Keyword(ref k)) if matches!(&**k, "bold" | "bolder" | "lighter") => weight = keyword(k),
+            Some(Value::Number(n)) => weight = Value::Number(n),
+            Some(Value::Keyword(ref k)) if k.ends_with("condensed") || k.ends_with("expanded") => stretch = keyword(k),
+            Some(size) => break size,
+            None => return Err("The font shorthand requires a font-size".to_string()),
+        }
+    };
+    if prefix.peek() == Some(&keyword("/")) {
+        prefix.next();
+        line_height = prefix.next().ok_or("Missing line-height after / in font")?;
+    }
+    if prefix.len() == 0 {
+        return Err("The font shorthand requires a font-family".to_string());
+    }
+    groups.insert(0, list(prefix.collect()));
+    Ok(vec![
+        Declaration::new("font-style".to_string(), style),
+        Declaration::new("font-variant".to_string(), variant),
+        Declaration::new("font-weight".to_string(), weight),
+        Declaration::new("font-stretch".to_string(), stretch),
+        Declaration::new("font-size".to_string(), size),
+        Declaration::new("line-height".to_string(), line_height),
+        Declaration::new("font-family".to_string(), font_family_list(Value::CommaList(groups))?),
+    ])
+}
+
+/// Normalize a font family list, joining unquoted multi-word names into strings.
+fn font_family_list(value: Value) -> ParseResult<Value> {
+    const GENERIC: [&str; 6] = ["serif", "sans-serif", "monospace", "cursive", "fantasy", "system-ui"];
+    let family = |value: Value| Ok(match value {
+        Value::Keyword(ref k) if GENERIC.contains(&&**k) => value,
+        Value::Keyword(name) | Value::String(name) => Value::String(name),
+        Value::List(words) => Value::String(words.iter().map(|word| match word {
+            Value::Keyword(w) => Ok(w.clone()),
+            _ => Err(format!("Invalid font family name {:?}", word)),
+        }).collect::<ParseResult<Vec<_>>>()?.join(" ")),
+        _ => return Err(format!("Invalid font family {:?}", value)),
+    });
+    let families = match value {
+        Value::CommaList(families) => families,
+        value => vec![value],
+    };
+    Ok(Value::CommaList(families.into_iter().map(family).collect::<ParseResult<_>>()?))
+}
+
+/// Parse an `An+B` expression with whitespace removed, e.g. `2n+1`, `-n+3`, `n` or `4`.
+fn parse_an_plus_b(text: &str) -> ParseResult<Nth> {
+    let parse_int = |s: &str| -> ParseResult<i32> {
+        s.trim_start_matches('+').parse().map_err(|_| format!("Invalid An+B expression {}", text))
+    };
+    match text.find('n') {
+        Some(i) => {
+            let a = match &text[..i] {
+                "" | "+" => 1,
+                "-" => -1,
+                s => parse_int(s)?,
+            };
+            let b = match &text[i + 1..] {
+                "" => 0,
+                s => parse_int(s)?,
+            };
+            Ok(Nth { a, b })
+        }
+        None => Ok(Nth { a: 0, b: parse_int(text)? }),
+    }
+}
+
+/// Parse one range of a `unicode-range` descriptor: `U+26`, `U+0-7F` or `U+4??`.
+fn parse_unicode_range(range: &str) -> ParseResult<(u32, u32)> {
+    let range = range.trim();
+    let hex = match range.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("u+") => &range[2..],
+        _ => return Err(format!("Invalid unicode-range {}", range)),
+    };
+    let parse = |hex: &str| u32::from_str_radix(hex, 16).map_err(|_| format!("Invalid unicode-range {}", range));
+    Ok(match hex.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None if hex.contains('?') => (parse(&hex.replace('?', "0"))?, parse(&hex.replace('?', "F"))?),
+        None => (parse(hex)?, parse(hex)?),
+    })
+}
+
+impl SupportsCondition {
+    /// Whether the engine supports what the condition tests.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            SupportsCondition::Declaration(name, value) => supports_declaration(name, value),
+            SupportsCondition::Selector(selectors) => {
+                Parser::new(&format!("{} {{", selectors)).parse_selectors().is_ok()
+            }
+            SupportsCondition::Not(condition) => !condition.is_supported(),
+            SupportsCondition::And(conditions) => conditions.iter().all(SupportsCondition::is_supported),
+            SupportsCondition::Or(conditions) => conditions.iter().any(SupportsCondition::is_supported),
+            SupportsCondition::Unknown(_) => false,
+        }
+    }
+}
+
+/// Whether a property is supported and the value is valid for it. The parser checks values
+/// against the property's type, and keywords against those the computed style understands, so
+/// neither `display: ruby` nor `width: red` is supported.
+fn supports_declaration(name: &str, value: &str) -> bool {
+    is_custom_property(name) || !parse_declaration(&name.to_ascii_lowercase(), value).is_empty()
+}
+
+fn valid_identifier_char(c: char) -> bool {
+    match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true, // TODO: Include U+00A0 and higher.
+        _ => false,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rule sets at the top level of a style sheet.
+    fn style_rules(stylesheet: &Stylesheet) -> Vec<&Rule> {
+        stylesheet.rules.iter().filter_map(|rule| match rule {
+            CssRule::Style(rule) => Some(rule),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn unsupported_selectors_skip_only_their_rule() {
+        let stylesheet = parse("a:hover { color: red } p::first-line { color: red } \
+            input[type=text] { color: red } div { color: blue } h1, li:focus { color: red } \
+            em { color: green }".to_string());
+        let rules = style_rules(&stylesheet);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].selectors[0].rightmost().tag_name.as_deref(), Some("div"));
+        assert_eq!(rules[1].selectors[0].rightmost().tag_name.as_deref(), Some("em"));
+        assert_eq!(stylesheet.warnings.len(), 4, "{:?}", stylesheet.warnings);
+        assert!(stylesheet.warnings[0].contains("a:hover"));
+
+        let stylesheet = parse("p, { color: red } :is() { color: red } :not() { color: red } \
+            :where() { color: red } :has() { color: red } . { color: red } # { color: red } \
+            a > { color: red } p::before span { color: red } p::before.a { color: red } \
+            li:nth-child(2n of) { color: red } em { color: green }".to_string());
+        let rules = style_rules(&stylesheet);
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].selectors[0].rightmost().tag_name.as_deref(), Some("em"));
+        assert_eq!(stylesheet.warnings.len(), 11, "{:?}", stylesheet.warnings);
+    }
+
+    #[test]
+    fn invalid_selector_skips_nested_blocks() {
+        let stylesheet = parse("p:nth-child(foo) { color: red } @media screen { b { color: red } }".to_string());
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert!(matches!(stylesheet.rules[0], CssRule::Media(_)));
+    }
+
+    #[test]
+    fn parse_selector_list_reports_errors() {
+        assert!(parse_selector_list("ul > li:nth-child(2n+1 of .a)").is_ok());
+        assert!(parse_selector_list("a:hover").is_err());
+        assert!(parse_selector_list("a[href]").is_err());
+        assert!(parse_selector_list("p )").is_err());
+        assert!(parse_selector_list("").is_err());
+        assert!(parse_selector_list("p,").is_err());
+    }
+
+    /// The declarations of the first rule set of a style sheet.
+    fn declarations(source: &str) -> Vec<Declaration> {
+        match parse(source.to_string()).rules.into_iter().next() {
+            Some(CssRule::Style(rule)) => rule.declarations,
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn invalid_content_functions_are_skipped() {
+        let stylesheet = parse("p::before { content: url(x.png); color: red; content: counter(list.item) }".to_string());
+        let kept = &style_rules(&stylesheet)[0].declarations;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].name, "color");
+        assert_eq!(stylesheet.warnings.len(), 2, "{:?}", stylesheet.warnings);
+    }
+
+    #[test]
+    fn unterminated_content_function_ends_the_block() {
+        let stylesheet = parse("p::before { color: red; content: attr(title".to_string());
+        assert_eq!(style_rules(&stylesheet)[0].declarations.len(), 1);
+        assert_eq!(stylesheet.warnings.len(), 1, "{:?}", stylesheet.warnings);
+        assert_eq!(declarations("p::before { content: attr(title) \"!\" }")[0].value,
+                   Value::Content(vec![ContentItem::Attr("title".to_string()), ContentItem::String("!".to_string())]));
+    }
+
+    #[test]
+    fn invalid_values_skip_only_their_declaration() {
+        let stylesheet = parse("p { background-image: url(a.png); transform: rotate(45deg); width: 1foo; \
+            color: #ggg; margin-top: calc(1px + 2); height: 2px !importnt; padding-left: 3px; color: red }".to_string());
+        let kept = &style_rules(&stylesheet)[0].declarations;
+        assert_eq!(kept.iter().map(|d| &*d.name).collect::<Vec<_>>(), ["padding-left", "color"]);
+        assert_eq!(stylesheet.warnings.len(), 6, "{:?}", stylesheet.warnings);
+        assert!(stylesheet.warnings.iter().any(|warning| warning.contains("Unknown unit foo")));
+        assert!(stylesheet.warnings.iter().any(|warning| warning.contains("Invalid hex color #ggg")));
+    }
+
+    #[test]
+    fn valid_values_still_parse() {
+        let kept = declarations("p { color: #0f08; width: calc(100% - 2em); background-color: rgb(255 0 0 / 50%) }");
+        assert_eq!(kept[0].value, Value::ColorValue(Color { r: 0, g: 255, b: 0, a: 136 }));
+        assert!(matches!(kept[1].value, Value::Math(MathExpr::Sub(..))));
+        assert_eq!(kept[2].value, Value::ColorValue(Color { r: 255, g: 0, b: 0, a: 128 }));
+    }
+
+    #[test]
+    fn invalid_shorthands_are_skipped() {
+        let stylesheet = parse("p { margin: 1px 2px 3px 4px 5px; background: url(a.png) red; background: red foo; \
+            border: 1px solid red blue; font: bold; padding: 1px 2px }".to_string());
+        let kept = &style_rules(&stylesheet)[0].declarations;
+        assert_eq!(kept.len(), 4);
+        assert!(kept.iter().all(|declaration| declaration.name.starts_with("padding-")));
+        assert_eq!(stylesheet.warnings.len(), 5, "{:?}", stylesheet.warnings);
+    }
+
+    #[test]
+    fn shorthands_expand_into_longhands() {
+        let kept = declarations("p { margin: 1px 2px; border-top: thin dashed; background: none blue; font: italic 12px/2 serif }");
+        let value = |name: &str| &kept.iter().find(|declaration| declaration.name == name).unwrap().value;
+        assert_eq!(value("margin-left"), &Value::Length(2.0, Unit::Px));
+        assert_eq!(value("border-top-style"), &Value::Keyword("dashed".to_string()));
+        assert_eq!(value("border-top-color"), &Value::Keyword("currentcolor".to_string()));
+        assert_eq!(value("background-image"), &Value::Keyword("none".to_string()));
+        assert_eq!(value("line-height"), &Value::Number(2.0));
+        assert_eq!(value("font-family"), &Value::CommaList(vec![Value::Keyword("serif".to_string())]));
+    }
+
+    #[test]
+    fn invalid_at_rules_are_skipped() {
+        let stylesheet = parse("@import foo; @layer a b { p { color: red } } @layer !; \
+            @supports color: red { p { color: red } } @media screen; @font-face; em { color: blue }".to_string());
+        assert!(matches!(stylesheet.rules[..], [CssRule::Style(_)]), "{:?}", stylesheet.rules);
+        assert_eq!(stylesheet.warnings.len(), 6, "{:?}", stylesheet.warnings);
+        assert!(stylesheet.warnings[0].starts_with("Skipped rule @import foo:"));
+    }
+
+    #[test]
+    fn invalid_media_queries_never_match() {
+        let not_all = MediaQuery { negated: true, media_type: Some("all".to_string()), condition: None };
+        let stylesheet = parse("@media screen and (min-width: 100px) junk, print, (width < ) { p { color: red } }".to_string());
+        let CssRule::Media(media_rule) = &stylesheet.rules[0] else { panic!("{:?}", stylesheet.rules) };
+        assert_eq!(media_rule.queries, [not_all.clone(), parse_media_queries("print")[0].clone(), not_all.clone()]);
+        assert_eq!(media_rule.rules.len(), 1);
+        assert_eq!(stylesheet.warnings.len(), 2, "{:?}", stylesheet.warnings);
+
+        assert_eq!(parse_media_queries("!, (5px)"), [not_all.clone(), not_all.clone()]);
+        assert_eq!(parse_media_queries("(min-width: 1px"), [not_all]);
+    }
+
+    #[test]
+    fn invalid_font_face_descriptors_are_skipped() {
+        let stylesheet = parse("@font-face { font-family: X; font-weight: heavy; \
+            src: url(a.woff) format(\"woff\"), foo; unicode-range: U+0-7F, Z; font-style: italic".to_string());
+        let [CssRule::FontFace(font_face)] = &stylesheet.rules[..] else { panic!("{:?}", stylesheet.rules) };
+        assert_eq!(font_face.family.as_deref(), Some("X"));
+        assert_eq!(font_face.weight, (400, 400));
+        assert!(font_face.sources.is_empty());
+        assert!(font_face.unicode_range.is_empty());
+        assert_eq!(font_face.style, "italic");
+        assert_eq!(stylesheet.warnings.len(), 3, "{:?}", stylesheet.warnings);
+    }
+
+    #[test]
+    fn supports_rejects_invalid_declarations() {
+        let stylesheet = parse("@supports (width: 1foo) or (color: rgb(1 2)) { p { color: red } } \
+            @supports (width: 1px) and (not (display: ruby)) { p { color: red } }".to_string());
+        let supported: Vec<bool> = stylesheet.rules.iter().map(|rule| match rule {
+            CssRule::Supports(supports) => supports.supported,
+            rule => panic!("Expected @supports, got {:?}", rule),
+        }).collect();
+        assert_eq!(supported, [false, true]);
+        assert!(stylesheet.warnings.is_empty(), "{:?}", stylesheet.warnings);
+
+        assert!(!supports_declaration("width", "red"));
+        assert!(!supports_declaration("color", "10px"));
+        assert!(!supports_declaration("letter-spacing", "10%"));
+        assert!(!supports_declaration("z-index", "1.5"));
+        assert!(!supports_declaration("width", "calc(2 * 3)"));
+        assert!(supports_declaration("width", "calc(50% - 2em)"));
+        assert!(supports_declaration("opacity", "50%"));
+        assert!(supports_declaration("line-height", "1.5"));
+        assert!(supports_declaration("display", "inline flow-root"));
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_are_skipped() {
+        let stylesheet = parse("p { width: red; color: 10px; margin: 1px red; z-index: 2; height: 0; \
+            border-top-color: currentcolor; padding-left: auto }".to_string());
+        let kept: Vec<&str> = style_rules(&stylesheet)[0].declarations.iter()
+            .map(|declaration| &*declaration.name)
+            .collect();
+        assert_eq!(kept, ["z-index", "height", "border-top-color"]);
+        assert_eq!(stylesheet.warnings.len(), 4, "{:?}", stylesheet.warnings);
+        assert!(stylesheet.warnings[0].contains("width: red"), "{:?}", stylesheet.warnings);
+    }
+}
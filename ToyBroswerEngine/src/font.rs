@@ -0,0 +1,544 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+use fontdb::{Database, Family, Language, Query, Source, Stretch, Style, Weight, ID};
+use rustybuzz::ttf_parser::{self, GlyphId, OutlineBuilder, Tag};
+use rustybuzz::{Face, Feature, UnicodeBuffer, Variation};
+use crate::computed::{ComputedStyle, FontFamily, FontStyle, GenericFamily};
+use crate::css::{FontFaceRule, FontSource};
+use crate::inline::{FontMetrics, TextMeasurer};
+use crate::loader::ResourceLoader;
+
+/// The font that every fallback chain ends with, so that there's always a font to draw text in,
+/// and text looks the same wherever it's drawn.
+static BUNDLED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+/// The family name of the bundled font.
+pub const BUNDLED_FAMILY: &str = "DejaVu Sans";
+
+/// The fonts a document can use: installed fonts, the fonts of its `@font-face` rules, and a
+/// bundled fallback font. Matches styles to fonts, shapes text and gives the outlines of glyphs.
+pub struct FontContext {
+    /// Installed fonts and the bundled font.
+    system: Database,
+    /// Fonts from `@font-face` rules, under the family names the rules give them.
+    web: Database,
+    /// The faces in `web`, in the order of their rules.
+    web_faces: Vec<ID>,
+    /// The code points a web font covers, if its rule limits them.
+    unicode_ranges: HashMap<ID, Vec<(u32, u32)>>,
+    bundled: ID,
+    /// The data of each face, read when it's first used. None for faces that couldn't be read.
+    data: RefCell<HashMap<FontId, Option<FontData>>>,
+    /// The fallback chain of each combination of font properties.
+    chains: RefCell<HashMap<FontKey, Rc<[FontId]>>>,
+    /// The widths of text that's been measured, by the font properties and size it was shaped
+    /// with, since inline layout measures the same text more than once.
+    widths: RefCell<HashMap<(String, FontKey, u32, bool), f32>>,
+}
+
+/// The font properties that select the faces in a fallback chain: `font-family`, `font-weight`
+/// and `font-style`.
+type FontKey = (Vec<FontFamily>, u16, FontStyle);
+
+/// A font face in a [`FontContext`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FontId {
+    web: bool,
+    id: ID,
+}
+
+#[derive(Clone)]
+struct FontData {
+    bytes: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    /// The index of the face in a font collection.
+    index: u32,
+}
+
+/// Text shaped into glyphs, in one or more runs that each use one font.
+#[derive(Clone, Debug, Default)]
+pub struct ShapedText {
+    pub runs: Vec<GlyphRun>,
+    /// The advance width of all the glyphs, in px.
+    pub width: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct GlyphRun {
+    pub font: FontId,
+    /// The font size in px.
+    pub size: f32,
+    /// The weight that a variable font's glyphs are drawn at.
+    pub weight: u16,
+    pub glyphs: Vec<Glyph>,
+}
+
+/// A glyph placed in shaped text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    pub id: u16,
+    /// The position of the glyph's origin from the start of the text on its baseline, in px,
+    /// with y going down.
+    pub x: f32,
+    pub y: f32,
+    pub advance: f32,
+    /// The byte offset in the text of the first character the glyph is for.
+    pub cluster: usize,
+}
+
+/// A part of a glyph's outline, in px from the glyph's origin with y going down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathCommand {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    /// A quadratic Bézier curve, with its control point first.
+    QuadTo(f32, f32, f32, f32),
+    /// A cubic Bézier curve, with its two control points first.
+    CurveTo(f32, f32, f32, f32, f32, f32),
+    Close,
+}
+
+impl FontContext {
+    /// The installed fonts and the bundled font.
+    pub fn system() -> FontContext {
+        let mut system = Database::new();
+        system.load_system_fonts();
+        FontContext::with_database(system)
+    }
+
+    /// Only the bundled font, which every family falls back to, so that text lays out and
+    /// renders the same on every machine.
+    pub fn bundled() -> FontContext {
+        FontContext::with_database(Database::new())
+    }
+
+    fn with_database(mut system: Database) -> FontContext {
+        let bundled = system.load_font_source(Source::Binary(Arc::new(BUNDLED_FONT)))[0];
+        FontContext {
+            system,
+            web: Database::new(),
+            web_faces: Vec::new(),
+            unicode_ranges: HashMap::new(),
+            bundled,
+            data: Default::default(),
+            chains: Default::default(),
+            widths: Default::default(),
+        }
+    }
+
+    /// Make the fonts of `@font-face` rules available under the rules' family names, loading each
+    /// from the first of its sources that works. Returns a warning for each rule whose font
+    /// couldn't be loaded.
+    pub fn add_font_faces(&mut self, font_faces: &[&FontFaceRule], loader: &dyn ResourceLoader) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for font_face in font_faces {
+            // A rule without a family name is invalid.
+            let Some(ref family) = font_face.family else { continue };
+            let mut errors = Vec::new();
+            let loaded = font_face.sources.iter().find_map(|source| {
+                self.load_source(source, loader).map_err(|error| errors.push(error)).ok()
+            });
+            let Some((source, index)) = loaded else {
+                warnings.push(format!("Couldn't load a font for font-family {}: {}", family, errors.join("; ")));
+                continue;
+            };
+
+            // Keep the face the source refers to, and describe it the way the rule does.
+            let mut face = None;
+            for id in self.web.load_font_source(source) {
+                if self.web.face(id).is_some_and(|info| info.index == index) {
+                    face = self.web.face(id).cloned();
+                }
+                self.web.remove_face(id);
+            }
+            let Some(mut face) = face else { continue };
+            face.families = vec![(family.clone(), Language::English_UnitedStates)];
+            let (min_weight, max_weight) = font_face.weight;
+            face.weight = Weight(face.weight.0.clamp(min_weight, max_weight));
+            face.style = match &*font_face.style {
+                "italic" => Style::Italic,
+                "oblique" => Style::Oblique,
+                _ => Style::Normal,
+            };
+            let id = self.web.push_face_info(face);
+            self.web_faces.push(id);
+            if !font_face.unicode_range.is_empty() {
+                self.unicode_ranges.insert(id, font_face.unicode_range.clone());
+            }
+        }
+        self.chains.get_mut().clear();
+        self.widths.get_mut().clear();
+        warnings
+    }
+
+    /// Get the font data a `src` descriptor refers to, and the index of the face in it.
+    fn load_source(&self, source: &FontSource, loader: &dyn ResourceLoader) -> Result<(Source, u32), String> {
+        match source {
+            FontSource::Url(url, format) => {
+                // Compressed formats, like WOFF, aren't supported.
+                if let Some(format) = format {
+                    if !matches!(&*format.to_ascii_lowercase(), "truetype" | "opentype" | "collection") {
+                        return Err(format!("{}: unsupported format {}", url, format));
+                    }
+                }
+                let bytes = loader.load_bytes(url)?;
+                if ttf_parser::fonts_in_collection(&bytes).is_none() && ttf_parser::Face::parse(&bytes, 0).is_err() {
+                    return Err(format!("{}: not a TrueType or OpenType font", url));
+                }
+                Ok((Source::Binary(Arc::new(bytes)), 0))
+            }
+            FontSource::Local(name) => self.system.faces()
+                .find(|face| face.post_script_name.eq_ignore_ascii_case(name)
+                    || face.families.iter().any(|(family, _)| family.eq_ignore_ascii_case(name)))
+                .map(|face| (face.source.clone(), face.index))
+                .ok_or_else(|| format!("local({}): not installed", name)),
+        }
+    }
+
+    /// The faces to draw text in a style in, most preferred first: the best match for each
+    /// family in `font-family` that has one, then the bundled font. A family from `@font-face`
+    /// rules gives all of its faces that only differ in the characters they cover.
+    fn font_chain(&self, style: &ComputedStyle) -> Rc<[FontId]> {
+        let key = (style.font_family.clone(), style.font_weight, style.font_style);
+        if let Some(chain) = self.chains.borrow().get(&key) {
+            return chain.clone();
+        }
+        let system_font = |name: &str| {
+            let name = family_name(&self.system, name)?;
+            self.system.query(&query(&[Family::Name(name)], style)).map(|id| FontId { web: false, id })
+        };
+        let mut chain: Vec<FontId> = Vec::new();
+        for family in &style.font_family {
+            match family {
+                FontFamily::Named(name) => {
+                    let web_fonts = self.web_fonts(name, style);
+                    if web_fonts.is_empty() {
+                        chain.extend(system_font(name));
+                    }
+                    chain.extend(web_fonts);
+                }
+                FontFamily::Generic(generic) => chain.extend(generic_family_names(*generic).iter().find_map(|name| system_font(name))),
+            }
+        }
+        chain.push(FontId { web: false, id: self.bundled });
+        let mut seen = Vec::new();
+        chain.retain(|font| if seen.contains(font) { false } else { seen.push(*font); true });
+
+        let chain: Rc<[FontId]> = chain.into();
+        self.chains.borrow_mut().insert(key, chain.clone());
+        chain
+    }
+
+    /// The faces of a family from `@font-face` rules with the weight and style that best match a
+    /// style. Later rules take precedence.
+    fn web_fonts(&self, name: &str, style: &ComputedStyle) -> Vec<FontId> {
+        let Some(name) = family_name(&self.web, name) else { return Vec::new() };
+        let Some(best) = self.web.query(&query(&[Family::Name(name)], style)).and_then(|id| self.web.face(id)) else {
+            return Vec::new();
+        };
+        self.web_faces.iter().rev()
+            .filter_map(|&id| self.web.face(id))
+            .filter(|face| face.families.iter().any(|(family, _)| family == name))
+            .filter(|face| face.weight == best.weight && face.style == best.style)
+            .map(|face| FontId { web: true, id: face.id })
+            .collect()
+    }
+
+    fn font_data(&self, font: FontId) -> Option<FontData> {
+        if let Some(data) = self.data.borrow().get(&font) {
+            return data.clone();
+        }
+        let database = if font.web { &self.web } else { &self.system };
+        let data = database.face_source(font.id).and_then(|(source, index)| {
+            let bytes = match source {
+                Source::Binary(bytes) | Source::SharedFile(_, bytes) => bytes,
+                Source::File(path) => Arc::new(std::fs::read(path).ok()?),
+            };
+            ttf_parser::Face::parse((*bytes).as_ref(), index).ok()?;
+            Some(FontData { bytes, index })
+        });
+        self.data.borrow_mut().insert(font, data.clone());
+        data
+    }
+
+    /// Shape text in the fonts a style selects. Each character is drawn in the first font in the
+    /// fallback chain that has a glyph for it, so the text may be split into runs in different
+    /// fonts.
+    pub fn shape(&self, text: &str, style: &ComputedStyle) -> ShapedText {
+        let chain = self.font_chain(style);
+        let data: Vec<Option<FontData>> = chain.iter().map(|&font| self.font_data(font)).collect();
+        let faces: Vec<Option<Face>> = data.iter().map(|data| {
+            let data = data.as_ref()?;
+            let mut face = Face::from_slice((*data.bytes).as_ref(), data.index)?;
+            set_weight(&mut face, style.font_weight);
+            Some(face)
+        }).collect();
+
+        // Split the text into runs that each use one font. Marks and joiners stay in the font of
+        // the character they go with.
+        let covers = |index: usize, c: char| {
+            faces[index].as_ref().is_some_and(|face| face.glyph_index(c).is_some())
+                && self.unicode_ranges.get(&chain[index].id).filter(|_| chain[index].web)
+                    .is_none_or(|ranges| ranges.iter().any(|&(start, end)| (start..=end).contains(&(c as u32))))
+        };
+        let primary = faces.iter().position(Option::is_some).expect("The bundled font couldn't be loaded");
+        let mut runs: Vec<(usize, Range<usize>)> = Vec::new();
+        for (offset, c) in text.char_indices() {
+            let face = match runs.last() {
+                Some(&(face, _)) if is_combining(c) => face,
+                _ => (0..faces.len()).find(|&index| covers(index, c)).unwrap_or(primary),
+            };
+            match runs.last_mut() {
+                Some((last, range)) if *last == face => range.end = offset + c.len_utf8(),
+                _ => runs.push((face, offset..offset + c.len_utf8())),
+            }
+        }
+
+        // Ligatures would take the space out from between letters.
+        let features = if style.letter_spacing != 0.0 {
+            vec![Feature::new(Tag::from_bytes(b"liga"), 0, ..), Feature::new(Tag::from_bytes(b"clig"), 0, ..)]
+        } else {
+            Vec::new()
+        };
+        let mut shaped = ShapedText::default();
+        for (index, range) in runs {
+            let face = faces[index].as_ref().unwrap();
+            let mut buffer = UnicodeBuffer::new();
+            buffer.push_str(&text[range.clone()]);
+            buffer.guess_segment_properties();
+            let glyphs = rustybuzz::shape(face, &features, buffer);
+            let scale = style.font_size / face.units_per_em() as f32;
+            let mut run = GlyphRun { font: chain[index], size: style.font_size, weight: style.font_weight, glyphs: Vec::new() };
+            for (info, position) in glyphs.glyph_infos().iter().zip(glyphs.glyph_positions()) {
+                let advance = position.x_advance as f32 * scale;
+                run.glyphs.push(Glyph {
+                    id: info.glyph_id as u16,
+                    x: shaped.width + position.x_offset as f32 * scale,
+                    y: -position.y_offset as f32 * scale,
+                    advance,
+                    cluster: range.start + info.cluster as usize,
+                });
+                shaped.width += advance;
+            }
+            shaped.runs.push(run);
+        }
+        shaped
+    }
+
+    /// The vertical metrics of the first font a style selects.
+    pub fn metrics(&self, style: &ComputedStyle) -> FontMetrics {
+        let data = self.font_chain(style).iter().find_map(|&font| self.font_data(font))
+            .expect("The bundled font couldn't be loaded");
+        let face = ttf_parser::Face::parse((*data.bytes).as_ref(), data.index).unwrap();
+        let scale = style.font_size / face.units_per_em() as f32;
+        FontMetrics {
+            ascent: face.ascender() as f32 * scale,
+            descent: -face.descender() as f32 * scale,
+            line_gap: face.line_gap() as f32 * scale,
+        }
+    }
+
+    /// The outline of a glyph in a run, or None if it has none, like a space's.
+    pub fn glyph_outline(&self, run: &GlyphRun, glyph: u16) -> Option<Vec<PathCommand>> {
+        let data = self.font_data(run.font)?;
+        let mut face = Face::from_slice((*data.bytes).as_ref(), data.index)?;
+        set_weight(&mut face, run.weight);
+        let mut outline = Outline { commands: Vec::new(), scale: run.size / face.units_per_em() as f32 };
+        face.outline_glyph(GlyphId(glyph), &mut outline)?;
+        Some(outline.commands)
+    }
+}
+
+impl TextMeasurer for FontContext {
+    fn text_width(&self, text: &str, style: &ComputedStyle) -> f32 {
+        let key = (text.to_string(), (style.font_family.clone(), style.font_weight, style.font_style),
+                   style.font_size.to_bits(), style.letter_spacing != 0.0);
+        if let Some(&width) = self.widths.borrow().get(&key) {
+            return width;
+        }
+        let width = self.shape(text, style).width;
+        self.widths.borrow_mut().insert(key, width);
+        width
+    }
+
+    fn font_metrics(&self, style: &ComputedStyle) -> FontMetrics {
+        self.metrics(style)
+    }
+}
+
+fn query<'a>(families: &'a [Family<'a>], style: &ComputedStyle) -> Query<'a> {
+    Query {
+        families,
+        weight: Weight(style.font_weight),
+        stretch: Stretch::Normal,
+        style: match style.font_style {
+            FontStyle::Normal => Style::Normal,
+            FontStyle::Italic => Style::Italic,
+            FontStyle::Oblique => Style::Oblique,
+        },
+    }
+}
+
+/// The name a database has for a family, which CSS matches without regard to ASCII case.
+fn family_name<'d>(database: &'d Database, name: &str) -> Option<&'d str> {
+    database.faces().flat_map(|face| &face.families)
+        .map(|(family, _)| family.as_str())
+        .find(|family| family.eq_ignore_ascii_case(name))
+}
+
+/// Common families for each generic family, most preferred first. The first that's installed is
+/// used.
+fn generic_family_names(generic: GenericFamily) -> &'static [&'static str] {
+    match generic {
+        GenericFamily::Serif => &["Times New Roman", "Liberation Serif", "DejaVu Serif", "Noto Serif"],
+        GenericFamily::SansSerif | GenericFamily::SystemUi => &["Arial", "Helvetica", "Liberation Sans", BUNDLED_FAMILY, "Noto Sans"],
+        GenericFamily::Monospace => &["Courier New", "Liberation Mono", "DejaVu Sans Mono", "Noto Sans Mono"],
+        GenericFamily::Cursive => &["Comic Sans MS", "URW Chancery L"],
+        GenericFamily::Fantasy => &["Impact", "Papyrus"],
+    }
+}
+
+/// Whether a character is drawn together with the one before it: a combining mark, a zero-width
+/// joiner or non-joiner, or a variation selector.
+fn is_combining(c: char) -> bool {
+    matches!(c as u32, 0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x200C | 0x200D | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F | 0xE0100..=0xE01EF)
+}
+
+/// Set the weight axis of a variable font.
+fn set_weight(face: &mut Face, weight: u16) {
+    let wght = Tag::from_bytes(b"wght");
+    if face.variation_axes().into_iter().any(|axis| axis.tag == wght) {
+        face.set_variations(&[Variation { tag: wght, value: weight as f32 }]);
+    }
+}
+
+/// Collects the outline of a glyph, scaled from font units to px and flipped so y goes down.
+struct Outline {
+    commands: Vec<PathCommand>,
+    scale: f32,
+}
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.commands.push(PathCommand::MoveTo(x * self.scale, -y * self.scale));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.commands.push(PathCommand::LineTo(x * self.scale, -y * self.scale));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let s = self.scale;
+        self.commands.push(PathCommand::QuadTo(x1 * s, -y1 * s, x * s, -y * s));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let s = self.scale;
+        self.commands.push(PathCommand::CurveTo(x1 * s, -y1 * s, x2 * s, -y2 * s, x * s, -y * s));
+    }
+
+    fn close(&mut self) {
+        self.commands.push(PathCommand::Close);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::MediaEnvironment;
+    use crate::style::{style_tree, Cascade};
+    use crate::{css, html};
+
+    /// Serves the bundled font at every URL.
+    struct BundledFontLoader;
+
+    impl ResourceLoader for BundledFontLoader {
+        fn load(&self, url: &str) -> Result<String, String> {
+            Err(format!("{}: not text", url))
+        }
+
+        fn load_bytes(&self, _url: &str) -> Result<Vec<u8>, String> {
+            Ok(BUNDLED_FONT.to_vec())
+        }
+    }
+
+    /// The style of a paragraph, and the `@font-face` rules, of a style sheet.
+    fn paragraph_style(source: &str) -> (ComputedStyle, css::Stylesheet) {
+        let stylesheet = css::parse(source.to_string());
+        let root = html::parse("<p>Text</p>".to_string());
+        let style = {
+            let styles = style_tree(&root, &Cascade::author(&stylesheet), &MediaEnvironment::screen(800.0, 600.0));
+            (*styles.children[0].style).clone()
+        };
+        (style, stylesheet)
+    }
+
+    #[test]
+    fn unknown_families_fall_back_to_the_bundled_font() {
+        let fonts = FontContext::bundled();
+        let bundled = FontId { web: false, id: fonts.bundled };
+        let (style, _) = paragraph_style("p { font-family: Missing, serif; font-size: 20px }");
+        assert_eq!(*fonts.font_chain(&style), [bundled]);
+
+        let shaped = fonts.shape("Hello", &style);
+        assert_eq!(shaped.runs.len(), 1);
+        assert_eq!(shaped.runs[0].font, bundled);
+        assert_eq!(shaped.runs[0].glyphs.len(), 5);
+        assert!(shaped.runs[0].glyphs.iter().all(|glyph| glyph.id != 0));
+        assert!(shaped.width > 0.0);
+
+        // A character no font has is drawn as the bundled font's missing glyph.
+        let shaped = fonts.shape("\u{4E2D}", &style);
+        assert_eq!(shaped.runs[0].font, bundled);
+        assert_eq!(shaped.runs[0].glyphs[0].id, 0);
+
+        // Family names match without regard to case.
+        let (named, _) = paragraph_style("p { font-family: 'dejavu sans'; font-size: 40px }");
+        assert_eq!(*fonts.font_chain(&named), [bundled]);
+        assert!((fonts.text_width("Hello", &named) - 2.0 * fonts.text_width("Hello", &style)).abs() < 0.01);
+    }
+
+    #[test]
+    fn web_fonts_cover_their_unicode_range() {
+        let mut fonts = FontContext::bundled();
+        let (style, stylesheet) = paragraph_style("
+            @font-face { font-family: Web; src: url(web.ttf); unicode-range: U+41-5A }
+            @font-face { font-family: Compressed; src: url(web.woff2) format('woff2'), local(Missing) }
+            p { font-family: Compressed, Web }");
+        let media = MediaEnvironment::screen(800.0, 600.0);
+        let warnings = fonts.add_font_faces(&Cascade::author(&stylesheet).font_faces(&media), &BundledFontLoader);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].starts_with("Couldn't load a font for font-family Compressed"));
+
+        // Capitals are in the web font, and everything else falls back to the bundled font.
+        let shaped = fonts.shape("ABcd", &style);
+        let runs: Vec<(bool, usize)> = shaped.runs.iter().map(|run| (run.font.web, run.glyphs.len())).collect();
+        assert_eq!(runs, [(true, 2), (false, 2)]);
+        assert_eq!(shaped.runs[1].font.id, fonts.bundled);
+    }
+
+    #[test]
+    fn glyph_outlines_and_metrics() {
+        let fonts = FontContext::bundled();
+        let (style, _) = paragraph_style("p { font-size: 32px }");
+        let shaped = fonts.shape("A ", &style);
+        let run = &shaped.runs[0];
+
+        let outline = fonts.glyph_outline(run, run.glyphs[0].id).unwrap();
+        assert!(matches!(outline.first(), Some(PathCommand::MoveTo(..))));
+        assert_eq!(outline.last(), Some(&PathCommand::Close));
+        // The outline is scaled to px, with y going down from the baseline.
+        assert!(outline.iter().all(|command| match *command {
+            PathCommand::MoveTo(x, y) | PathCommand::LineTo(x, y) => (0.0..=32.0).contains(&x) && (-32.0..=0.0).contains(&y),
+            _ => true,
+        }));
+        assert_eq!(fonts.glyph_outline(run, run.glyphs[1].id), None);
+
+        let metrics = fonts.metrics(&style);
+        assert!(metrics.ascent > 20.0 && metrics.ascent < 32.0, "{:?}", metrics.ascent);
+        assert!(metrics.descent > 0.0 && metrics.descent < 16.0, "{:?}", metrics.descent);
+    }
+}
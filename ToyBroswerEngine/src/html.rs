@@ -10,6 +10,11 @@ pub fn parse(source: String) -> crate::dom::Node {
     }
 }
 
+// elements that can't have children, such as <link>, <br> and <img>
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+];
+
 pub struct Parser {
     pos: usize,
     input: String,
@@ -42,7 +47,14 @@ impl Parser {
         assert_eq!(self.consume_char(), '<');
         let tag_name = self.parse_tag_name();
         let attrs = self.parse_attributes();
+        // void elements have no contents or closing tag, and may end with `/>`
+        if self.starts_with("/") {
+            self.consume_char();
+        }
         assert_eq!(self.consume_char(), '>');
+        if VOID_ELEMENTS.contains(&&*tag_name.to_ascii_lowercase()) {
+            return crate::dom::elem(tag_name, attrs, Vec::new());
+        }
         // contents
         let children = self.parse_nodes();
         // closing tag
@@ -55,10 +67,7 @@ impl Parser {
     }
 
     pub fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-            _ => false
-        })
+        self.consume_while(|c| c.is_ascii_alphanumeric())
     }
 
     // parse a list of name = value pairs, seperated by whitespace
@@ -66,7 +75,7 @@ impl Parser {
         let mut attributes = HashMap::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '>' {
+            if self.next_char() == '>' || self.starts_with("/>") {
                 break;
             }
             let (name, value) = self.parse_attr();
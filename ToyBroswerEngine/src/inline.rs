@@ -0,0 +1,777 @@
+use std::ops::Range;
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use crate::computed::{ComputedStyle, Direction, LineHeight, TextAlign, TextTransform, VerticalAlign, WhiteSpace};
+use crate::layout::BoxType::{BlockNode, InlineBlockNode, InlineNode};
+use crate::layout::{Dimensions, LayoutBox, Rect};
+use crate::style::StyleNode;
+
+/// The vertical metrics of a font at some size, in px.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontMetrics {
+    /// How far glyphs reach above the baseline.
+    pub ascent: f32,
+    /// How far glyphs reach below the baseline, as a positive distance.
+    pub descent: f32,
+    /// The gap the font recommends between lines, which `line-height: normal` adds.
+    pub line_gap: f32,
+}
+
+/// Measures text for inline layout, in the font that a style selects.
+pub trait TextMeasurer {
+    /// The advance width of a run of text, without letter or word spacing.
+    fn text_width(&self, text: &str, style: &ComputedStyle) -> f32;
+    fn font_metrics(&self, style: &ComputedStyle) -> FontMetrics;
+}
+
+/// Estimates the size of text from the font size alone, for when there are no fonts. Like `ch`
+/// in style, every character is taken to be half an em wide.
+pub struct ApproximateTextMeasurer;
+
+impl TextMeasurer for ApproximateTextMeasurer {
+    fn text_width(&self, text: &str, style: &ComputedStyle) -> f32 {
+        text.chars().count() as f32 * style.font_size / 2.0
+    }
+
+    fn font_metrics(&self, style: &ComputedStyle) -> FontMetrics {
+        let size = style.font_size;
+        FontMetrics { ascent: size * 0.8, descent: size * 0.2, line_gap: size * 0.2 }
+    }
+}
+
+/// A line of inline content.
+pub struct LineBox<'a> {
+    /// Spans the width of the containing block.
+    pub rect: Rect,
+    /// The y coordinate of the line's baseline.
+    pub baseline: f32,
+    /// Inline boxes come before their content, in document order.
+    pub fragments: Vec<Fragment<'a>>,
+}
+
+/// A piece of inline content placed on a line. Atomic inlines, like inline blocks, aren't
+/// fragments: they stay in the box tree, moved to their place on the line.
+pub struct Fragment<'a> {
+    /// The text node, or the element of an inline box.
+    pub style_node: &'a StyleNode<'a>,
+    pub kind: FragmentKind,
+    /// For text, its advance width by the height of its font. For an inline box, the border box
+    /// of its part on the line.
+    pub rect: Rect,
+    /// The y coordinate of the baseline the fragment sits on, which `vertical-align` can move
+    /// off the line's baseline.
+    pub baseline: f32,
+}
+
+pub enum FragmentKind {
+    Text(String),
+    /// The part of an inline box on one line. Its start margin, border and padding are only on
+    /// its first part, and its end ones on its last.
+    InlineBox { first: bool, last: bool },
+}
+
+/// Inline content in document order, with the inline boxes it's in flattened into start and end
+/// markers. `range` is where the item's content is in the text of the whole block.
+struct Item<'a> {
+    kind: ItemKind<'a>,
+    range: Range<usize>,
+}
+
+enum ItemKind<'a> {
+    Start(&'a StyleNode<'a>),
+    End(&'a StyleNode<'a>),
+    /// Text after white space processing and `text-transform`.
+    Text(&'a StyleNode<'a>),
+    /// An atomic inline. `path` leads to its box from the block, and the sizes are those of its
+    /// margin box once it's been laid out or measured.
+    Atomic {
+        path: Vec<usize>,
+        style: Option<&'a ComputedStyle>,
+        width: f32,
+        height: f32,
+        /// The distance from the top of the margin box to the baseline.
+        baseline: f32,
+        /// Whether the content around it can wrap.
+        wrap: bool,
+    },
+}
+
+/// A part of an item that falls between two break opportunities.
+struct Piece {
+    item: usize,
+    range: Range<usize>,
+    width: f32,
+}
+
+/// Lay out the inline children of an anonymous block in line boxes, filling each line as far as
+/// the containing block's width allows. `style` is the style of the element the block is in.
+pub(crate) fn layout_inline_content<'a>(block: &mut LayoutBox<'a>, containing_block: Dimensions,
+                                        style: &ComputedStyle, text: &dyn TextMeasurer) {
+    let d = &mut block.dimensions;
+    d.content.x = containing_block.content.x;
+    d.content.y = containing_block.content.y + containing_block.content.height;
+    d.content.width = containing_block.content.width;
+    let (left, top, width) = (d.content.x, d.content.y, d.content.width);
+    block.lines.clear();
+
+    let (mut items, content) = collect_items(block, style);
+    for item in &mut items {
+        if let ItemKind::Atomic { ref path, width: ref mut atomic_width, ref mut height, ref mut baseline, .. } = item.kind {
+            // Atomic inlines are laid out at the origin, and moved onto their line later.
+            let mut containing_block = Dimensions::default();
+            containing_block.content.width = width;
+            let atomic = find_box(block, path);
+            atomic.layout(&mut containing_block, style, text);
+            let margin_box = atomic.dimensions.margin_box();
+            *atomic_width = margin_box.width;
+            *height = margin_box.height;
+            *baseline = atomic.last_baseline().map_or(margin_box.height, |baseline| baseline - margin_box.y);
+        }
+    }
+
+    // Lay out the segments between break opportunities in lines, starting a new line when the
+    // next segment doesn't fit. Collapsible spaces at the end of a line don't have to fit.
+    let (breaks, segments) = segment_items(&items, &content, width, text);
+    let mut lines: Vec<(Range<usize>, bool)> = Vec::new();
+    let mut line_start = 0;
+    let mut line_width = 0.0;
+    for (index, segment) in segments.iter().enumerate() {
+        let available = width - if lines.is_empty() { style.text_indent.resolve(width) } else { 0.0 };
+        let segment_width: f32 = segment.iter().map(|piece| piece.width).sum();
+        if index > line_start && line_width + segment_width - hanging_width(&items, &content, segment, text) > available {
+            lines.push((line_start..index, false));
+            line_start = index;
+            line_width = 0.0;
+        }
+        line_width += segment_width;
+        if breaks.get(index).is_some_and(|&(_, forced)| forced) {
+            lines.push((line_start..index + 1, true));
+            line_start = index + 1;
+            line_width = 0.0;
+        }
+    }
+    if line_start < segments.len() {
+        lines.push((line_start..segments.len(), true));
+    }
+
+    let mut builder = LineBuilder { items: &items, content: &content, style, text, open: Vec::new(), atomics: Vec::new() };
+    let mut y = top;
+    for (index, (range, last)) in lines.into_iter().enumerate() {
+        // Pieces of the same text next to each other on a line make one fragment.
+        let mut pieces: Vec<Piece> = Vec::new();
+        for piece in segments[range].iter().flatten() {
+            match pieces.last_mut() {
+                Some(last) if last.item == piece.item && !last.range.is_empty() && last.range.end == piece.range.start => {
+                    last.range.end = piece.range.end;
+                    last.width += piece.width;
+                }
+                _ => pieces.push(Piece { item: piece.item, range: piece.range.clone(), width: piece.width }),
+            }
+        }
+        let indent = if index == 0 { style.text_indent.resolve(width) } else { 0.0 };
+        if let Some(line) = builder.build(&pieces, Rect { x: left, y, width, height: 0.0 }, indent, last) {
+            y += line.rect.height;
+            block.lines.push(line);
+        }
+    }
+    for (path, dx, dy) in builder.atomics {
+        find_box(block, &path).translate(dx, dy);
+    }
+    block.dimensions.content.height = y - top;
+}
+
+/// The min-content and max-content widths of the inline children of an anonymous block: the
+/// width of the widest thing that can't be broken, and the width the content takes when it's
+/// only broken where it has to be.
+pub(crate) fn inline_content_widths(block: &LayoutBox, style: &ComputedStyle, text: &dyn TextMeasurer) -> (f32, f32) {
+    let (mut items, content) = collect_items(block, style);
+    let mut min_widths = vec![None; items.len()];
+    for (item, min_width) in items.iter_mut().zip(&mut min_widths) {
+        if let ItemKind::Atomic { ref path, ref mut width, .. } = item.kind {
+            let (min, max) = find_box_ref(block, path).preferred_widths(style, text);
+            *width = max;
+            *min_width = Some(min);
+        }
+    }
+    // Percentages of the width count as zero.
+    let (breaks, segments) = segment_items(&items, &content, 0.0, text);
+    let mut min: f32 = 0.0;
+    let mut max: f32 = 0.0;
+    let mut line_width = 0.0;
+    for (index, segment) in segments.iter().enumerate() {
+        let hanging = hanging_width(&items, &content, segment, text);
+        let min_width: f32 = segment.iter().map(|piece| min_widths[piece.item].unwrap_or(piece.width)).sum();
+        min = min.max(min_width - hanging);
+        line_width += segment.iter().map(|piece| piece.width).sum::<f32>();
+        if breaks.get(index).is_none_or(|&(_, forced)| forced) {
+            max = max.max(line_width - hanging);
+            line_width = 0.0;
+        }
+    }
+    let indent = style.text_indent.resolve(0.0);
+    (min + indent.max(0.0), max + indent)
+}
+
+fn find_box<'b, 'a>(block: &'b mut LayoutBox<'a>, path: &[usize]) -> &'b mut LayoutBox<'a> {
+    path.iter().fold(block, |layout_box, &index| &mut layout_box.children[index])
+}
+
+fn find_box_ref<'b, 'a>(block: &'b LayoutBox<'a>, path: &[usize]) -> &'b LayoutBox<'a> {
+    path.iter().fold(block, |layout_box, &index| &layout_box.children[index])
+}
+
+/// Flatten the inline content of a block into items, and the text it all adds up to. Atomic
+/// inlines stand for an object replacement character in the text, so that line breaking treats
+/// them like an image. Their sizes are left at zero.
+fn collect_items<'a>(block: &LayoutBox<'a>, style: &ComputedStyle) -> (Vec<Item<'a>>, String) {
+    let mut collector = ItemCollector { items: Vec::new(), content: String::new(), column: 0, after_space: true };
+    collector.collect(&block.children, &mut Vec::new(), style);
+    (collector.items, collector.content)
+}
+
+struct ItemCollector<'a> {
+    items: Vec<Item<'a>>,
+    content: String,
+    /// The number of characters in the content since its last line break.
+    column: usize,
+    /// The content so far ends with a collapsible space, or is empty, so collapsible spaces that
+    /// follow are removed.
+    after_space: bool,
+}
+
+impl<'a> ItemCollector<'a> {
+    fn collect(&mut self, boxes: &[LayoutBox<'a>], path: &mut Vec<usize>, parent_style: &ComputedStyle) {
+        for (index, child) in boxes.iter().enumerate() {
+            path.push(index);
+            match child.box_type {
+                InlineNode(node) => match node.text() {
+                    Some(text) => {
+                        let text = process_white_space(&transform_text(text, node.style.text_transform),
+                                                       node.style.white_space, &mut self.column, &mut self.after_space);
+                        self.push(ItemKind::Text(node), &text);
+                    }
+                    None => {
+                        self.push(ItemKind::Start(node), "");
+                        self.collect(&child.children, path, &node.style);
+                        self.push(ItemKind::End(node), "");
+                    }
+                },
+                _ => {
+                    let style = match child.box_type {
+                        BlockNode(node) | InlineBlockNode(node) => Some(&*node.style),
+                        _ => None
+                    };
+                    let wrap = allows_wrap(parent_style.white_space);
+                    self.push(ItemKind::Atomic { path: path.clone(), style, width: 0.0, height: 0.0, baseline: 0.0, wrap }, "\u{FFFC}");
+                    self.column += 1;
+                    self.after_space = false;
+                }
+            }
+            path.pop();
+        }
+    }
+
+    fn push(&mut self, kind: ItemKind<'a>, text: &str) {
+        let start = self.content.len();
+        self.content.push_str(text);
+        self.items.push(Item { kind, range: start..self.content.len() });
+    }
+}
+
+/// Find the break opportunities in the content, following UAX #14 and `white-space`, and split
+/// the items at them into the pieces of each segment between them. Returns each break's position
+/// and whether it's forced, along with the segments. Segment `i` ends at break `i`.
+fn segment_items(items: &[Item], content: &str, width: f32, text: &dyn TextMeasurer) -> (Vec<(usize, bool)>, Vec<Vec<Piece>>) {
+    // Whether content can wrap at a break depends on the `white-space` of the content before it.
+    let content_items: Vec<&Item> = items.iter().filter(|item| !item.range.is_empty()).collect();
+    let wraps = |position: usize| {
+        let index = content_items.partition_point(|item| item.range.end < position);
+        match content_items.get(index).map(|item| &item.kind) {
+            Some(ItemKind::Text(node)) => allows_wrap(node.style.white_space),
+            Some(&ItemKind::Atomic { wrap, .. }) => wrap,
+            _ => true,
+        }
+    };
+    let breaks: Vec<(usize, bool)> = linebreaks(content)
+        .filter(|&(position, _)| position > 0 && position < content.len())
+        .filter_map(|(position, opportunity)| match opportunity {
+            BreakOpportunity::Mandatory => Some((position, true)),
+            BreakOpportunity::Allowed if wraps(position) => Some((position, false)),
+            BreakOpportunity::Allowed => None,
+        })
+        .collect();
+
+    // Inline box starts go with the content after them, and ends with the content before them.
+    let starts: Vec<usize> = [0].into_iter().chain(breaks.iter().map(|&(position, _)| position)).collect();
+    let segment_at = |position: usize| starts.partition_point(|&start| start <= position) - 1;
+    let mut segments: Vec<Vec<Piece>> = starts.iter().map(|_| Vec::new()).collect();
+    for (index, item) in items.iter().enumerate() {
+        let Range { start, end } = item.range;
+        match item.kind {
+            ItemKind::Start(node) => {
+                let width = start_edge(&node.style, width);
+                segments[segment_at(start)].push(Piece { item: index, range: start..start, width });
+            }
+            ItemKind::End(node) => {
+                let width = end_edge(&node.style, width);
+                segments[segment_at(start.saturating_sub(1))].push(Piece { item: index, range: start..start, width });
+            }
+            ItemKind::Atomic { width, .. } => {
+                segments[segment_at(start)].push(Piece { item: index, range: start..end, width });
+            }
+            ItemKind::Text(node) => {
+                let mut piece_start = start;
+                while piece_start < end {
+                    let segment = segment_at(piece_start);
+                    let piece_end = starts.get(segment + 1).map_or(end, |&next| next.min(end));
+                    let width = spaced_width(&content[piece_start..piece_end], &node.style, text);
+                    segments[segment].push(Piece { item: index, range: piece_start..piece_end, width });
+                    piece_start = piece_end;
+                }
+            }
+        }
+    }
+    (breaks, segments)
+}
+
+/// The width of the collapsible spaces at the end of a segment, which can hang past the end of
+/// a line.
+fn hanging_width(items: &[Item], content: &str, segment: &[Piece], text: &dyn TextMeasurer) -> f32 {
+    let Some(piece) = segment.iter().rev().find(|piece| !piece.range.is_empty()) else { return 0.0 };
+    match items[piece.item].kind {
+        ItemKind::Text(node) if hangs_spaces(node.style.white_space) => {
+            let piece_text = &content[piece.range.clone()];
+            spaced_width(&piece_text[piece_text.trim_end_matches(' ').len()..], &node.style, text)
+        }
+        _ => 0.0
+    }
+}
+
+/// Places the pieces of each line, keeping track of the inline boxes that continue from one
+/// line to the next.
+struct LineBuilder<'a, 'i, 't> {
+    items: &'i [Item<'a>],
+    content: &'i str,
+    /// The style of the block, whose font sets the minimum height of a line.
+    style: &'t ComputedStyle,
+    text: &'t dyn TextMeasurer,
+    /// The inline boxes open at the end of the last line, outermost first.
+    open: Vec<&'a StyleNode<'a>>,
+    /// The atomic inlines placed so far, with how far to move them from the origin.
+    atomics: Vec<(Vec<usize>, f32, f32)>,
+}
+
+/// A fragment being placed, with how far `vertical-align` raises its baseline.
+struct Placed<'a> {
+    fragment: Fragment<'a>,
+    shift: f32,
+}
+
+impl<'a> LineBuilder<'a, '_, '_> {
+    /// Build a line from its pieces, or return None if it has nothing on it that takes up space.
+    /// `last` is set for the last line of the block and lines that end in a forced break.
+    fn build(&mut self, pieces: &[Piece], rect: Rect, indent: f32, last: bool) -> Option<LineBox<'a>> {
+        let texts = self.trimmed_texts(pieces);
+        let widths: Vec<f32> = pieces.iter().zip(&texts).map(|(piece, text)| match (text, &self.items[piece.item].kind) {
+            (Some(text), ItemKind::Text(node)) => spaced_width(text, &node.style, self.text),
+            _ => piece.width,
+        }).collect();
+        let has_content = texts.iter().any(|text| text.as_ref().is_some_and(|text| !text.is_empty()))
+            || pieces.iter().any(|piece| match self.items[piece.item].kind {
+                ItemKind::Atomic { .. } => true,
+                ItemKind::Start(_) | ItemKind::End(_) => piece.width != 0.0,
+                ItemKind::Text(_) => false,
+            });
+        if !has_content && self.open.is_empty() {
+            return None;
+        }
+
+        // Justified lines stretch the spaces between words to fill the line.
+        let remaining = rect.width - indent - widths.iter().sum::<f32>();
+        let spaces: usize = texts.iter().flatten().map(|text| text.matches(' ').count()).sum();
+        let justify = self.style.text_align == TextAlign::Justify && !last && spaces > 0 && remaining > 0.0;
+        let extra_spacing = if justify { remaining / spaces as f32 } else { 0.0 };
+        let rtl = self.style.direction == Direction::Rtl;
+        let offset = match self.style.text_align {
+            _ if justify => 0.0,
+            TextAlign::Left => 0.0,
+            TextAlign::Right => remaining,
+            TextAlign::Center => remaining / 2.0,
+            TextAlign::Start | TextAlign::Justify if rtl => remaining,
+            TextAlign::End if !rtl => remaining,
+            TextAlign::Start | TextAlign::End | TextAlign::Justify => 0.0,
+        };
+
+        // Place the fragments from left to right, starting with the inline boxes continued from
+        // the last line. `open` has the index and baseline shift of each inline box on the line
+        // that hasn't ended yet.
+        let mut x = rect.x + indent + offset;
+        let mut placed: Vec<Placed<'a>> = Vec::new();
+        let mut open: Vec<(usize, f32)> = Vec::new();
+        for &node in &self.open {
+            let shift = self.shift_in(&placed, &open, &node.style);
+            open.push((placed.len(), shift));
+            placed.push(Placed { fragment: inline_box(node, x, false), shift });
+        }
+        let mut atomics = Vec::new();
+        for ((piece, text), width) in pieces.iter().zip(texts).zip(widths) {
+            match self.items[piece.item].kind {
+                ItemKind::Start(node) => {
+                    let margin = node.style.margin.left.resolve(rect.width).unwrap_or(0.0);
+                    let shift = self.shift_in(&placed, &open, &node.style);
+                    open.push((placed.len(), shift));
+                    placed.push(Placed { fragment: inline_box(node, x + margin, true), shift });
+                    self.open.push(node);
+                    x += width;
+                }
+                ItemKind::End(node) => {
+                    let margin = node.style.margin.right.resolve(rect.width).unwrap_or(0.0);
+                    x += width - margin;
+                    if let Some((index, _)) = open.pop() {
+                        close_inline_box(&mut placed[index].fragment, x, true);
+                    }
+                    self.open.pop();
+                    x += margin;
+                }
+                ItemKind::Text(node) => {
+                    let text = text.unwrap_or_default();
+                    let shift = open.last().map_or(0.0, |&(_, shift)| shift);
+                    let words: Vec<&str> = if justify { text.split_inclusive(' ').collect() } else { vec![&text] };
+                    for word in words.into_iter().filter(|word| !word.is_empty()) {
+                        let width = if justify { spaced_width(word, &node.style, self.text) } else { width };
+                        let fragment = Fragment {
+                            style_node: node,
+                            kind: FragmentKind::Text(word.to_string()),
+                            rect: Rect { x, y: 0.0, width, height: 0.0 },
+                            baseline: 0.0,
+                        };
+                        placed.push(Placed { fragment, shift });
+                        x += width + if word.ends_with(' ') { extra_spacing } else { 0.0 };
+                    }
+                }
+                ItemKind::Atomic { ref path, style, height, baseline, .. } => {
+                    let shift = match style {
+                        Some(style) => self.shift_in(&placed, &open, style),
+                        None => open.last().map_or(0.0, |&(_, shift)| shift),
+                    };
+                    atomics.push((path.clone(), x, height, baseline, shift));
+                    x += width;
+                }
+            }
+        }
+        for &(index, _) in &open {
+            close_inline_box(&mut placed[index].fragment, x, false);
+        }
+
+        // Align everything on the baseline. Each inline box and run of text takes up its line
+        // height, with the leading split evenly above and below its font, and the line is tall
+        // enough for all of them. The block's own font sets the least a line can take up.
+        let extent = |style: &ComputedStyle, shift: f32| {
+            let metrics = self.text.font_metrics(style);
+            let half_leading = (line_height(style, &metrics) - metrics.ascent - metrics.descent) / 2.0;
+            (shift + metrics.ascent + half_leading, metrics.descent + half_leading - shift)
+        };
+        let (mut above, mut below) = extent(self.style, 0.0);
+        for placed in &placed {
+            let (a, b) = extent(&placed.fragment.style_node.style, placed.shift);
+            above = above.max(a);
+            below = below.max(b);
+        }
+        for &(_, _, height, baseline, shift) in &atomics {
+            above = above.max(baseline + shift);
+            below = below.max(height - baseline - shift);
+        }
+        let baseline = rect.y + above;
+        for placed in &mut placed {
+            let fragment = &mut placed.fragment;
+            let style = &fragment.style_node.style;
+            let metrics = self.text.font_metrics(style);
+            fragment.baseline = baseline - placed.shift;
+            fragment.rect.y = fragment.baseline - metrics.ascent;
+            fragment.rect.height = metrics.ascent + metrics.descent;
+            if let FragmentKind::InlineBox { .. } = fragment.kind {
+                let top = style.border_width.top + style.padding.top.resolve(rect.width);
+                let bottom = style.border_width.bottom + style.padding.bottom.resolve(rect.width);
+                fragment.rect.y -= top;
+                fragment.rect.height += top + bottom;
+            }
+        }
+        for (path, x, _, atomic_baseline, shift) in atomics {
+            self.atomics.push((path, x, baseline - shift - atomic_baseline));
+        }
+        Some(LineBox {
+            rect: Rect { height: above + below, ..rect },
+            baseline,
+            fragments: placed.into_iter().map(|placed| placed.fragment).collect(),
+        })
+    }
+
+    /// The baseline shift of something with `style` in the innermost open inline box, or in the
+    /// block if there isn't one.
+    fn shift_in(&self, placed: &[Placed], open: &[(usize, f32)], style: &ComputedStyle) -> f32 {
+        match open.last() {
+            Some(&(index, shift)) => shift + baseline_shift(style, &placed[index].fragment.style_node.style, self.text),
+            None => baseline_shift(style, self.style, self.text),
+        }
+    }
+
+    /// The text of each text piece on the line, without the collapsible spaces at the start and
+    /// end of the line, or the newlines that forced breaks.
+    fn trimmed_texts(&self, pieces: &[Piece]) -> Vec<Option<String>> {
+        let mut texts: Vec<Option<String>> = pieces.iter().map(|piece| match self.items[piece.item].kind {
+            ItemKind::Text(_) => Some(self.content[piece.range.clone()].replace('\n', "")),
+            _ => None,
+        }).collect();
+        let collapsible = |index: usize| match self.items[pieces[index].item].kind {
+            ItemKind::Text(node) => Some(hangs_spaces(node.style.white_space)),
+            ItemKind::Atomic { .. } => Some(false),
+            ItemKind::Start(_) | ItemKind::End(_) => None,
+        };
+        let mut trim = |indices: &mut dyn Iterator<Item=usize>, trim: fn(&str) -> &str| {
+            for index in indices {
+                match collapsible(index) {
+                    None => continue,
+                    Some(false) => break,
+                    Some(true) => {}
+                }
+                let text = texts[index].as_mut().unwrap();
+                *text = trim(text).to_string();
+                if !text.is_empty() {
+                    break;
+                }
+            }
+        };
+        trim(&mut (0..pieces.len()), |text| text.trim_start_matches(' '));
+        trim(&mut (0..pieces.len()).rev(), |text| text.trim_end_matches(' '));
+        texts
+    }
+}
+
+fn inline_box<'a>(node: &'a StyleNode<'a>, x: f32, first: bool) -> Fragment<'a> {
+    Fragment {
+        style_node: node,
+        kind: FragmentKind::InlineBox { first, last: false },
+        rect: Rect { x, y: 0.0, width: 0.0, height: 0.0 },
+        baseline: 0.0,
+    }
+}
+
+fn close_inline_box(fragment: &mut Fragment, x: f32, last: bool) {
+    fragment.rect.width = x - fragment.rect.x;
+    if let FragmentKind::InlineBox { last: ref mut is_last, .. } = fragment.kind {
+        *is_last = last;
+    }
+}
+
+/// Whether content can wrap at the break opportunities in it.
+fn allows_wrap(white_space: WhiteSpace) -> bool {
+    !matches!(white_space, WhiteSpace::Pre | WhiteSpace::Nowrap)
+}
+
+/// Whether spaces at the end of a line hang past it and are removed, as are spaces at the start
+/// of a line. Spaces that `pre` and `break-spaces` keep always take up room.
+fn hangs_spaces(white_space: WhiteSpace) -> bool {
+    matches!(white_space, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine | WhiteSpace::PreWrap)
+}
+
+/// Collapse white space as `white-space` says. Collapsed spaces become one space, with spaces
+/// after a space removed, even in earlier text; `after_space` carries that from one run of text
+/// to the next. Tabs that are kept go to the next multiple of 8 columns; `column` is the number
+/// of characters since the last line break, and is kept up to date.
+fn process_white_space(text: &str, white_space: WhiteSpace, column: &mut usize, after_space: &mut bool) -> String {
+    let mut processed = String::with_capacity(text.len());
+    for c in text.chars() {
+        match (white_space, c) {
+            (_, '\r') => {}
+            (WhiteSpace::Normal | WhiteSpace::Nowrap, ' ' | '\t' | '\n' | '\x0c')
+            | (WhiteSpace::PreLine, ' ' | '\t' | '\x0c') => {
+                if !*after_space {
+                    processed.push(' ');
+                    *column += 1;
+                    *after_space = true;
+                }
+            }
+            (WhiteSpace::PreLine, '\n') => {
+                processed.push('\n');
+                *column = 0;
+                *after_space = true;
+            }
+            (_, '\t') => {
+                let spaces = 8 - *column % 8;
+                processed.extend(std::iter::repeat_n(' ', spaces));
+                *column += spaces;
+                *after_space = false;
+            }
+            (_, '\n') => {
+                processed.push('\n');
+                *column = 0;
+                *after_space = false;
+            }
+            _ => {
+                processed.push(c);
+                *column += 1;
+                *after_space = false;
+            }
+        }
+    }
+    processed
+}
+
+fn transform_text(text: &str, text_transform: TextTransform) -> String {
+    match text_transform {
+        TextTransform::None => text.to_string(),
+        TextTransform::Uppercase => text.to_uppercase(),
+        TextTransform::Lowercase => text.to_lowercase(),
+        TextTransform::Capitalize => {
+            let mut transformed = String::with_capacity(text.len());
+            let mut in_word = false;
+            for c in text.chars() {
+                if !in_word && c.is_alphabetic() {
+                    transformed.extend(c.to_uppercase());
+                } else {
+                    transformed.push(c);
+                }
+                in_word = c.is_alphanumeric();
+            }
+            transformed
+        }
+    }
+}
+
+/// The width of some text, with its letter and word spacing.
+fn spaced_width(text: &str, style: &ComputedStyle, measurer: &dyn TextMeasurer) -> f32 {
+    let text = text.trim_end_matches('\n');
+    measurer.text_width(text, style) + style.letter_spacing * text.chars().count() as f32
+        + style.word_spacing * text.matches(' ').count() as f32
+}
+
+/// The margin, border and padding at the start of an inline box.
+fn start_edge(style: &ComputedStyle, percent_basis: f32) -> f32 {
+    style.margin.left.resolve(percent_basis).unwrap_or(0.0) + style.border_width.left
+        + style.padding.left.resolve(percent_basis)
+}
+
+fn end_edge(style: &ComputedStyle, percent_basis: f32) -> f32 {
+    style.margin.right.resolve(percent_basis).unwrap_or(0.0) + style.border_width.right
+        + style.padding.right.resolve(percent_basis)
+}
+
+/// The used line height of a style in px.
+fn line_height(style: &ComputedStyle, metrics: &FontMetrics) -> f32 {
+    match style.line_height {
+        LineHeight::Normal => metrics.ascent + metrics.descent + metrics.line_gap,
+        LineHeight::Number(number) => number * style.font_size,
+        LineHeight::Px(px) => px,
+    }
+}
+
+/// How far `vertical-align` raises a box's baseline above its parent's. The values that align to
+/// the line box or the parent's font aren't supported, and stay on the baseline.
+fn baseline_shift(style: &ComputedStyle, parent: &ComputedStyle, text: &dyn TextMeasurer) -> f32 {
+    match style.vertical_align {
+        VerticalAlign::Sub => -parent.font_size * 0.2,
+        VerticalAlign::Super => parent.font_size * 0.33,
+        // Percentages are of the box's own line height.
+        VerticalAlign::LengthPercentage(ref length) => length.resolve(line_height(style, &text.font_metrics(style))),
+        _ => 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{self, BoxType};
+    use crate::media::MediaEnvironment;
+    use crate::style::{self, Cascade};
+    use crate::{css, html};
+
+    /// Lay out the content of a body styled with `css` in an 800x600 viewport, with 10px text that
+    /// `ApproximateTextMeasurer` makes 5px a character, and check the lines of each block of
+    /// inline content in document order.
+    fn with_lines(html: &str, css: &str, check: impl FnOnce(Vec<&[LineBox]>)) {
+        fn collect<'b>(layout_box: &'b LayoutBox, lines: &mut Vec<&'b [LineBox<'b>]>) {
+            if let BoxType::AnonymousBlock = layout_box.box_type {
+                lines.push(&layout_box.lines);
+            }
+            for child in &layout_box.children {
+                collect(child, lines);
+            }
+        }
+        let root = html::parse(format!("<body>{}</body>", html));
+        let stylesheet = css::parse(format!("body {{ margin: 0; font-size: 10px }} p {{ margin: 0 }} {}", css));
+        let styles = style::style_tree(&root, &Cascade::author(&stylesheet), &MediaEnvironment::screen(800.0, 600.0));
+        let mut viewport = Dimensions::default();
+        viewport.content.width = 800.0;
+        viewport.content.height = 600.0;
+        let root_box = layout::layout_tree(&styles, viewport);
+        let mut lines = Vec::new();
+        collect(&root_box, &mut lines);
+        check(lines);
+    }
+
+    /// The text on each line.
+    fn texts(lines: &[LineBox]) -> Vec<String> {
+        lines.iter().map(|line| line.fragments.iter().filter_map(|fragment| match fragment.kind {
+            FragmentKind::Text(ref text) => Some(&**text),
+            FragmentKind::InlineBox { .. } => None,
+        }).collect()).collect()
+    }
+
+    #[test]
+    fn lines_break_at_the_last_opportunity_that_fits() {
+        with_lines("<p>aaa bbb ccc-ddd eeeeeeeeeeee f</p>", "p { width: 50px }", |blocks| {
+            // Lines break after spaces and hyphens, and a word too long for a line overflows it.
+            assert_eq!(texts(blocks[0]), ["aaa bbb", "ccc-ddd", "eeeeeeeeeeee", "f"]);
+            let tops: Vec<f32> = blocks[0].iter().map(|line| line.rect.y).collect();
+            assert_eq!(tops, [0.0, 12.0, 24.0, 36.0]);
+        });
+    }
+
+    #[test]
+    fn white_space_says_what_collapses_and_wraps() {
+        with_lines("<p id=\"pre\">a  b c\nd</p><p id=\"nowrap\">a  b c\nd</p><p id=\"pre-line\">a  b c\nd</p>\
+                <p id=\"tabs\">ab<b>c\td</b>e\nf\tg</p>",
+            "p { width: 15px } #pre, #tabs { white-space: pre } #nowrap { white-space: nowrap } \
+             #pre-line { white-space: pre-line }",
+            |blocks| {
+                assert_eq!(texts(blocks[0]), ["a  b c", "d"]);
+                assert_eq!(texts(blocks[1]), ["a b c d"]);
+                assert_eq!(texts(blocks[2]), ["a b", "c", "d"]);
+                // Tabs go to the next multiple of 8 columns, counting from the last line break.
+                assert_eq!(texts(blocks[3]), ["abc     de", "f       g"]);
+            });
+    }
+
+    #[test]
+    fn text_align_places_the_line_in_the_block() {
+        with_lines("<p id=\"left\">abc</p><p id=\"right\">abc</p><p id=\"center\">abc</p>\
+                <p id=\"justify\">aaa bb cc ddddddd</p>",
+            "p { width: 50px } #right { text-align: right } #center { text-align: center } \
+             #justify { text-align: justify }",
+            |blocks| {
+                let x = |lines: &[LineBox]| lines[0].fragments[0].rect.x;
+                assert_eq!(x(blocks[0]), 0.0);
+                assert_eq!(x(blocks[1]), 35.0);
+                assert_eq!(x(blocks[2]), 17.5);
+                // Justified lines share out the room left between their spaces, except the last.
+                let xs: Vec<f32> = blocks[3][0].fragments.iter().map(|fragment| fragment.rect.x).collect();
+                assert_eq!(xs, [0.0, 22.5, 40.0]);
+                assert_eq!(texts(blocks[3]), ["aaa bb cc", "ddddddd"]);
+                assert_eq!(x(&blocks[3][1..]), 0.0);
+            });
+    }
+
+    #[test]
+    fn text_of_different_sizes_shares_a_baseline() {
+        with_lines("<p>a<span>b</span>c</p>", "span { font-size: 20px }", |blocks| {
+            let line = &blocks[0][0];
+            // The line is tall enough for the larger font's ascent and descent, with its leading.
+            assert_eq!(line.baseline, 18.0);
+            assert_eq!(line.rect.height, 24.0);
+            for fragment in &line.fragments {
+                assert_eq!(fragment.baseline, line.baseline);
+            }
+            let tops: Vec<f32> = line.fragments.iter().map(|fragment| fragment.rect.y).collect();
+            assert_eq!(tops, [10.0, 2.0, 2.0, 10.0]);
+        });
+    }
+}
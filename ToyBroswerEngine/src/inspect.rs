@@ -0,0 +1,220 @@
+use std::fmt;
+use crate::css::{self, is_custom_property, Selector, Value};
+use crate::css::Value::Keyword;
+use crate::dom::{Node, NodeType};
+use crate::media::MediaEnvironment;
+use crate::properties;
+use crate::rule_map::{AncestorFilter, RuleMap};
+use crate::style::{matched_declarations, matches, winning_declaration};
+use crate::style::{Cascade, ElementRef, MatchedDeclaration, Origin, StyleNode};
+
+/// Answers questions about how a document was styled, such as which elements a selector
+/// matches and which declarations gave an element its style.
+pub struct Inspector<'a> {
+    root: &'a Node,
+    rules: RuleMap<'a>,
+}
+
+/// Where a computed value came from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueSource {
+    /// A declaration set it.
+    Declared,
+    /// It's the parent's value, because the property is inherited or was set to `inherit`.
+    Inherited,
+    /// It's the property's initial value.
+    Initial,
+}
+
+/// How one property of a node got its value.
+#[derive(Clone, Debug)]
+pub struct PropertyExplanation<'a> {
+    pub name: String,
+    /// None for a custom property that isn't set.
+    pub value: Option<Value>,
+    pub source: ValueSource,
+    /// The declaration that won the cascade.
+    pub winner: Option<MatchedDeclaration<'a>>,
+    /// The other declarations of the property that apply to the node, highest precedence first.
+    pub overridden: Vec<MatchedDeclaration<'a>>,
+}
+
+/// How every property of a node got its value, sorted by property name.
+#[derive(Clone, Debug)]
+pub struct StyleExplanation<'a> {
+    pub properties: Vec<PropertyExplanation<'a>>,
+}
+
+impl<'a> Inspector<'a> {
+    pub fn new(root: &'a Node, cascade: &Cascade<'a>, media: &MediaEnvironment) -> Inspector<'a> {
+        Inspector { root, rules: RuleMap::new(cascade.active_rules(media)) }
+    }
+
+    /// The elements that match a selector list, in document order. Fails if the selector list
+    /// is invalid, or uses selectors that aren't supported.
+    pub fn query_selector_all(&self, selectors: &str) -> Result<Vec<&'a Node>, String> {
+        let selectors: Vec<Selector> = css::parse_selector_list(selectors)?.into_iter()
+            .filter(|selector| selector.pseudo_element().is_none())
+            .collect();
+        let mut found = Vec::new();
+        if let NodeType::Element(_) = self.root.node_type {
+            query(&ElementRef::root(self.root), &selectors, &mut found);
+        }
+        Ok(found)
+    }
+
+    /// Explain the computed values of a node in the style tree built from this document, listing
+    /// for each property the declarations that apply, in the way a browser's style inspector
+    /// does.
+    pub fn explain(&self, style: &StyleNode) -> StyleExplanation<'a> {
+        let is_element = matches!(style.node.node_type, NodeType::Element(_));
+        let mut declarations = if is_element && style.generated_text.is_none() {
+            with_element(&ElementRef::root(self.root), style.node, &mut AncestorFilter::new(), &mut |elem, ancestors| {
+                matched_declarations(elem, style.pseudo, &self.rules, ancestors)
+            }).unwrap_or_else(|| panic!("The node isn't in the inspected document"))
+        } else {
+            Default::default()
+        };
+        // Only the root element has nothing to inherit from.
+        let has_parent = !std::ptr::eq(style.node, self.root) || style.pseudo.is_some() || !is_element;
+
+        let mut names: Vec<String> = style.computed_values().into_iter().map(|(name, _)| name.to_string())
+            .chain(declarations.keys().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        let properties = names.into_iter().map(|name| {
+            let mut candidates = declarations.remove(&*name).unwrap_or_default();
+            let winner = winning_declaration(&candidates).copied();
+            candidates.reverse();
+            let overridden = candidates.into_iter()
+                .filter(|candidate| !winner.is_some_and(|winner| std::ptr::eq(winner.declaration, candidate.declaration)))
+                .collect();
+            let inherited = properties::lookup(&name).map_or(is_custom_property(&name), |property| property.inherited);
+            let source = match winner.map(|winner| &winner.declaration.value) {
+                Some(Keyword(keyword)) if keyword == "inherit" => ValueSource::Inherited,
+                Some(Keyword(keyword)) if keyword == "initial" => ValueSource::Initial,
+                Some(Keyword(keyword)) if keyword == "unset" && inherited && has_parent => ValueSource::Inherited,
+                Some(Keyword(keyword)) if keyword == "unset" => ValueSource::Initial,
+                Some(_) => ValueSource::Declared,
+                None if inherited && has_parent => ValueSource::Inherited,
+                None => ValueSource::Initial,
+            };
+            PropertyExplanation { value: style.computed_value(&name).cloned(), name, source, winner, overridden }
+        }).collect();
+        StyleExplanation { properties }
+    }
+}
+
+/// Find the matching elements in the subtree of `elem`.
+fn query<'a>(elem: &ElementRef<'a, '_>, selectors: &[Selector], found: &mut Vec<&'a Node>) {
+    if selectors.iter().any(|selector| matches(elem, selector)) {
+        found.push(elem.node);
+    }
+    for child in elem.children() {
+        query(&child, selectors, found);
+    }
+}
+
+/// Call `f` with the element for `target` and a filter of its ancestors, if `target` is in the
+/// subtree of `elem`.
+fn with_element<R>(elem: &ElementRef, target: &Node, ancestors: &mut AncestorFilter,
+                   f: &mut dyn FnMut(&ElementRef, &AncestorFilter) -> R) -> Option<R> {
+    if std::ptr::eq(elem.node, target) {
+        return Some(f(elem, ancestors));
+    }
+    ancestors.push(elem.data());
+    let result = elem.children().find_map(|child| with_element(&child, target, ancestors, f));
+    ancestors.pop(elem.data());
+    result
+}
+
+impl StyleExplanation<'_> {
+    pub fn property(&self, name: &str) -> Option<&PropertyExplanation<'_>> {
+        self.properties.iter().find(|property| property.name == name)
+    }
+}
+
+/// Lists the properties that declarations apply to, each followed by its declarations, like
+/// the styles pane of a browser's developer tools.
+impl fmt::Display for StyleExplanation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let declared = |property: &&PropertyExplanation| property.winner.is_some() || !property.overridden.is_empty();
+        for property in self.properties.iter().filter(declared) {
+            match property.value {
+                Some(ref value) => write!(f, "{}: {}", property.name, value)?,
+                None => write!(f, "{}: (not set)", property.name)?,
+            }
+            match property.source {
+                ValueSource::Declared => writeln!(f)?,
+                ValueSource::Inherited => writeln!(f, "  (inherited)")?,
+                ValueSource::Initial => writeln!(f, "  (initial)")?,
+            }
+            for matched in property.winner.iter().chain(&property.overridden) {
+                let (a, b, c) = matched.specificity;
+                let origin = match matched.origin {
+                    Origin::UserAgent => "user agent",
+                    Origin::User => "user",
+                    Origin::Author => "author",
+                };
+                write!(f, "    {} {{ {} }}  {}, specificity ({}, {}, {})",
+                       matched.selector, matched.declaration, origin, a, b, c)?;
+                if !property.winner.is_some_and(|winner| std::ptr::eq(winner.declaration, matched.declaration)) {
+                    f.write_str("  overridden")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+    use crate::style::style_tree;
+
+    #[test]
+    fn explain_lists_the_winner_then_overridden_declarations() {
+        let root = html::parse("<div><p id=\"x\" class=\"a\">Text</p></div>".to_string());
+        let user = css::parse("p { color: orange !important; padding-left: 1px }".to_string());
+        let author = css::parse("p { color: red; font-weight: inherit } .a { color: blue } #x { color: green } \
+            div p { color: purple; padding-left: 2px }".to_string());
+        let cascade = Cascade { user: vec![&user], ..Cascade::author(&author) };
+        let media = MediaEnvironment::screen(800.0, 600.0);
+        let styles = style_tree(&root, &cascade, &media);
+        let inspector = Inspector::new(&root, &cascade, &media);
+
+        let nodes = inspector.query_selector_all("div > p.a").unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert!(inspector.query_selector_all("p:hover").is_err());
+        let explanation = inspector.explain(styles.find(nodes[0], None).unwrap());
+        let selectors = |property: &PropertyExplanation| -> Vec<String> {
+            property.winner.iter().chain(&property.overridden).map(|matched| matched.selector.to_string()).collect()
+        };
+
+        // An important user declaration beats every normal author declaration, which are then
+        // in order of specificity.
+        let color = explanation.property("color").unwrap();
+        assert_eq!(color.source, ValueSource::Declared);
+        assert_eq!(color.winner.unwrap().origin, Origin::User);
+        assert_eq!(selectors(color), ["p", "#x", ".a", "div p", "p"]);
+        let padding = explanation.property("padding-left").unwrap();
+        assert_eq!(padding.winner.unwrap().origin, Origin::Author);
+        assert_eq!(selectors(padding), ["div p", "p"]);
+
+        assert_eq!(explanation.property("font-weight").unwrap().source, ValueSource::Inherited);
+        let font_size = explanation.property("font-size").unwrap();
+        assert_eq!(font_size.source, ValueSource::Inherited);
+        assert!(font_size.winner.is_none());
+        assert_eq!(explanation.property("padding-right").unwrap().source, ValueSource::Initial);
+
+        let text = explanation.to_string();
+        let line = |needle: &str| text.lines().position(|line| line.contains(needle)).unwrap();
+        assert!(line("p { color: rgb(255, 165, 0) !important }  user") < line("#x { color"));
+        assert!(line("#x { color") < line("div p { color"));
+        assert!(text.contains("#x { color: rgb(0, 128, 0) }  author, specificity (1, 0, 0)  overridden"), "{}", text);
+        assert!(text.contains("font-weight: 400  (inherited)"), "{}", text);
+    }
+}
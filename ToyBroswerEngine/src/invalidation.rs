@@ -0,0 +1,266 @@
+use std::collections::{HashMap, HashSet};
+use crate::css::{Combinator, CssRule, PseudoClass, Rule, Selector, SimpleSelector, Stylesheet};
+use crate::dom::ElementData;
+use crate::serialize::ToCss;
+
+/// Which elements may need restyling when an id or class of an element changes.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub(crate) struct Scope {
+    /// The element and its descendants, which selectors match as the subject or an ancestor.
+    pub subtree: bool,
+    /// The siblings after the element and their descendants.
+    pub following_siblings: bool,
+    /// All of the element's siblings, before and after it, and their descendants. The position
+    /// of an element among siblings that match the `of` selectors of `:nth-child()` changes
+    /// with the names of the siblings on either side.
+    pub siblings: bool,
+    /// Any element: `:has()` lets an element's style depend on its descendants and later
+    /// siblings.
+    pub document: bool,
+}
+
+impl Scope {
+    const SUBTREE: Scope = Scope { subtree: true, following_siblings: false, siblings: false, document: false };
+    const FOLLOWING_SIBLINGS: Scope = Scope { subtree: false, following_siblings: true, siblings: false, document: false };
+    const SIBLINGS: Scope = Scope { subtree: false, following_siblings: false, siblings: true, document: false };
+    const DOCUMENT: Scope = Scope { subtree: false, following_siblings: false, siblings: false, document: true };
+
+    pub fn union(self, other: Scope) -> Scope {
+        Scope {
+            subtree: self.subtree || other.subtree,
+            following_siblings: self.following_siblings || other.following_siblings,
+            siblings: self.siblings || other.siblings,
+            document: self.document || other.document,
+        }
+    }
+}
+
+/// The ids and classes that selectors depend on, and where, so that changing them only restyles
+/// the elements whose style might change.
+#[derive(Default)]
+pub(crate) struct InvalidationMap {
+    ids: HashMap<String, Scope>,
+    classes: HashMap<String, Scope>,
+    /// Some selector depends on the position of an element among its siblings, on its siblings
+    /// themselves, or on whether it's empty, so adding or removing nodes can restyle siblings and
+    /// the parent.
+    pub structural: bool,
+    /// Some selector uses `:has()`, so adding or removing nodes can restyle any element.
+    pub relational: bool,
+}
+
+impl InvalidationMap {
+    pub fn new<'a>(rules: impl IntoIterator<Item=&'a Rule>) -> InvalidationMap {
+        let mut map = InvalidationMap::default();
+        for rule in rules {
+            map.add_rule(rule);
+        }
+        map
+    }
+
+    /// Add the selectors of every style rule in a style sheet, including those in conditional
+    /// rules and imported style sheets, whether or not they currently apply.
+    pub fn add_stylesheet(&mut self, stylesheet: &Stylesheet) {
+        self.add_css_rules(&stylesheet.rules);
+    }
+
+    fn add_css_rules(&mut self, rules: &[CssRule]) {
+        for rule in rules {
+            match rule {
+                CssRule::Style(rule) => self.add_rule(rule),
+                CssRule::Media(media) => self.add_css_rules(&media.rules),
+                CssRule::Supports(supports) => self.add_css_rules(&supports.rules),
+                CssRule::Layer(layer) => self.add_css_rules(&layer.rules),
+                CssRule::Import(import) => if let Some(ref stylesheet) = import.stylesheet {
+                    self.add_stylesheet(stylesheet)
+                },
+                CssRule::LayerStatement(_) | CssRule::FontFace(_) => {}
+            }
+        }
+    }
+
+    fn add_rule(&mut self, rule: &Rule) {
+        for selector in &rule.selectors {
+            self.add_selector(selector, Scope::SUBTREE);
+        }
+    }
+
+    /// Add a selector whose subject is restyled within `scope` when one of its names changes.
+    fn add_selector(&mut self, selector: &Selector, scope: Scope) {
+        match *selector {
+            Selector::Simple(ref simple) => self.add_compound(simple, scope),
+            Selector::Complex(ref left, combinator, ref right) => {
+                self.add_compound(right, scope);
+                // An element matched by `left` is an ancestor of the subject, or a sibling before
+                // the subject or one of its ancestors. Inside `:has()` it could be anywhere, and
+                // inside the `of` selectors of `:nth-child()` it moves the siblings on both sides.
+                let left_scope = match combinator {
+                    _ if scope.document => Scope::DOCUMENT,
+                    Combinator::Descendant | Combinator::Child => Scope::SUBTREE,
+                    Combinator::NextSibling | Combinator::SubsequentSibling => {
+                        self.structural = true;
+                        if scope.siblings { Scope::SIBLINGS } else { Scope::FOLLOWING_SIBLINGS }
+                    }
+                };
+                self.add_selector(left, left_scope);
+            }
+        }
+    }
+
+    fn add_compound(&mut self, selector: &SimpleSelector, scope: Scope) {
+        if let Some(ref id) = selector.id {
+            let entry = self.ids.entry(id.clone()).or_default();
+            *entry = entry.union(scope);
+        }
+        for class in &selector.class {
+            let entry = self.classes.entry(class.clone()).or_default();
+            *entry = entry.union(scope);
+        }
+        for pseudo in &selector.pseudo_classes {
+            match *pseudo {
+                PseudoClass::Root => {}
+                PseudoClass::Not(ref list) | PseudoClass::Is(ref list) | PseudoClass::Where(ref list) => {
+                    for selector in list {
+                        self.add_selector(selector, scope);
+                    }
+                }
+                PseudoClass::NthChild(_, ref list) | PseudoClass::NthLastChild(_, ref list) => {
+                    self.structural = true;
+                    // Whether an element matches the `of` selectors changes the index of its
+                    // siblings, as well as its own.
+                    let of_scope = if scope.document { Scope::DOCUMENT } else { scope.union(Scope::SIBLINGS) };
+                    for selector in list {
+                        self.add_selector(selector, of_scope);
+                    }
+                }
+                PseudoClass::Has(ref list) => {
+                    self.relational = true;
+                    for relative in list {
+                        self.add_selector(&relative.selector, Scope::DOCUMENT);
+                    }
+                }
+                _ => self.structural = true,
+            }
+        }
+    }
+
+    pub fn id_scope(&self, id: &str) -> Scope {
+        self.ids.get(id).copied().unwrap_or_default()
+    }
+
+    pub fn class_scope(&self, class: &str) -> Scope {
+        self.classes.get(class).copied().unwrap_or_default()
+    }
+}
+
+/// The elements that changed style rules could apply to, found from the ids, classes and tag
+/// names of their selectors' subjects. Rules that change the order of the cascade, like `@layer`
+/// and `@import`, can affect every element.
+#[derive(Default)]
+pub(crate) struct Subjects {
+    ids: HashSet<String>,
+    classes: HashSet<String>,
+    tag_names: HashSet<String>,
+    /// Some subject has no id, class or tag name.
+    all: bool,
+}
+
+impl Subjects {
+    /// The subjects of the rules that differ between two versions of a style sheet. Rules are
+    /// compared by their CSS text, and those outside the longest common subsequence of the two
+    /// versions changed. A rule that moved changed too, since order decides ties in the cascade.
+    pub fn changed(old: &Stylesheet, new: &Stylesheet) -> Subjects {
+        let old_rules: Vec<String> = old.rules.iter().map(ToCss::to_css_string).collect();
+        let new_rules: Vec<String> = new.rules.iter().map(ToCss::to_css_string).collect();
+        let (old_unchanged, new_unchanged) = common_subsequence(&old_rules, &new_rules);
+        let mut subjects = Subjects::default();
+        for (rule, unchanged) in old.rules.iter().zip(old_unchanged).chain(new.rules.iter().zip(new_unchanged)) {
+            if !unchanged {
+                subjects.add_css_rules(std::slice::from_ref(rule));
+            }
+        }
+        subjects
+    }
+
+    fn add_css_rules(&mut self, rules: &[CssRule]) {
+        for rule in rules {
+            match rule {
+                CssRule::Style(rule) => for selector in &rule.selectors {
+                    let subject = selector.rightmost();
+                    if let Some(ref id) = subject.id {
+                        self.ids.insert(id.clone());
+                    } else if let Some(class) = subject.class.first() {
+                        self.classes.insert(class.clone());
+                    } else if let Some(ref tag_name) = subject.tag_name {
+                        self.tag_names.insert(tag_name.clone());
+                    } else {
+                        self.all = true;
+                    }
+                },
+                CssRule::Media(media) => self.add_css_rules(&media.rules),
+                CssRule::Supports(supports) => self.add_css_rules(&supports.rules),
+                CssRule::Import(_) | CssRule::Layer(_) | CssRule::LayerStatement(_) => self.all = true,
+                CssRule::FontFace(_) => {}
+            }
+        }
+    }
+
+    /// Every element may be affected.
+    pub fn all(&self) -> bool {
+        self.all
+    }
+
+    pub fn contains(&self, elem: &ElementData) -> bool {
+        self.all
+            || self.tag_names.contains(&elem.tag_name)
+            || elem.id().is_some_and(|id| self.ids.contains(id))
+            || elem.classes().iter().any(|class| self.classes.contains(*class))
+    }
+}
+
+/// Which items of `old` and of `new` are in a longest common subsequence of the two. Edits are
+/// usually small, so the common start and end are skipped first; if what's left is still too
+/// big to compare item by item, all of it counts as changed.
+fn common_subsequence(old: &[String], new: &[String]) -> (Vec<bool>, Vec<bool>) {
+    const MAX_TABLE_SIZE: usize = 1 << 20;
+    let prefix = old.iter().zip(new).take_while(|(old, new)| old == new).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let mut old_common = vec![true; old.len()];
+    let mut new_common = vec![true; new.len()];
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    old_common[prefix..old.len() - suffix].fill(false);
+    new_common[prefix..new.len() - suffix].fill(false);
+    if (old_middle.len() + 1) * (new_middle.len() + 1) > MAX_TABLE_SIZE {
+        return (old_common, new_common);
+    }
+
+    // lengths[i][j] is the length of the longest common subsequence of old_middle[i..] and
+    // new_middle[j..].
+    let columns = new_middle.len() + 1;
+    let mut lengths = vec![0u32; (old_middle.len() + 1) * columns];
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            lengths[i * columns + j] = if old_middle[i] == new_middle[j] {
+                lengths[(i + 1) * columns + j + 1] + 1
+            } else {
+                lengths[(i + 1) * columns + j].max(lengths[i * columns + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() && j < new_middle.len() {
+        if old_middle[i] == new_middle[j] {
+            old_common[prefix + i] = true;
+            new_common[prefix + j] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * columns + j] >= lengths[i * columns + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    (old_common, new_common)
+}
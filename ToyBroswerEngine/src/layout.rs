@@ -1,7 +1,10 @@
-use crate::css::Unit::Px;
-use crate::css::Value::{Keyword, Length};
-use crate::layout::BoxType::{AnonymousBlock, BlockNode, InlineNode};
-use crate::style::{StyleNode, Display};
+use crate::computed::{ComputedStyle, Display, DisplayInside, DisplayInternal, LengthPercentage, LengthPercentageOrAuto};
+use crate::computed::{ListStylePosition, Overflow, WhiteSpace};
+use crate::css::PseudoElement;
+use crate::inline::{self, ApproximateTextMeasurer, LineBox, TextMeasurer};
+use crate::layout::BoxType::{AnonymousBlock, AnonymousTable, AnonymousTableCell, AnonymousTableRow};
+use crate::layout::BoxType::{BlockNode, InlineBlockNode, InlineNode, MarkerNode};
+use crate::style::StyleNode;
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Dimensions {
     pub content: Rect,
@@ -29,7 +32,9 @@ pub struct EdgeSizes {
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
-    pub children: Vec<LayoutBox<'a>>
+    pub children: Vec<LayoutBox<'a>>,
+    /// The lines of an anonymous block, which its inline-level children are laid out in.
+    pub lines: Vec<LineBox<'a>>
 }
 
 impl<'a> LayoutBox<'a> {
@@ -38,19 +43,84 @@ impl<'a> LayoutBox<'a> {
             box_type,
             dimensions: Default::default(), // sets to 0.0
             children: Vec::new(),
+            lines: Vec::new(),
         }
     }
     fn get_style_node(&self) -> &'a StyleNode<'a> {
         match self.box_type {
-            BlockNode(node) | InlineNode(node) => node,
-            AnonymousBlock => panic!("Anonymous block box has no style node")
+            BlockNode(node) | InlineNode(node) | InlineBlockNode(node) | MarkerNode(node) => node,
+            AnonymousBlock | AnonymousTable { .. } | AnonymousTableRow | AnonymousTableCell => {
+                panic!("Anonymous box has no style node")
+            }
+        }
+    }
+
+    fn is_inline_level(&self) -> bool {
+        matches!(self.box_type, InlineNode(_) | InlineBlockNode(_) | AnonymousTable { inline: true })
+    }
+
+    fn table_role(&self) -> Option<TableRole> {
+        match self.box_type {
+            BlockNode(node) | InlineBlockNode(node) => match node.style.display {
+                Display::Box { inside: DisplayInside::Table, .. } => Some(TableRole::Table),
+                Display::Internal(internal) => Some(match internal {
+                    DisplayInternal::TableRowGroup | DisplayInternal::TableHeaderGroup
+                    | DisplayInternal::TableFooterGroup => TableRole::RowGroup,
+                    DisplayInternal::TableRow => TableRole::Row,
+                    DisplayInternal::TableCell => TableRole::Cell,
+                    DisplayInternal::TableColumnGroup => TableRole::ColumnGroup,
+                    DisplayInternal::TableColumn => TableRole::Column,
+                    DisplayInternal::TableCaption => TableRole::Caption,
+                }),
+                _ => None
+            },
+            AnonymousTable { .. } => Some(TableRole::Table),
+            AnonymousTableRow => Some(TableRole::Row),
+            AnonymousTableCell => Some(TableRole::Cell),
+            InlineNode(_) | MarkerNode(_) | AnonymousBlock => None,
         }
     }
 
+    /// Whether the box belongs directly in a table: a row group, row, column group, column or
+    /// caption.
+    fn is_proper_table_child(&self) -> bool {
+        matches!(self.table_role(), Some(TableRole::RowGroup | TableRole::Row | TableRole::ColumnGroup
+            | TableRole::Column | TableRole::Caption))
+    }
+
+    /// Whether the box is text with only white space that `white-space` collapses away.
+    fn is_collapsible_white_space(&self) -> bool {
+        let InlineNode(node) = self.box_type else { return false };
+        let Some(text) = node.text() else { return false };
+        text.chars().all(|c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c')) && match node.style.white_space {
+            WhiteSpace::Normal | WhiteSpace::Nowrap => true,
+            // Line breaks are kept.
+            WhiteSpace::PreLine => !text.contains('\n'),
+            WhiteSpace::Pre | WhiteSpace::PreWrap | WhiteSpace::BreakSpaces => false,
+        }
+    }
+
+    /// Add the boxes of this box's children, completing any tables among them, and putting
+    /// inline-level children of a box with block-level children in anonymous blocks. Anonymous
+    /// blocks that would only hold collapsible white space aren't generated.
+    fn add_children(&mut self, children: Vec<LayoutBox<'a>>) {
+        let inline = matches!(self.box_type, InlineNode(_));
+        for child in fix_up_tables(children, self.table_role(), inline) {
+            if inline || !child.is_inline_level() {
+                self.children.push(child);
+            } else {
+                self.get_inline_container().children.push(child);
+            }
+        }
+        self.children.retain(|child| !matches!(child.box_type, AnonymousBlock)
+            || !child.children.iter().all(LayoutBox::is_collapsible_white_space));
+    }
+
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
             InlineNode(_) | AnonymousBlock => self,
-            BlockNode(_) => {
+            BlockNode(_) | InlineBlockNode(_) | MarkerNode(_) | AnonymousTable { .. } | AnonymousTableRow
+            | AnonymousTableCell => {
                 // If we've just generated an anonymous block box, keep using it.
                 // Otherwise, create a new one.
                 match self.children.last() {
@@ -62,109 +132,234 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
-    fn layout(&mut self, containing_block: &mut Dimensions) {
+    /// Lay out the box and its descendants. `parent_style` is the style of the element the box is
+    /// in, which anonymous boxes lay out their content with.
+    pub(crate) fn layout(&mut self, containing_block: &mut Dimensions, parent_style: &ComputedStyle, text: &dyn TextMeasurer) {
         match self.box_type {
-            BlockNode(_) => self.layout_block(containing_block),
-            InlineNode(_) => {}, //todo
-            AnonymousBlock => {} //todo
+            BlockNode(_) => self.layout_block(containing_block, text),
+            InlineBlockNode(_) => self.layout_inline_block(containing_block, text),
+            MarkerNode(_) => self.layout_marker(containing_block, text),
+            InlineNode(_) => panic!("Inline boxes are laid out in the lines of their block"),
+            AnonymousBlock => inline::layout_inline_content(self, *containing_block, parent_style, text),
+            AnonymousTable { .. } | AnonymousTableRow | AnonymousTableCell => {
+                self.layout_anonymous_block(containing_block, parent_style, text)
+            }
         }
     }
     /// This function performs a single traversal of the layout tree, doing width
     /// calculations on the way down and height calculations on the way back up.
-    fn layout_block(&mut self, containing_block: &mut Dimensions) {
+    fn layout_block(&mut self, containing_block: &mut Dimensions, text: &dyn TextMeasurer) {
         // Child width can depend on parents width, so we need to calc
         // this box width before laying out its children
-        self.calculate_block_width(containing_block.clone());
+        self.calculate_block_width(*containing_block);
 
         // Determine where the box is located within its container
-        self.calculate_block_position(containing_block.clone());
+        self.calculate_block_position(*containing_block);
 
         // recursively lay out the children of this box
-        self.layout_block_children();
+        self.layout_block_children(&self.get_style_node().style, text);
 
         // Parent height can depend on the child height so calculate_hieght
         // must be called after the children are laid out
         self.calculate_block_height();
     }
 
+    /// Lay out a box that sits on a line as a unit, like an inline block, as a block whose auto
+    /// width shrinks to fit its content (CSS 2.1 §10.3.9).
+    fn layout_inline_block(&mut self, containing_block: &mut Dimensions, text: &dyn TextMeasurer) {
+        self.calculate_block_width(*containing_block);
+        let style = &self.get_style_node().style;
+        if let LengthPercentageOrAuto::Auto = style.width {
+            let percent_basis = containing_block.content.width;
+            let (min, max) = self.content_widths(style, text);
+            let d = &mut self.dimensions;
+            // Auto margins are zero, rather than taking up the space left over.
+            d.margin.left = style.margin.left.resolve(percent_basis).unwrap_or(0.0);
+            d.margin.right = style.margin.right.resolve(percent_basis).unwrap_or(0.0);
+            d.content.width = 0.0;
+            let available = percent_basis - d.margin_box().width;
+            d.content.width = available.max(min).min(max);
+        }
+        self.calculate_block_position(*containing_block);
+        self.layout_block_children(style, text);
+        self.calculate_block_height();
+    }
+
+    /// Lay out an outside marker like an inline block, hanging to the left of where its list
+    /// item's content starts.
+    fn layout_marker(&mut self, containing_block: &mut Dimensions, text: &dyn TextMeasurer) {
+        self.layout_inline_block(containing_block, text);
+        let margin_box = self.dimensions.margin_box();
+        self.translate(containing_block.content.x - margin_box.x - margin_box.width, 0.0);
+    }
+
+    /// Lay out an anonymous box, which has no margins, borders or padding, as a block that
+    /// fills its container's width. An inline-level anonymous table shrinks to fit instead.
+    fn layout_anonymous_block(&mut self, containing_block: &mut Dimensions, parent_style: &ComputedStyle,
+                              text: &dyn TextMeasurer) {
+        let mut width = containing_block.content.width;
+        if let AnonymousTable { inline: true } = self.box_type {
+            let (min, max) = self.content_widths(parent_style, text);
+            width = width.max(min).min(max);
+        }
+        let d = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = width;
+        self.layout_block_children(parent_style, text);
+    }
+
+    /// The min-content and max-content widths of the box's margin box, for shrinking it to fit
+    /// its content. Percentages count as zero.
+    pub(crate) fn preferred_widths(&self, parent_style: &ComputedStyle, text: &dyn TextMeasurer) -> (f32, f32) {
+        match self.box_type {
+            BlockNode(node) | InlineBlockNode(node) => {
+                let style = &node.style;
+                let edges = sum([style.margin.left.resolve(0.0).unwrap_or(0.0), style.margin.right.resolve(0.0).unwrap_or(0.0),
+                    style.border_width.left, style.border_width.right,
+                    style.padding.left.resolve(0.0), style.padding.right.resolve(0.0)].into_iter());
+                let (min, max) = match style.width {
+                    LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(width)) => (width, width),
+                    _ => self.content_widths(style, text),
+                };
+                (min + edges, max + edges)
+            }
+            InlineNode(_) => panic!("Inline boxes are measured with the lines of their block"),
+            // Outside markers hang outside their list item.
+            MarkerNode(_) => (0.0, 0.0),
+            AnonymousBlock => inline::inline_content_widths(self, parent_style, text),
+            AnonymousTable { .. } | AnonymousTableRow | AnonymousTableCell => self.content_widths(parent_style, text),
+        }
+    }
+
+    /// The widest of the preferred widths of the box's children. `style` is the style they're
+    /// in.
+    fn content_widths(&self, style: &ComputedStyle, text: &dyn TextMeasurer) -> (f32, f32) {
+        self.children.iter().map(|child| child.preferred_widths(style, text))
+            .fold((0.0, 0.0), |(min, max), (child_min, child_max)| (f32::max(min, child_min), f32::max(max, child_max)))
+    }
+
+    /// Move the box and everything in it.
+    pub(crate) fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content.x += dx;
+        self.dimensions.content.y += dy;
+        for line in &mut self.lines {
+            line.rect.x += dx;
+            line.rect.y += dy;
+            line.baseline += dy;
+            for fragment in &mut line.fragments {
+                fragment.rect.x += dx;
+                fragment.rect.y += dy;
+                fragment.baseline += dy;
+            }
+        }
+        for child in &mut self.children {
+            child.translate(dx, dy);
+        }
+    }
+
+    /// The baseline of the last line in the box, which an inline block sits on. An inline block
+    /// that doesn't let its content overflow has none, and sits on its bottom margin edge.
+    pub(crate) fn last_baseline(&self) -> Option<f32> {
+        if let InlineBlockNode(node) = self.box_type {
+            if node.style.overflow != Overflow::Visible {
+                return None;
+            }
+        }
+        self.lines.last().map(|line| line.baseline)
+            .or_else(|| self.children.iter().rev().find_map(LayoutBox::last_baseline))
+    }
+
     fn calculate_block_width(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
-        // width has an init value auto
-        let auto = Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or(auto.clone());
-        // margin, border and padding hav init val 0
-        let zero = Length(0.0, Px);
+        let style = &self.get_style_node().style;
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+        // Percentages are relative to the width of the containing block. `None` means auto.
+        let percent_basis = containing_block.content.width;
+        let mut width = style.width.resolve(percent_basis);
+        let mut margin_left = style.margin.left.resolve(percent_basis);
+        let mut margin_right = style.margin.right.resolve(percent_basis);
 
-        let border_left = style.lookup("border-left-width", "border-width", &zero);
-        let border_right = style.lookup("border-right-width", "border_width", &zero);
+        let border_left = style.border_width.left;
+        let border_right = style.border_width.right;
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+        let padding_left = style.padding.left.resolve(percent_basis);
+        let padding_right = style.padding.right.resolve(percent_basis);
 
-        let total: f32 = sum([&margin_left, &margin_right, &border_left, &border_right,
-            &padding_left, &padding_right, &width].iter().map(|v| v.to_px()));
+        // auto counts as 0 in the total
+        let total: f32 = sum([margin_left, margin_right, Some(border_left), Some(border_right),
+            Some(padding_left), Some(padding_right), width].iter().map(|v| v.unwrap_or(0.0)));
 
         // if width is not auto and the total is wider than the container, treat auto margin as 0
-        if width != auto && total > containing_block.content.width {
-            if margin_left == auto {
-                margin_left = Length(0.0, Px);
+        if width.is_some() && total > containing_block.content.width {
+            if margin_left.is_none() {
+                margin_left = Some(0.0);
             }
-            if margin_right == auto {
-                margin_right = Length(0.0, Px);
+            if margin_right.is_none() {
+                margin_right = Some(0.0);
             }
         }
 
         let underflow = containing_block.content.width - total; // the amount of space left in the container
         // https://www.w3.org/TR/CSS2/visudet.html#blockwidth - algorithm
-        match(width == auto, margin_left == auto, margin_right == auto) {
+        match (width, margin_left, margin_right) {
             // if the values are overconstrained, calc margin_right.
-            (false, false, false) => {
-                margin_right = Length(margin_right.to_px() + underflow, Px);
+            (Some(_), Some(_), Some(right)) => {
+                margin_right = Some(right + underflow);
             }
             // if exactly one size is auto its used value follows from the equality.
-            (false, false, true) => { margin_left = Length(underflow, Px); }
-            (false, true, false) => { margin_right = Length(underflow, Px); }
+            (Some(_), Some(_), None) => { margin_right = Some(underflow); }
+            (Some(_), None, Some(_)) => { margin_left = Some(underflow); }
             // if width is auto then all other auto values become 0
-            (true, _, _) => {
-                if margin_left == auto { margin_left = Length(0.0, Px); }
-                if margin_right == auto { margin_right = Length(0.0, Px); }
+            (None, _, _) => {
+                margin_left = Some(margin_left.unwrap_or(0.0));
+                let right = margin_right.unwrap_or(0.0);
 
                 if underflow >= 0.0 {
                     // Expand width to fill the underflow.
-                    width = Length(underflow, Px);
+                    width = Some(underflow);
+                    margin_right = Some(right);
                 } else {
                     // Width can't be negative. Adjust the right margin instead.
-                    width = Length(0.0, Px);
-                    margin_right = Length(margin_right.to_px() + underflow, Px);
+                    width = Some(0.0);
+                    margin_right = Some(right + underflow);
                 }
             }
             // if marin-left and margin-right are both auto their used values are equal
-            (false, true, true) => {
-                margin_left = Length(underflow / 2.0, Px);
-                margin_right = Length(underflow / 2.0, Px);
+            (Some(_), None, None) => {
+                margin_left = Some(underflow / 2.0);
+                margin_right = Some(underflow / 2.0);
             }
         }
+
+        let d = &mut self.dimensions;
+        d.content.width = width.unwrap_or(0.0);
+
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
+
+        d.border.left = border_left;
+        d.border.right = border_right;
+
+        d.margin.left = margin_left.unwrap_or(0.0);
+        d.margin.right = margin_right.unwrap_or(0.0);
     }
 
     pub fn calculate_block_position(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+        let style = &self.get_style_node().style;
         let d = &mut self.dimensions;
 
-        // margin border and padding have init value 0
-        let zero = Length(0.0, Px);
+        // Vertical percentages are also relative to the containing block's width.
+        let percent_basis = containing_block.content.width;
 
         // If margin to or margin bottom is auto the use value is zero
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        d.margin.top = style.margin.top.resolve(percent_basis).unwrap_or(0.0);
+        d.margin.bottom = style.margin.bottom.resolve(percent_basis).unwrap_or(0.0);
 
-        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
-        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
+        d.border.top = style.border_width.top;
+        d.border.bottom = style.border_width.bottom;
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+        d.padding.top = style.padding.top.resolve(percent_basis);
+        d.padding.bottom = style.padding.bottom.resolve(percent_basis);
 
         d.content.x = containing_block.content.x +
             d.margin.left + d.border.left + d.padding.left;
@@ -174,17 +369,21 @@ impl<'a> LayoutBox<'a> {
                         d.margin.top + d.border.top + d.padding.top;
     }
 
-    fn layout_block_children(&mut self) {
+    fn layout_block_children(&mut self, style: &ComputedStyle, text: &dyn TextMeasurer) {
         let d = &mut self.dimensions;
         for child in &mut self.children {
-            child.layout(d);
-            // Track the height so each child is laid out below the previous content.
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+            child.layout(d, style, text);
+            // Track the height so each child is laid out below the previous content. Outside
+            // markers take up no room.
+            if !matches!(child.box_type, MarkerNode(_)) {
+                d.content.height += child.dimensions.margin_box().height;
+            }
         }
     }
 
     fn calculate_block_height(&mut self) {
-        if let Some(Length(h, Px)) = self.get_style_node().value("height") {
+        // TODO: Percentage heights need the containing block's height.
+        if let LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(h)) = self.get_style_node().style.height {
             self.dimensions.content.height = h;
         }
     }
@@ -200,7 +399,7 @@ impl Dimensions {
         self.padding_box().expanded_by(self.border)
     }
     // the area covered by the content area plus its padding, borders and margin
-    fn margin_box(self) -> Rect {
+    pub(crate) fn margin_box(self) -> Rect {
         self.border_box().expanded_by(self.margin)
     }
 }
@@ -217,38 +416,178 @@ impl Rect {
 }
 
 pub enum BoxType<'a> {
+    /// A block-level box. Flex, grid and table boxes, and the parts of tables, are laid out as
+    /// blocks for now.
     BlockNode(&'a StyleNode<'a>),
+    /// An inline box, whose content is laid out in its parent's lines.
     InlineNode(&'a StyleNode<'a>),
+    /// An inline-level box that lays out its own content and sits on a line as a single unit,
+    /// like `inline-block`, `inline-flex` or `inline-table`.
+    InlineBlockNode(&'a StyleNode<'a>),
+    /// The `::marker` of a list item with `list-style-position: outside`, which lays out its own
+    /// content like an inline block, to the left of the list item's first line.
+    MarkerNode(&'a StyleNode<'a>),
+    /// Holds the inline-level children of a box that also has block-level children.
     AnonymousBlock,
+    /// Generated around table parts that aren't in a table. It's inline-level in an inline box.
+    AnonymousTable { inline: bool },
+    /// Generated around table cells that aren't in a row, and around the content of a table or
+    /// row group that isn't in one.
+    AnonymousTableRow,
+    /// Generated around the content of a table row that isn't in a cell.
+    AnonymousTableCell,
+}
+
+/// The part a box plays in a table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TableRole {
+    Table,
+    RowGroup,
+    Row,
+    Cell,
+    ColumnGroup,
+    Column,
+    Caption,
 }
 
 pub fn build_layout_tree<'a>(style_node: &'a StyleNode<'a>) -> LayoutBox<'a> {
     //create root box
-    let mut root = LayoutBox::new(match style_node.display() {
-        Display::Block => BlockNode(style_node),
-        Display::Inline => InlineNode(style_node),
-        Display::None => panic!("Root node has display: none")
+    let display = style_node.style.display;
+    let mut root = LayoutBox::new(match display {
+        Display::None | Display::Contents => panic!("Root node has display: {:?}", display),
+        _ if display.is_inline_level() && display.inside() == Some(DisplayInside::Flow) => InlineNode(style_node),
+        _ if display.is_inline_level() => InlineBlockNode(style_node),
+        _ => BlockNode(style_node),
     });
     //create descendant boxes
+    let mut children = Vec::new();
+    if let Some(marker) = outside_marker(style_node) {
+        let mut marker_box = LayoutBox::new(MarkerNode(marker));
+        let mut marker_children = Vec::new();
+        add_child_boxes(&mut marker_children, marker);
+        marker_box.add_children(marker_children);
+        children.push(marker_box);
+    }
+    add_child_boxes(&mut children, style_node);
+    root.add_children(children);
+    root
+}
+
+/// Build the boxes of a node's children. The children of a `display: contents` element go
+/// where its box would have been.
+fn add_child_boxes<'a>(boxes: &mut Vec<LayoutBox<'a>>, style_node: &'a StyleNode<'a>) {
+    let outside_marker = outside_marker(style_node);
     for child in &style_node.children {
-        match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root.get_inline_container().children.push(build_layout_tree(child)),
+        if outside_marker.is_some_and(|marker| std::ptr::eq(marker, child)) {
+            continue;
+        }
+        let display = child.style.display;
+        match display {
             Display::None => {}
+            Display::Contents => add_child_boxes(boxes, child),
+            _ if display.is_inline_level() && display.inside() == Some(DisplayInside::Flow) => {
+                boxes.extend(build_inline_boxes(child))
+            }
+            _ => boxes.push(build_layout_tree(child)),
+        }
+    }
+}
+
+/// The marker of a list item that goes outside its principal box, and gets a box of its own.
+/// Other markers are inline boxes at the start of their list item's content. So are the markers
+/// of inline list items, which have no block to hang outside of.
+fn outside_marker<'a>(style_node: &'a StyleNode<'a>) -> Option<&'a StyleNode<'a>> {
+    let marker = style_node.children.first().filter(|child| child.pseudo == Some(PseudoElement::Marker))?;
+    let display = style_node.style.display;
+    let inline = display.is_inline_level() && display.inside() == Some(DisplayInside::Flow);
+    (style_node.style.list_style_position == ListStylePosition::Outside && !inline).then_some(marker)
+}
+
+/// Build the boxes of an inline element. An inline box with block-level boxes inside is broken
+/// around them, so that the blocks become its siblings, each piece of it holding the inline
+/// content on its side (CSS 2.1 §9.2.1.1).
+fn build_inline_boxes<'a>(style_node: &'a StyleNode<'a>) -> Vec<LayoutBox<'a>> {
+    let mut children = Vec::new();
+    add_child_boxes(&mut children, style_node);
+    let mut boxes = vec![LayoutBox::new(InlineNode(style_node))];
+    for child in fix_up_tables(children, None, true) {
+        if child.is_inline_level() {
+            boxes.last_mut().unwrap().children.push(child);
+        } else {
+            boxes.push(child);
+            boxes.push(LayoutBox::new(InlineNode(style_node)));
+        }
+    }
+    boxes
+}
+
+/// Complete the structure of tables among the children of a box, following CSS 2.1 §17.2.1:
+/// children that can't be in the parent are removed, and anonymous tables, rows and cells are
+/// generated around table parts and content that are missing them.
+fn fix_up_tables<'a>(mut boxes: Vec<LayoutBox<'a>>, parent: Option<TableRole>, parent_inline: bool) -> Vec<LayoutBox<'a>> {
+    use TableRole::*;
+    // Columns have no content, and white space between table parts doesn't show.
+    match parent {
+        Some(Column) => boxes.clear(),
+        Some(ColumnGroup) => boxes.retain(|child| child.table_role() == Some(Column)),
+        Some(Table | RowGroup | Row) => boxes.retain(|child| !child.is_collapsible_white_space()),
+        _ => {}
+    }
+
+    // Wrap content that's directly in a table, row group or row.
+    match parent {
+        Some(Table) => wrap_runs(&mut boxes, |child| !child.is_proper_table_child(), || AnonymousTableRow),
+        Some(RowGroup) => wrap_runs(&mut boxes, |child| child.table_role() != Some(Row), || AnonymousTableRow),
+        Some(Row) => wrap_runs(&mut boxes, |child| child.table_role() != Some(Cell), || AnonymousTableCell),
+        _ => {}
+    }
+
+    // Wrap cells that aren't in a row, and table parts that aren't in a table.
+    if parent != Some(Row) {
+        wrap_runs(&mut boxes, |child| child.table_role() == Some(Cell), || AnonymousTableRow);
+    }
+    if !matches!(parent, Some(Table | RowGroup | Row | ColumnGroup | Column)) {
+        wrap_runs(&mut boxes, LayoutBox::is_proper_table_child, || AnonymousTable { inline: parent_inline });
+    }
+    boxes
+}
+
+/// Replace each run of consecutive boxes that `wrap` picks with an anonymous box around them.
+fn wrap_runs<'a>(boxes: &mut Vec<LayoutBox<'a>>, wrap: impl Fn(&LayoutBox<'a>) -> bool,
+                 box_type: impl Fn() -> BoxType<'a>) {
+    let mut wrapped = Vec::with_capacity(boxes.len());
+    let mut run = Vec::new();
+    let end_run = |run: &mut Vec<LayoutBox<'a>>, wrapped: &mut Vec<LayoutBox<'a>>| if !run.is_empty() {
+        let mut anonymous = LayoutBox::new(box_type());
+        anonymous.add_children(std::mem::take(run));
+        wrapped.push(anonymous);
+    };
+    for child in boxes.drain(..) {
+        if wrap(&child) {
+            run.push(child);
+        } else {
+            end_run(&mut run, &mut wrapped);
+            wrapped.push(child);
         }
     }
+    end_run(&mut run, &mut wrapped);
+    *boxes = wrapped;
+}
 
-    return root;
+pub fn layout_tree<'a>(node: &'a StyleNode<'a>, containing_block: Dimensions) -> LayoutBox<'a> {
+    layout_tree_with_measurer(node, containing_block, &ApproximateTextMeasurer)
 }
 
-pub fn layout_tree<'a>(node: &'a StyleNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
+/// Lay out a style tree, measuring text with `text`.
+pub fn layout_tree_with_measurer<'a>(node: &'a StyleNode<'a>, mut containing_block: Dimensions,
+                                     text: &dyn TextMeasurer) -> LayoutBox<'a> {
     // The layout algorithm expects the container height to start at 0.
     // TODO: Save the initial containing block height, for calculating percent heights.
     containing_block.content.height = 0.0;
 
     let mut root_box = build_layout_tree(node);
-    root_box.layout((&mut containing_block).into());
-    return root_box;
+    root_box.layout(&mut containing_block, &node.style, text);
+    root_box
 }
 
 fn sum<I>(iter: I) -> f32 where I: Iterator<Item=f32> {
@@ -256,3 +595,224 @@ fn sum<I>(iter: I) -> f32 where I: Iterator<Item=f32> {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::{self, Node, NodeType};
+    use crate::media::MediaEnvironment;
+    use crate::style::{self, Cascade};
+    use crate::{css, html};
+
+    /// Lay out a document styled with `css` in an 800x600 viewport, and check its boxes.
+    fn with_layout(html: &str, css: &str, check: impl FnOnce(&LayoutBox)) {
+        with_document_layout(&html::parse(html.to_string()), css, check)
+    }
+
+    fn with_document_layout(root: &Node, css: &str, check: impl FnOnce(&LayoutBox)) {
+        let stylesheet = css::parse(css.to_string());
+        let styles = style::style_tree(root, &Cascade::author(&stylesheet), &MediaEnvironment::screen(800.0, 600.0));
+        let mut viewport = Dimensions::default();
+        viewport.content.width = 800.0;
+        viewport.content.height = 600.0;
+        check(&layout_tree(&styles, viewport));
+    }
+
+    /// The box of the element with the given id.
+    fn find<'b, 'a>(layout_box: &'b LayoutBox<'a>, id: &str) -> Option<&'b LayoutBox<'a>> {
+        match layout_box.box_type {
+            BlockNode(node) | InlineNode(node) | InlineBlockNode(node) if node.pseudo.is_none()
+                && matches!(node.node.node_type, NodeType::Element(ref elem) if elem.id().is_some_and(|i| i == id)) => {
+                Some(layout_box)
+            }
+            _ => layout_box.children.iter().find_map(|child| find(child, id)),
+        }
+    }
+
+    #[test]
+    fn math_functions_resolve_against_the_containing_block() {
+        with_layout("<body><div id=\"calc\"></div>\
+                <div id=\"outer\"><div id=\"inner\"></div></div>\
+                <div id=\"min\"></div><div id=\"max\"></div><div id=\"clamp\"></div><div id=\"inverted\"></div></body>",
+            "body { margin: 0 } \
+             #calc { font-size: 10px; width: calc(100% - 2em) } \
+             #outer { width: 50%; padding-left: 10%; margin-left: calc(5% + 10px) } \
+             #inner { width: calc(50% + 10px); margin-left: calc(10% / 2) } \
+             #min { width: min(50%, 300px) } \
+             #max { width: max(10%, 100px) } \
+             #clamp { width: clamp(100px, 20%, 300px) } \
+             #inverted { width: clamp(500px, 50%, 300px) }",
+            |root| {
+                let dimensions = |id: &str| find(root, id).unwrap().dimensions;
+                assert_eq!(dimensions("calc").content.width, 780.0);
+                // Percentages in margins and padding are also relative to the containing block's
+                // width.
+                let outer = dimensions("outer");
+                assert_eq!(outer.content.width, 400.0);
+                assert_eq!(outer.padding.left, 80.0);
+                assert_eq!(outer.margin.left, 50.0);
+                let inner = dimensions("inner");
+                assert_eq!(inner.content.width, 210.0);
+                assert_eq!(inner.margin.left, 20.0);
+                assert_eq!(dimensions("min").content.width, 300.0);
+                assert_eq!(dimensions("max").content.width, 100.0);
+                assert_eq!(dimensions("clamp").content.width, 160.0);
+                // If the minimum of a clamp() is larger than its maximum, the minimum wins.
+                assert_eq!(dimensions("inverted").content.width, 500.0);
+            });
+    }
+
+    /// The text of a list item's marker, whether it's inside or outside.
+    fn marker_text<'a>(list_item: &LayoutBox<'a>) -> Option<&'a str> {
+        let marker = match list_item.children.first()?.box_type {
+            MarkerNode(marker) => marker,
+            AnonymousBlock => match list_item.children[0].children.first()?.box_type {
+                InlineNode(marker) if marker.pseudo == Some(PseudoElement::Marker) => marker,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        marker.children[0].text()
+    }
+
+    #[test]
+    fn list_items_get_markers_from_the_list_item_counter() {
+        with_layout("<body><ol><li id=\"a\">a</li><li id=\"b\">b<ol><li id=\"nested\">c</li></ol></li>\
+                <li id=\"c\">d</li></ol>\
+                <ul><li id=\"bullet\">e</li></ul>\
+                <ol id=\"roman\"><li id=\"four\">f</li></ol>\
+                <ul><li id=\"none\">g</li><li id=\"content\">h</li></ul></body>",
+            "#roman { counter-reset: list-item 3; list-style-type: upper-roman } \
+             #none { list-style-type: none } \
+             #content::marker { content: \"-> \" }",
+            |root| {
+                let text = |id: &str| marker_text(find(root, id).unwrap());
+                assert_eq!(text("a"), Some("1. "));
+                assert_eq!(text("b"), Some("2. "));
+                // Each list starts its own count.
+                assert_eq!(text("nested"), Some("1. "));
+                assert_eq!(text("c"), Some("3. "));
+                assert_eq!(text("bullet"), Some("\u{2022} "));
+                assert_eq!(text("four"), Some("IV. "));
+                assert_eq!(text("none"), None);
+                assert_eq!(text("content"), Some("-> "));
+            });
+    }
+
+    #[test]
+    fn outside_markers_hang_to_the_left_of_the_first_line() {
+        with_layout("<body><ol><li id=\"outside\">a</li><li id=\"inside\">b</li></ol></body>",
+            "#inside { list-style-position: inside }",
+            |root| {
+                let outside = find(root, "outside").unwrap();
+                let marker = &outside.children[0];
+                assert!(matches!(marker.box_type, MarkerNode(_)));
+                let margin_box = marker.dimensions.margin_box();
+                assert!(margin_box.width > 0.0);
+                assert_eq!(margin_box.x + margin_box.width, outside.dimensions.content.x);
+                assert_eq!(margin_box.y, outside.dimensions.content.y);
+                // The marker takes up no room, so the list item is one line high.
+                assert_eq!(outside.dimensions.content.height, outside.children[1].dimensions.content.height);
+
+                // An inside marker starts the list item's first line.
+                let inside = find(root, "inside").unwrap();
+                assert_eq!(inside.children.len(), 1);
+                let line = &inside.children[0].lines[0];
+                assert_eq!(line.fragments[0].style_node.pseudo, Some(PseudoElement::Marker));
+                assert_eq!(line.rect.x, inside.dimensions.content.x);
+            });
+    }
+
+    /// The box tree of the element with the given id, as its box types. Elements show as their
+    /// ids, or tag names if they have none, and text as `"text"`.
+    fn box_tree(root: &LayoutBox, id: &str) -> String {
+        fn describe(layout_box: &LayoutBox, out: &mut String) {
+            let name = |node: &StyleNode| match node.node.node_type {
+                NodeType::Element(ref elem) => elem.id().unwrap_or(&elem.tag_name).to_string(),
+                NodeType::Text(ref text) => format!("{:?}", text),
+            };
+            out.push_str(&match layout_box.box_type {
+                BlockNode(node) => format!("block {}", name(node)),
+                InlineNode(node) => format!("inline {}", name(node)),
+                InlineBlockNode(node) => format!("inline-block {}", name(node)),
+                MarkerNode(_) => "marker".to_string(),
+                AnonymousBlock => "anonymous block".to_string(),
+                AnonymousTable { inline } => format!("anonymous {}table", if inline { "inline " } else { "" }),
+                AnonymousTableRow => "anonymous row".to_string(),
+                AnonymousTableCell => "anonymous cell".to_string(),
+            });
+            if !layout_box.children.is_empty() {
+                out.push_str(" [");
+                for (index, child) in layout_box.children.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    describe(child, out);
+                }
+                out.push(']');
+            }
+        }
+        let mut out = String::new();
+        describe(find(root, id).unwrap(), &mut out);
+        out
+    }
+
+    #[test]
+    fn blocks_split_the_inline_boxes_they_are_in() {
+        with_layout("<body><p id=\"p\"><span id=\"span\">a<em id=\"em\">b<div id=\"div\">c</div>d</em>e</span></p></body>", "",
+            |root| {
+                assert_eq!(box_tree(root, "p"), "block p [\
+                    anonymous block [inline span [inline \"a\", inline em [inline \"b\"]]], \
+                    block div [anonymous block [inline \"c\"]], \
+                    anonymous block [inline span [inline em [inline \"d\"], inline \"e\"]]]");
+            });
+    }
+
+    #[test]
+    fn collapsible_white_space_between_blocks_has_no_box() {
+        // The HTML parser drops white space between tags, so build the document by hand.
+        let text = |text: &str| dom::text(text.to_string());
+        let elem = |tag_name: &str, id: &str, children: Vec<Node>| {
+            dom::elem(tag_name.to_string(), [("id".to_string(), id.to_string())].into(), children)
+        };
+        let root = elem("body", "body", vec![
+            elem("div", "normal", vec![text("\n  "), elem("p", "a", vec![text("a")]), text("\n  "),
+                                       elem("p", "b", vec![text("b")]), text("\n")]),
+            elem("div", "pre", vec![text("\n"), elem("p", "c", vec![text("c")])]),
+            elem("div", "pre-line", vec![text(" "), elem("p", "d", vec![text("d")]), text(" ")]),
+            elem("div", "inline", vec![elem("em", "e", vec![text("e")]), text(" "), elem("em", "f", vec![text("f")])]),
+        ]);
+        with_document_layout(&root, "#pre { white-space: pre } #pre-line { white-space: pre-line }",
+            |root| {
+                assert_eq!(box_tree(root, "normal"),
+                           "block normal [block a [anonymous block [inline \"a\"]], block b [anonymous block [inline \"b\"]]]");
+                // Preserved white space keeps its box.
+                assert_eq!(box_tree(root, "pre"), "block pre [anonymous block [inline \"\\n\"], \
+                    block c [anonymous block [inline \"c\"]]]");
+                // pre-line only keeps line breaks.
+                assert_eq!(box_tree(root, "pre-line"), "block pre-line [block d [anonymous block [inline \"d\"]]]");
+                // Between inline boxes, white space is a word separator.
+                assert_eq!(box_tree(root, "inline"), "block inline [anonymous block [\
+                    inline e [inline \"e\"], inline \" \", inline f [inline \"f\"]]]");
+            });
+    }
+
+    #[test]
+    fn table_parts_get_the_table_boxes_they_are_missing() {
+        with_layout("<body><div id=\"block\"><p id=\"cell\">a</p></div>\
+                <div id=\"inline\"><span id=\"span\">b<em id=\"inline-cell\">c</em></span></div>\
+                <div id=\"row\"><p id=\"row-cell\">d</p>e</div></body>",
+            "#cell, #inline-cell, #row-cell { display: table-cell } #row { display: table-row }",
+            |root| {
+                // A bare cell gets a row, and a table around that.
+                assert_eq!(box_tree(root, "block"), "block block [\
+                    anonymous table [anonymous row [block cell [anonymous block [inline \"a\"]]]]]");
+                // In an inline box the table is inline-level.
+                assert_eq!(box_tree(root, "inline"), "block inline [anonymous block [inline span [inline \"b\", \
+                    anonymous inline table [anonymous row [block inline-cell [anonymous block [inline \"c\"]]]]]]]");
+                // Content in a row that isn't in a cell gets one.
+                assert_eq!(box_tree(root, "row"), "block row [block row-cell [anonymous block [inline \"d\"]], \
+                    anonymous cell [anonymous block [inline \"e\"]]]");
+            });
+    }
+}
@@ -1,12 +1,43 @@
+// The crate is named after the project, not the Rust convention.
+#![allow(non_snake_case)]
+
 #[path = "./dom.rs"]
 pub mod dom;
 #[path = "./html.rs"]
 pub mod html;
 #[path = "./css.rs"]
 pub mod css;
+#[path = "./serialize.rs"]
+pub mod serialize;
+#[path = "./media.rs"]
+pub mod media;
+#[path = "./loader.rs"]
+pub mod loader;
+#[path = "./color.rs"]
+pub mod color;
+#[path = "./properties.rs"]
+pub mod properties;
+#[path = "./variables.rs"]
+pub mod variables;
+#[path = "./computed.rs"]
+pub mod computed;
+#[path = "./rule_map.rs"]
+pub mod rule_map;
+#[path = "./invalidation.rs"]
+pub mod invalidation;
+#[path = "./sharing.rs"]
+pub mod sharing;
 #[path = "./style.rs"]
 pub mod style;
+#[path = "./inspect.rs"]
+pub mod inspect;
+#[path = "./restyle.rs"]
+pub mod restyle;
+#[path = "./font.rs"]
+pub mod font;
+#[path = "./inline.rs"]
+pub mod inline;
 #[path = "./layout.rs"]
 pub mod layout;
 #[path = "./painting.rs"]
-pub mod painting;
\ No newline at end of file
+pub mod painting;
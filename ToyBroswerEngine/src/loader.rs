@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use crate::css::{self, CssRule, FontSource, MediaRule, Stylesheet};
+use crate::dom::{ElementData, Node, NodeType};
+
+/// Fetches the resources a document refers to, such as linked and imported style sheets.
+pub trait ResourceLoader {
+    /// Fetch the text of the resource at an absolute URL, or describe why it can't be fetched.
+    fn load(&self, url: &str) -> Result<String, String>;
+
+    /// Fetch a binary resource, such as a font.
+    fn load_bytes(&self, url: &str) -> Result<Vec<u8>, String> {
+        self.load(url).map(String::into_bytes)
+    }
+}
+
+/// Loads `file:` URLs, and URLs without a scheme, which are taken to be paths, from the file
+/// system.
+pub struct FileLoader;
+
+impl ResourceLoader for FileLoader {
+    fn load(&self, url: &str) -> Result<String, String> {
+        let path = file_path(url)?;
+        std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    fn load_bytes(&self, url: &str) -> Result<Vec<u8>, String> {
+        let path = file_path(url)?;
+        std::fs::read(path).map_err(|error| format!("{}: {}", path, error))
+    }
+}
+
+/// The path of a file that a `file:` URL, or a URL without a scheme, refers to.
+fn file_path(url: &str) -> Result<&str, String> {
+    Ok(match scheme(url) {
+        None => url,
+        Some(scheme) if scheme.eq_ignore_ascii_case("file") => {
+            // Skip the host of `file://host/path`, which is usually empty.
+            let rest = &url[scheme.len() + 1..];
+            match rest.strip_prefix("//") {
+                Some(authority) => &authority[authority.find('/').unwrap_or(authority.len())..],
+                None => rest,
+            }
+        }
+        Some(scheme) => return Err(format!("Unsupported URL scheme {}: in {}", scheme, url)),
+    })
+}
+
+/// Serves resources from memory, for tests and for documents that aren't on disk.
+#[derive(Default)]
+pub struct MemoryLoader {
+    resources: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        Default::default()
+    }
+
+    /// Add a resource, or replace the one at the same URL.
+    pub fn insert(&mut self, url: &str, text: &str) {
+        self.resources.insert(url.to_string(), text.to_string());
+    }
+}
+
+impl ResourceLoader for MemoryLoader {
+    fn load(&self, url: &str) -> Result<String, String> {
+        self.resources.get(url).cloned().ok_or_else(|| format!("{}: not found", url))
+    }
+}
+
+/// The scheme of an absolute URL, such as `file` or `https`. A single letter before the colon is
+/// a Windows drive letter, not a scheme.
+fn scheme(url: &str) -> Option<&str> {
+    let colon = url.find(':')?;
+    let scheme = &url[..colon];
+    let valid = scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+/// Resolve a URL relative to a base URL, which may itself be a plain file path.
+pub fn resolve_url(base: &str, url: &str) -> String {
+    if url.is_empty() {
+        return base.to_string();
+    }
+    if scheme(url).is_some() {
+        return url.to_string();
+    }
+
+    // Split the base into `scheme://authority` and a path without its query or fragment.
+    let (origin, base_path) = match scheme(base) {
+        Some(scheme) if base[scheme.len() + 1..].starts_with("//") => {
+            let authority_start = scheme.len() + 3;
+            let path_start = base[authority_start..].find('/').map_or(base.len(), |i| authority_start + i);
+            base.split_at(path_start)
+        }
+        Some(scheme) => base.split_at(scheme.len() + 1),
+        None => ("", base),
+    };
+    let mut base_path = &base_path[..base_path.find(['?', '#']).unwrap_or(base_path.len())];
+    if base_path.is_empty() && !origin.is_empty() {
+        base_path = "/";
+    }
+
+    if url.starts_with("//") {
+        return match scheme(base) {
+            Some(scheme) => format!("{}:{}", scheme, url),
+            None => url.to_string(),
+        };
+    }
+    // The query and fragment of the URL aren't part of its path.
+    let (url_path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    let path = if url_path.starts_with('/') {
+        url_path.to_string()
+    } else {
+        let directory = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}{}", directory, url_path)
+    };
+    format!("{}{}{}", origin, remove_dot_segments(&path), suffix)
+}
+
+/// Remove `.` and `..` segments from a path. A relative path keeps the `..` segments that go
+/// above its start.
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = path.split('/').peekable();
+    if absolute {
+        parts.next();
+    }
+    while let Some(segment) = parts.next() {
+        let last = parts.peek().is_none();
+        match segment {
+            "." => if last { segments.push("") },
+            ".." => {
+                match segments.last() {
+                    Some(&previous) if previous != ".." => { segments.pop(); }
+                    _ if absolute => {}
+                    _ => segments.push(".."),
+                }
+                if last {
+                    segments.push("");
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let path = segments.join("/");
+    if absolute { format!("/{}", path) } else { path }
+}
+
+/// Fetch and parse the style sheet at `url`, along with everything it imports.
+pub fn load_stylesheet(url: &str, loader: &dyn ResourceLoader) -> Result<Stylesheet, String> {
+    let mut stylesheet = css::parse(loader.load(url)?);
+    load_imports(&mut stylesheet, url, loader);
+    Ok(stylesheet)
+}
+
+/// Fetch the style sheets that a style sheet parsed from `base_url` imports, recursively.
+/// Imports that fail to load, or that import a style sheet that is already being loaded, are
+/// left empty and reported in the style sheet's warnings. The font URLs of `@font-face` rules
+/// are made absolute, since they're relative to the style sheet they're in.
+pub fn load_imports(stylesheet: &mut Stylesheet, base_url: &str, loader: &dyn ResourceLoader) {
+    resolve_font_urls(&mut stylesheet.rules, base_url);
+    let mut loading = vec![base_url.to_string()];
+    let mut warnings = Vec::new();
+    load_imports_in(&mut stylesheet.rules, base_url, loader, &mut loading, &mut warnings);
+    stylesheet.warnings.extend(warnings);
+}
+
+fn load_imports_in(rules: &mut [CssRule], base_url: &str, loader: &dyn ResourceLoader,
+                   loading: &mut Vec<String>, warnings: &mut Vec<String>) {
+    for rule in rules {
+        let CssRule::Import(import) = rule else { continue };
+        let url = resolve_url(base_url, &import.url);
+        if loading.contains(&url) {
+            warnings.push(format!("Ignored @import of {}, which imports itself", url));
+            continue;
+        }
+        let mut imported = match loader.load(&url) {
+            Ok(source) => css::parse(source),
+            Err(error) => {
+                warnings.push(format!("Failed to load @import: {}", error));
+                continue;
+            }
+        };
+        resolve_font_urls(&mut imported.rules, &url);
+        loading.push(url.clone());
+        load_imports_in(&mut imported.rules, &url, loader, loading, warnings);
+        loading.pop();
+        warnings.extend(imported.warnings.drain(..).map(|warning| format!("{}: {}", url, warning)));
+        import.stylesheet = Some(Box::new(imported));
+    }
+}
+
+fn resolve_font_urls(rules: &mut [CssRule], base_url: &str) {
+    for rule in rules {
+        match rule {
+            CssRule::FontFace(font_face) => for source in &mut font_face.sources {
+                if let FontSource::Url(url, _) = source {
+                    *url = resolve_url(base_url, url);
+                }
+            },
+            CssRule::Media(media_rule) => resolve_font_urls(&mut media_rule.rules, base_url),
+            CssRule::Supports(supports) => resolve_font_urls(&mut supports.rules, base_url),
+            CssRule::Layer(layer) => resolve_font_urls(&mut layer.rules, base_url),
+            _ => {}
+        }
+    }
+}
+
+/// The document's base URL: the `href` of its first `<base>` element, resolved against the
+/// document's own URL, or the document URL if there is none.
+pub fn base_url(root: &Node, document_url: &str) -> String {
+    let base = elements(root).find(|elem| elem.tag_name == "base" && elem.attributes.contains_key("href"));
+    match base {
+        Some(base) => resolve_url(document_url, &base.attributes["href"]),
+        None => document_url.to_string(),
+    }
+}
+
+/// Load the style sheets of the document's `<link rel="stylesheet" href>` elements, in document
+/// order. A link with a `media` attribute only applies when its media queries match. Links that
+/// fail to load give an empty style sheet with a warning.
+pub fn linked_stylesheets(root: &Node, document_url: &str, loader: &dyn ResourceLoader) -> Vec<Stylesheet> {
+    let base = base_url(root, document_url);
+    elements(root)
+        .filter(|elem| is_stylesheet_link(elem))
+        .map(|link| {
+            let url = resolve_url(&base, link.attributes["href"].trim());
+            let mut stylesheet = match load_stylesheet(&url, loader) {
+                Ok(stylesheet) => stylesheet,
+                Err(error) => Stylesheet {
+                    rules: Vec::new(),
+                    warnings: vec![format!("Failed to load style sheet: {}", error)],
+                },
+            };
+            if let Some(media) = link.attributes.get("media") {
+                let rules = std::mem::take(&mut stylesheet.rules);
+                stylesheet.rules = vec![CssRule::Media(MediaRule { queries: css::parse_media_queries(media), rules })];
+            }
+            stylesheet
+        })
+        .collect()
+}
+
+fn is_stylesheet_link(elem: &ElementData) -> bool {
+    let rel = elem.attributes.get("rel").map_or("", |rel| rel);
+    elem.tag_name == "link"
+        && rel.split_ascii_whitespace().any(|keyword| keyword.eq_ignore_ascii_case("stylesheet"))
+        && elem.attributes.get("href").is_some_and(|href| !href.trim().is_empty())
+}
+
+/// Every element in the tree, in document order.
+fn elements(root: &Node) -> impl Iterator<Item=&ElementData> {
+    let mut stack = vec![root];
+    std::iter::from_fn(move || {
+        while let Some(node) = stack.pop() {
+            stack.extend(node.children.iter().rev());
+            if let NodeType::Element(ref elem) = node.node_type {
+                return Some(elem);
+            }
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    fn import(stylesheet: &Stylesheet, index: usize) -> &css::ImportRule {
+        match &stylesheet.rules[index] {
+            CssRule::Import(import) => import,
+            rule => panic!("Expected an @import, got {:?}", rule),
+        }
+    }
+
+    #[test]
+    fn resolve_url_removes_dot_segments() {
+        let base = "http://example.com/a/b/page.html";
+        assert_eq!(resolve_url(base, "style.css"), "http://example.com/a/b/style.css");
+        assert_eq!(resolve_url(base, "./style.css"), "http://example.com/a/b/style.css");
+        assert_eq!(resolve_url(base, "../style.css"), "http://example.com/a/style.css");
+        assert_eq!(resolve_url(base, "../../../../style.css"), "http://example.com/style.css");
+        assert_eq!(resolve_url(base, "c/./d/../e.css"), "http://example.com/a/b/c/e.css");
+        assert_eq!(resolve_url(base, ".."), "http://example.com/a/");
+        assert_eq!(resolve_url(base, "/style.css?v=1#top"), "http://example.com/style.css?v=1#top");
+        assert_eq!(resolve_url("http://example.com", "style.css"), "http://example.com/style.css");
+        assert_eq!(resolve_url("http://example.com/page?a/b", "style.css"), "http://example.com/style.css");
+        assert_eq!(resolve_url(base, ""), base);
+
+        // Relative file paths keep the `..` segments that go above their start.
+        assert_eq!(resolve_url("pages/index.html", "../css/site.css"), "css/site.css");
+        assert_eq!(resolve_url("index.html", "../site.css"), "../site.css");
+        assert_eq!(resolve_url("/pages/index.html", "../../site.css"), "/site.css");
+    }
+
+    #[test]
+    fn resolve_url_handles_schemes() {
+        let base = "https://example.com/a/page.html";
+        assert_eq!(resolve_url(base, "file:///tmp/style.css"), "file:///tmp/style.css");
+        assert_eq!(resolve_url(base, "data:text/css,p{}"), "data:text/css,p{}");
+        assert_eq!(resolve_url(base, "//cdn.example.com/style.css"), "https://cdn.example.com/style.css");
+        assert_eq!(resolve_url("file:///home/user/page.html", "style.css"), "file:///home/user/style.css");
+        assert_eq!(resolve_url("file:/home/user/page.html", "../style.css"), "file:/home/style.css");
+        // A drive letter isn't a scheme.
+        assert_eq!(resolve_url(base, "C:/style.css"), "https://example.com/a/C:/style.css");
+
+        assert_eq!(file_path("file:///tmp/style.css"), Ok("/tmp/style.css"));
+        assert_eq!(file_path("file://localhost/tmp/style.css"), Ok("/tmp/style.css"));
+        assert_eq!(file_path("style.css"), Ok("style.css"));
+        assert!(file_path("https://example.com/style.css").is_err());
+    }
+
+    #[test]
+    fn imports_are_loaded_recursively() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("http://example.com/css/main.css", "@import url(\"parts/a.css\"); p { color: red }");
+        loader.insert("http://example.com/css/parts/a.css", "@import \"../b.css\"; a { color: blue }");
+        loader.insert("http://example.com/css/b.css", "b { color: green }");
+
+        let stylesheet = load_stylesheet("http://example.com/css/main.css", &loader).unwrap();
+        assert!(stylesheet.warnings.is_empty(), "{:?}", stylesheet.warnings);
+        let a = import(&stylesheet, 0).stylesheet.as_ref().unwrap();
+        let b = import(a, 0).stylesheet.as_ref().unwrap();
+        assert!(matches!(b.rules[..], [CssRule::Style(_)]));
+    }
+
+    #[test]
+    fn cyclic_imports_are_ignored() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.css", "@import \"b.css\"; a { color: red }");
+        loader.insert("b.css", "@import \"./a.css\"; @import \"b.css\"; b { color: blue }");
+
+        let stylesheet = load_stylesheet("a.css", &loader).unwrap();
+        assert_eq!(stylesheet.warnings, [
+            "Ignored @import of a.css, which imports itself",
+            "Ignored @import of b.css, which imports itself",
+        ]);
+        let b = import(&stylesheet, 0).stylesheet.as_ref().unwrap();
+        assert!(import(b, 0).stylesheet.is_none());
+        assert!(import(b, 1).stylesheet.is_none());
+    }
+
+    #[test]
+    fn missing_imports_are_reported() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.css", "@import \"missing.css\";");
+
+        let stylesheet = load_stylesheet("a.css", &loader).unwrap();
+        assert_eq!(stylesheet.warnings, ["Failed to load @import: missing.css: not found"]);
+        assert!(import(&stylesheet, 0).stylesheet.is_none());
+        assert!(load_stylesheet("missing.css", &loader).is_err());
+    }
+
+    #[test]
+    fn links_resolve_against_the_base_element() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("http://cdn.example.com/css/site.css", "p { color: red }");
+        let root = html::parse("<html><head>\
+            <base href=\"http://cdn.example.com/css/\">\
+            <link rel=\"stylesheet\" href=\"site.css\">\
+            <link rel=\"icon\" href=\"favicon.ico\">\
+            <link rel=\"stylesheet\" href=\"missing.css\">\
+            </head></html>".to_string());
+
+        assert_eq!(base_url(&root, "http://example.com/index.html"), "http://cdn.example.com/css/");
+        let stylesheets = linked_stylesheets(&root, "http://example.com/index.html", &loader);
+        assert_eq!(stylesheets.len(), 2);
+        assert!(stylesheets[0].warnings.is_empty());
+        assert!(matches!(stylesheets[0].rules[..], [CssRule::Style(_)]));
+        assert_eq!(stylesheets[1].warnings,
+                   ["Failed to load style sheet: http://cdn.example.com/css/missing.css: not found"]);
+    }
+
+    #[test]
+    fn link_media_wraps_the_stylesheet() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("print.css", "p { color: red } @media (min-width: 600px) { p { color: blue } }");
+        let root = html::parse("<html><head>\
+            <link rel=\"stylesheet\" href=\"print.css\" media=\"print, (max-width: 400px)\">\
+            <link rel=\"Alternate StyleSheet\" href=\"print.css\">\
+            </head></html>".to_string());
+
+        let stylesheets = linked_stylesheets(&root, "index.html", &loader);
+        assert_eq!(stylesheets.len(), 2);
+        let [CssRule::Media(media_rule)] = &stylesheets[0].rules[..] else {
+            panic!("Expected one @media rule, got {:?}", stylesheets[0].rules);
+        };
+        assert_eq!(media_rule.queries, css::parse_media_queries("print, (max-width: 400px)"));
+        assert!(matches!(media_rule.rules[..], [CssRule::Style(_), CssRule::Media(_)]));
+        // Without a media attribute the rules aren't wrapped.
+        assert!(matches!(stylesheets[1].rules[..], [CssRule::Style(_), CssRule::Media(_)]));
+    }
+}
@@ -1,13 +1,14 @@
-use std::fs::File;
+// The crate is named after the project, not the Rust convention.
+#![allow(non_snake_case)]
+
 use std::env;
 use std::default::Default;
 use std::path::Path;
-use getopts::{Options, Matches};
+use getopts::Options;
 use image::{ImageBuffer, Rgba};
 use image::DynamicImage::ImageRgba8;
 
-mod lib;
-use lib::*;
+use ToyBroswerEngine::*;
 
 
 fn main() {
@@ -16,7 +17,15 @@ fn main() {
     let mut opts = Options::new();
     opts.optopt("h", "html", "HTML document", "FILENAME");
     opts.optopt("c", "css", "CSS stylesheet", "FILENAME");
+    opts.optopt("u", "user-css", "User CSS stylesheet", "FILENAME");
     opts.optopt("o", "output", "Output file", "FILENAME");
+    opts.optopt("w", "width", "Viewport width in px (default 800)", "PX");
+    opts.optopt("", "height", "Viewport height in px (default 600)", "PX");
+    opts.optflag("", "print", "Style the document for print media");
+    opts.optflag("", "dark", "Prefer a dark color scheme");
+    opts.optflag("", "bundled-font", "Draw all text in the bundled font, ignoring installed fonts");
+    opts.optflag("", "style-stats", "Print style sharing statistics");
+    opts.optopt("", "explain", "Print where the styles of the matching elements come from", "SELECTOR");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -24,19 +33,26 @@ fn main() {
     };
 
     // Read input files:
-    let read_source = |arg_filename: Option<String>, default_filename: &str| {
-        let path = match arg_filename {
-            Some(filename) => filename,
-            None => default_filename.to_string(),
-        };
-        std::fs::read_to_string(Path::new(&path)).unwrap()
-    };
-    let html = read_source(matches.opt_str("h"), "examples/test.html");
-    let css  = read_source(matches.opt_str("c"), "examples/test.css");
+    let read_source = |path: &str| std::fs::read_to_string(Path::new(path)).unwrap();
+    let html_path = matches.opt_str("h").unwrap_or_else(|| "examples/test.html".to_string());
+    let css_path = matches.opt_str("c").unwrap_or_else(|| "examples/test.css".to_string());
+    let html = read_source(&html_path);
+    let css  = read_source(&css_path);
     println!("css -> {}", &css);
-    // Since we don't have an actual window, hard-code the "viewport" size.
+    // Since we don't have an actual window, the "viewport" size comes from the command line.
+    let size = |name: &str, default: f32| match matches.opt_str(name) {
+        Some(px) => px.parse::<f32>().unwrap_or_else(|_| panic!("Invalid --{} {}", name, px)),
+        None => default,
+    };
+    let mut media = media::MediaEnvironment::screen(size("width", 800.0), size("height", 600.0));
+    if matches.opt_present("print") {
+        media.media_type = media::MediaType::Print;
+    }
+    if matches.opt_present("dark") {
+        media.color_scheme = media::ColorScheme::Dark;
+    }
     let initial_containing_block = layout::Dimensions {
-        content: layout::Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 },
+        content: layout::Rect { x: 0.0, y: 0.0, width: media.width, height: media.height },
         padding: Default::default(),
         border: Default::default(),
         margin: Default::default(),
@@ -44,21 +60,61 @@ fn main() {
 
     // Parsing and rendering:
     let root_node = html::parse(html);
-    let stylesheet = css::parse(css);
-    let style_root = style::style_tree(&root_node, &stylesheet);
-    let layout_root = layout::layout_tree(&style_root, initial_containing_block);
-    let canvas = painting::paint(&layout_root, initial_containing_block.content);
+    let mut stylesheet = css::parse(css);
+    loader::load_imports(&mut stylesheet, &css_path, &loader::FileLoader);
+    let user_stylesheet = matches.opt_str("u").map(|path| loader::load_stylesheet(&path, &loader::FileLoader)
+        .unwrap_or_else(|error| panic!("{}", error)));
+    // Style sheets linked from the document come before the one given on the command line.
+    let linked_stylesheets = loader::linked_stylesheets(&root_node, &html_path, &loader::FileLoader);
+    let warnings = linked_stylesheets.iter().chain([&stylesheet]).chain(user_stylesheet.as_ref())
+        .flat_map(|sheet| &sheet.warnings);
+    for warning in warnings {
+        eprintln!("CSS warning: {}", warning);
+    }
+    let mut cascade = style::Cascade::default();
+    cascade.author.extend(&linked_stylesheets);
+    cascade.author.push(&stylesheet);
+    cascade.user.extend(user_stylesheet.as_ref());
+    let (style_root, style_stats) = style::style_tree_with_stats(&root_node, &cascade, &media);
+    if matches.opt_present("style-stats") {
+        eprintln!("Styled {} elements, {} of {} sharing candidates from the cache ({:.1}%)", style_stats.elements,
+                  style_stats.shared, style_stats.sharing_candidates, style_stats.hit_rate() * 100.0);
+    }
+    if let Some(selector) = matches.opt_str("explain") {
+        let inspector = inspect::Inspector::new(&root_node, &cascade, &media);
+        let nodes = inspector.query_selector_all(&selector)
+            .unwrap_or_else(|error| panic!("Invalid --explain {}: {}", selector, error));
+        for node in nodes {
+            let pseudo_elements = [css::PseudoElement::Marker, css::PseudoElement::Before, css::PseudoElement::After];
+            for pseudo in [None].into_iter().chain(pseudo_elements.map(Some)) {
+                let Some(style) = style_root.find(node, pseudo) else { continue };
+                let dom::NodeType::Element(ref elem) = node.node_type else { continue };
+                let suffix = pseudo.map_or(String::new(), |pseudo| format!("::{:?}", pseudo).to_lowercase());
+                println!("<{}>{}\n{}", elem.tag_name, suffix, inspector.explain(style));
+            }
+        }
+    }
+    let mut fonts = if matches.opt_present("bundled-font") {
+        font::FontContext::bundled()
+    } else {
+        font::FontContext::system()
+    };
+    for warning in fonts.add_font_faces(&cascade.font_faces(&media), &loader::FileLoader) {
+        eprintln!("Font warning: {}", warning);
+    }
+    let layout_root = layout::layout_tree_with_measurer(&style_root, initial_containing_block, &fonts);
+    let canvas = painting::paint_with_fonts(&layout_root, initial_containing_block.content, &fonts);
 
     // Create the output file:
     let filename = matches.opt_str("o").unwrap_or_else(|| "output.png".to_string());
-    let filePath = Path::new(&filename);
+    let file_path = Path::new(&filename);
 
     // Save an image:
     let (w, h) = (canvas.width as u32, canvas.height as u32);
     let buffer: Vec<Rgba<u8>> = unsafe { std::mem::transmute(canvas.pixels) };
     let img = ImageBuffer::from_fn(w, h, |x, y| buffer[(y * w + x) as usize]);
 
-    let result = ImageRgba8(img).save(filePath);
+    let result = ImageRgba8(img).save(file_path);
     match result {
         Ok(_) => println!("Saved output as {}", filename),
         Err(_) => println!("Error saving output as {}", filename)
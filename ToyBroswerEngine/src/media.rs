@@ -0,0 +1,232 @@
+use crate::css::{Unit, Value};
+
+/// The device a document is rendered for, which media queries are evaluated against.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaEnvironment {
+    pub media_type: MediaType,
+    /// The size of the viewport in px.
+    pub width: f32,
+    pub height: f32,
+    /// Device pixels per px.
+    pub resolution: f32,
+    pub color_scheme: ColorScheme,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaType {
+    Screen,
+    Print,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+/// One query of a comma-separated media query list, e.g. `not print and (min-width: 600px)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaQuery {
+    /// The query started with `not`.
+    pub negated: bool,
+    /// `None` if the query has no media type, which is the same as `all`.
+    pub media_type: Option<String>,
+    pub condition: Option<MediaCondition>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaCondition {
+    Feature(MediaFeature),
+    Not(Box<MediaCondition>),
+    And(Vec<MediaCondition>),
+    Or(Vec<MediaCondition>),
+}
+
+/// A test of a media feature such as `(min-width: 600px)` or `(400px <= width < 800px)`.
+/// `min-` and `max-` prefixes and range syntax are both turned into comparisons of the feature
+/// with each value. With no comparisons, the feature is tested in a boolean context.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaFeature {
+    pub name: String,
+    pub comparisons: Vec<(Comparison, Value)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// The font size that `em` and `rem` refer to in media queries.
+const INITIAL_FONT_SIZE: f32 = 16.0;
+
+impl MediaEnvironment {
+    /// A light-mode screen with the given viewport size, at one device pixel per px.
+    pub fn screen(width: f32, height: f32) -> MediaEnvironment {
+        MediaEnvironment {
+            media_type: MediaType::Screen,
+            width,
+            height,
+            resolution: 1.0,
+            color_scheme: ColorScheme::Light,
+        }
+    }
+}
+
+/// Whether a media query list matches. An empty list matches everything.
+pub fn matches(queries: &[MediaQuery], env: &MediaEnvironment) -> bool {
+    queries.is_empty() || queries.iter().any(|query| query.matches(env))
+}
+
+impl MediaQuery {
+    pub fn matches(&self, env: &MediaEnvironment) -> bool {
+        let type_matches = match self.media_type.as_deref() {
+            None | Some("all") => true,
+            Some("screen") => env.media_type == MediaType::Screen,
+            Some("print") => env.media_type == MediaType::Print,
+            Some(_) => false,
+        };
+        let matches = type_matches && self.condition.as_ref().is_none_or(|condition| condition.matches(env));
+        matches != self.negated
+    }
+}
+
+impl MediaCondition {
+    pub fn matches(&self, env: &MediaEnvironment) -> bool {
+        match self {
+            MediaCondition::Feature(feature) => feature.matches(env),
+            MediaCondition::Not(condition) => !condition.matches(env),
+            MediaCondition::And(conditions) => conditions.iter().all(|condition| condition.matches(env)),
+            MediaCondition::Or(conditions) => conditions.iter().any(|condition| condition.matches(env)),
+        }
+    }
+}
+
+/// The value of a media feature in the current environment.
+enum FeatureValue {
+    Number(f32),
+    Keyword(&'static str),
+}
+
+impl MediaFeature {
+    /// Evaluate the feature. Unknown features never match.
+    pub fn matches(&self, env: &MediaEnvironment) -> bool {
+        let actual = match &*self.name {
+            "width" => FeatureValue::Number(env.width),
+            "height" => FeatureValue::Number(env.height),
+            "aspect-ratio" => FeatureValue::Number(env.width / env.height),
+            "resolution" => FeatureValue::Number(env.resolution),
+            "orientation" => FeatureValue::Keyword(if env.height >= env.width { "portrait" } else { "landscape" }),
+            "prefers-color-scheme" => FeatureValue::Keyword(match env.color_scheme {
+                ColorScheme::Light => "light",
+                ColorScheme::Dark => "dark",
+            }),
+            // Bits per color component, and the number of entries in a color lookup table.
+            "color" => FeatureValue::Number(8.0),
+            "color-index" | "monochrome" | "grid" => FeatureValue::Number(0.0),
+            _ => return false
+        };
+        if self.comparisons.is_empty() {
+            return match actual {
+                FeatureValue::Number(n) => n != 0.0,
+                FeatureValue::Keyword(keyword) => keyword != "none",
+            };
+        }
+        self.comparisons.iter().all(|(comparison, value)| match (&actual, value) {
+            (&FeatureValue::Number(actual), value) => match number(value, env) {
+                Some(expected) => compare(actual, *comparison, expected),
+                None => false
+            },
+            (FeatureValue::Keyword(actual), Value::Keyword(expected)) => {
+                *comparison == Comparison::Equal && actual == expected
+            }
+            _ => false
+        })
+    }
+}
+
+/// A media feature value as a number, with lengths in px.
+fn number(value: &Value, env: &MediaEnvironment) -> Option<f32> {
+    match *value {
+        Value::Number(n) => Some(n),
+        Value::Length(f, Unit::Em | Unit::Rem) => Some(f * INITIAL_FONT_SIZE),
+        // Without a font, approximate the x-height and the width of "0" as half an em.
+        Value::Length(f, Unit::Ex | Unit::Ch) => Some(f * INITIAL_FONT_SIZE / 2.0),
+        Value::Length(f, Unit::Vw) => Some(f * env.width / 100.0),
+        Value::Length(f, Unit::Vh) => Some(f * env.height / 100.0),
+        Value::Length(f, Unit::Vmin) => Some(f * env.width.min(env.height) / 100.0),
+        Value::Length(f, Unit::Vmax) => Some(f * env.width.max(env.height) / 100.0),
+        Value::Length(f, unit) => unit.absolute_px().map(|px| f * px),
+        _ => None
+    }
+}
+
+fn compare(actual: f32, comparison: Comparison, expected: f32) -> bool {
+    match comparison {
+        Comparison::Less => actual < expected,
+        Comparison::LessOrEqual => actual <= expected,
+        Comparison::Equal => actual == expected,
+        Comparison::GreaterOrEqual => actual >= expected,
+        Comparison::Greater => actual > expected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+
+    /// Whether a media query list matches in the environment.
+    fn query(source: &str, env: &MediaEnvironment) -> bool {
+        matches(&css::parse_media_queries(source), env)
+    }
+
+    #[test]
+    fn range_syntax_compares_with_each_bound() {
+        let env = MediaEnvironment::screen(800.0, 600.0);
+        assert!(query("(width >= 800px)", &env));
+        assert!(!query("(width > 800px)", &env));
+        assert!(query("(600px < width)", &env));
+        assert!(query("(400px <= width < 801px)", &env));
+        assert!(!query("(400px <= width < 800px)", &env));
+        assert!(query("(799px < width <= 50em)", &env));
+        assert!(query("(min-width: 800px) and (max-width: 800px)", &env));
+        assert!(!query("(max-height: 599px)", &env));
+    }
+
+    #[test]
+    fn not_negates_and_or_matches_either() {
+        let env = MediaEnvironment::screen(800.0, 600.0);
+        let print = MediaEnvironment { media_type: MediaType::Print, ..env.clone() };
+        assert!(query("not print", &env));
+        assert!(!query("not screen", &env));
+        assert!(!query("not print", &print));
+        // A leading `not` negates the whole query.
+        assert!(query("not screen and (min-width: 900px)", &env));
+        assert!(!query("not screen and (orientation: landscape)", &env));
+        assert!(query("(not (monochrome))", &env));
+        assert!(!query("not all and (not (monochrome))", &env));
+
+        assert!(query("(min-width: 900px) or (orientation: landscape)", &env));
+        assert!(!query("(min-width: 900px) or (orientation: portrait)", &env));
+        assert!(query("((min-width: 900px) or (prefers-color-scheme: light)) and (height: 600px)", &env));
+        assert!(!query("((min-width: 900px) or (prefers-color-scheme: dark)) and (height: 600px)", &env));
+        // A query list matches if any of its queries do.
+        assert!(query("print, (max-width: 800px)", &env));
+        assert!(query("print, (min-width: 900px)", &print));
+        assert!(!query("screen, (min-width: 900px)", &print));
+    }
+
+    #[test]
+    fn viewport_units_are_relative_to_the_viewport() {
+        let env = MediaEnvironment::screen(800.0, 600.0);
+        assert!(query("(width: 100vw)", &env));
+        assert!(query("(height: 100vh)", &env));
+        assert!(query("(min-width: 50vh)", &env));
+        assert!(!query("(min-width: 101vw)", &env));
+        assert!(query("(width > 100vmin) and (width = 100vmax)", &env));
+    }
+}
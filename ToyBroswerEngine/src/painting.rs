@@ -1,83 +1,138 @@
-use std::iter::repeat;
-use crate::css::{Color, Value};
-use crate::layout::{BoxType, LayoutBox, Rect};
-use crate::layout::BoxType::{BlockNode, InlineNode};
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::computed::{ComputedStyle, Sides};
+use crate::css::Color;
+use crate::font::{FontContext, FontId, GlyphRun, PathCommand};
+use crate::inline::{Fragment, FragmentKind};
+use crate::layout::{BoxType, EdgeSizes, LayoutBox, Rect};
+use crate::layout::BoxType::{BlockNode, InlineBlockNode, InlineNode, MarkerNode};
 use num_traits::Float;
 type DisplayList = Vec<DisplayCommand>;
 
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
-    // add more commands
+    /// A run of glyphs in one font, with its origin at the start of its baseline.
+    Text { run: GlyphRun, x: f32, y: f32, color: Color },
 }
 
-fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
+fn build_display_list(layout_root: &LayoutBox, fonts: &FontContext) -> DisplayList {
     let mut list = Vec::new();
-    render_layout_box(&mut list, layout_root);
-    return list;
+    render_layout_box(&mut list, layout_root, fonts);
+    list
 }
 
-fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
+fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox, fonts: &FontContext) {
     render_background(list, layout_box);
     render_borders(list, layout_box);
-    // tod render text
+    for fragment in layout_box.lines.iter().flat_map(|line| &line.fragments) {
+        render_fragment(list, fragment, fonts);
+    }
     for child in &layout_box.children {
-        render_layout_box(list, child);
+        render_layout_box(list, child, fonts);
+    }
+}
+
+/// Inline boxes come before their content on a line, so their backgrounds are under its text.
+fn render_fragment(list: &mut DisplayList, fragment: &Fragment, fonts: &FontContext) {
+    let style = &fragment.style_node.style;
+    match fragment.kind {
+        FragmentKind::InlineBox { first, last } => {
+            if style.background_color.a > 0 {
+                list.push(DisplayCommand::SolidColor(style.background_color, fragment.rect));
+            }
+            // A box broken across lines has no border where it's broken.
+            let border = EdgeSizes {
+                left: if first { style.border_width.left } else { 0.0 },
+                right: if last { style.border_width.right } else { 0.0 },
+                top: style.border_width.top,
+                bottom: style.border_width.bottom,
+            };
+            push_borders(list, style.border_color, fragment.rect, border);
+        }
+        FragmentKind::Text(ref text) => render_text(list, text, style, fragment.rect.x, fragment.baseline, fonts),
+    }
+}
+
+/// Shape text and push its runs. Letter spacing goes after each character and word spacing after
+/// each space, which shaping leaves out.
+fn render_text(list: &mut DisplayList, text: &str, style: &ComputedStyle, x: f32, baseline: f32, fonts: &FontContext) {
+    let shaped = fonts.shape(text, style);
+    let mut clusters: Vec<usize> = shaped.runs.iter().flat_map(|run| &run.glyphs).map(|glyph| glyph.cluster).collect();
+    clusters.sort_unstable();
+    clusters.dedup();
+    let spacing = |cluster: usize| {
+        let end = clusters.iter().find(|&&start| start > cluster).copied().unwrap_or(text.len());
+        let chars = &text[cluster..end];
+        style.letter_spacing * chars.chars().count() as f32 + style.word_spacing * chars.matches(' ').count() as f32
+    };
+    let (mut extra, mut previous) = (0.0, None);
+    for mut run in shaped.runs {
+        if style.letter_spacing != 0.0 || style.word_spacing != 0.0 {
+            for glyph in &mut run.glyphs {
+                if let Some(cluster) = previous.filter(|&cluster| cluster != glyph.cluster) {
+                    extra += spacing(cluster);
+                }
+                previous = Some(glyph.cluster);
+                glyph.x += extra;
+            }
+        }
+        list.push(DisplayCommand::Text { run, x, y: baseline, color: style.color });
     }
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color|
-        list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box())));
+    if let Some(style) = get_style(layout_box) {
+        if style.background_color.a > 0 {
+            list.push(DisplayCommand::SolidColor(style.background_color, layout_box.dimensions.border_box()));
+        }
+    }
 }
 
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+fn get_style<'a>(layout_box: &LayoutBox<'a>) -> Option<&'a ComputedStyle> {
     match layout_box.box_type {
-        BlockNode(style) | InlineNode(style) => match style.value(name) {
-            Some(Value::ColorValue(color)) => Some(color),
-            _ => None
-        },
-        BoxType::AnonymousBlock => None
+        BlockNode(style) | InlineNode(style) | InlineBlockNode(style) | MarkerNode(style) => Some(&style.style),
+        BoxType::AnonymousBlock | BoxType::AnonymousTable { .. } | BoxType::AnonymousTableRow
+        | BoxType::AnonymousTableCell => None
     }
 }
 
 pub fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
-        Some(color) => color,
-        _ => return// bail out if no border color specified
-    };
+    let Some(style) = get_style(layout_box) else { return };
     let d = &layout_box.dimensions;
-    let border_box = d.border_box();
+    push_borders(list, style.border_color, d.border_box(), d.border);
+}
 
+fn push_borders(list: &mut DisplayList, colors: Sides<Color>, border_box: Rect, border: EdgeSizes) {
     // left border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.left, Rect {
         x: border_box.x,
         y: border_box.y,
-        width: d.border.left,
+        width: border.left,
         height: border_box.height
     }));
 
     // right border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x + border_box.width - d.border.right,
+    list.push(DisplayCommand::SolidColor(colors.right, Rect {
+        x: border_box.x + border_box.width - border.right,
         y: border_box.y,
-        width: d.border.right,
+        width: border.right,
         height: border_box.height
     }));
 
     // top border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.top, Rect {
         x: border_box.x,
         y: border_box.y,
         width: border_box.width,
-        height: d.border.top
+        height: border.top
     }));
 
     // bottom border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.bottom, Rect {
         x: border_box.x,
-        y: border_box.y,
-        width: d.border.left,
-        height: d.border.top
+        y: border_box.y + border_box.height - border.bottom,
+        width: border_box.width,
+        height: border.bottom
     }));
 }
 
@@ -85,18 +140,39 @@ pub struct Canvas {
     pub pixels: Vec<Color>,
     pub width: usize,
     pub height: usize,
+    /// Glyphs that have been rasterized, since text uses the same few glyphs over and over.
+    glyphs: HashMap<GlyphKey, Option<Rc<GlyphMask>>>,
+}
+
+/// A glyph in a font at a size and weight, offset by a fraction of a pixel to the right.
+type GlyphKey = (FontId, u16, u32, u16, u8);
+
+/// How many positions between pixels glyphs are rasterized at, so that text isn't pulled onto
+/// whole pixels.
+const SUBPIXEL_POSITIONS: f32 = 4.0;
+
+/// How much of each pixel a glyph covers, from 0 to 255. `left` and `top` are where the mask goes
+/// from the pixel the glyph's origin is in.
+struct GlyphMask {
+    left: i32,
+    top: i32,
+    width: usize,
+    height: usize,
+    coverage: Vec<u8>,
 }
+
 impl Canvas {
     fn new(width: usize, height: usize) -> Canvas {
         let white = Color { r: 255, g: 255, b: 255, a: 255 };
-        return Canvas {
-            pixels: repeat(white).take(width * height).collect(),
+        Canvas {
+            pixels: std::iter::repeat_n(white, width * height).collect(),
             width,
             height,
+            glyphs: HashMap::new(),
         }
     }
 
-    pub fn paint_item(&mut self, item: &DisplayCommand) {
+    pub fn paint_item(&mut self, item: &DisplayCommand, fonts: &FontContext) {
         match item {
             &DisplayCommand::SolidColor(color, rect) => {
                 // clip the rectangle to the convasa boundaries
@@ -104,24 +180,232 @@ impl Canvas {
                 let y0 = rect.y.clamp(0.0, self.width as f32) as usize;
                 let x1 = (rect.x + rect.width).clamp(0.0, self.width as f32) as usize;
                 let y1 = (rect.y + rect.height).clamp(0.0, self.height as f32) as usize;
-                for y in (y0 .. y1) {
-                    for x in (x0 .. x1) {
-                        // TODO: alpha compositing with existing pixel
-                        self.pixels[x + y * self.width] = color;
+                for y in y0 .. y1 {
+                    for x in x0 .. x1 {
+                        let pixel = &mut self.pixels[x + y * self.width];
+                        *pixel = blend(color, *pixel);
+                    }
+                }
+            }
+            DisplayCommand::Text { run, x, y, color } => {
+                for glyph in &run.glyphs {
+                    let glyph_x = x + glyph.x;
+                    let glyph_y = (y + glyph.y).round();
+                    let subpixel = ((glyph_x - glyph_x.floor()) * SUBPIXEL_POSITIONS).floor() as u8;
+                    let key = (run.font, glyph.id, run.size.to_bits(), run.weight, subpixel);
+                    let mask = self.glyphs.entry(key).or_insert_with(|| {
+                        let outline = fonts.glyph_outline(run, glyph.id)?;
+                        rasterize(&outline, subpixel as f32 / SUBPIXEL_POSITIONS).map(Rc::new)
+                    }).clone();
+                    if let Some(mask) = mask {
+                        self.fill_mask(&mask, glyph_x.floor() as i32, glyph_y as i32, *color);
                     }
                 }
             }
         }
     }
+
+    /// Fill the pixels a glyph covers with a color, with the coverage as extra transparency.
+    fn fill_mask(&mut self, mask: &GlyphMask, x: i32, y: i32, color: Color) {
+        for row in 0..mask.height {
+            let py = y + mask.top + row as i32;
+            if py < 0 || py >= self.height as i32 {
+                continue;
+            }
+            for column in 0..mask.width {
+                let px = x + mask.left + column as i32;
+                let coverage = mask.coverage[row * mask.width + column];
+                if px < 0 || px >= self.width as i32 || coverage == 0 {
+                    continue;
+                }
+                let alpha = (color.a as u32 * coverage as u32 + 127) / 255;
+                let pixel = &mut self.pixels[px as usize + py as usize * self.width];
+                *pixel = blend(Color { a: alpha as u8, ..color }, *pixel);
+            }
+        }
+    }
+}
+
+/// A point in px, with y going down.
+type Point = (f32, f32);
+
+/// Rasterize a glyph outline, moved `dx` px to the right, into a mask of how much of each pixel
+/// it covers. Curves are flattened into lines, and each line adds the signed area it covers to
+/// the pixels it crosses and everything to the right of them, so summing along a row gives the
+/// coverage. Returns None for an outline with nothing to fill.
+fn rasterize(outline: &[PathCommand], dx: f32) -> Option<GlyphMask> {
+    let mut lines: Vec<(Point, Point)> = Vec::new();
+    let (mut start, mut current) = ((0.0, 0.0), (0.0, 0.0));
+    for &command in outline {
+        match command {
+            PathCommand::MoveTo(x, y) => {
+                if current != start {
+                    lines.push((current, start));
+                }
+                start = (x + dx, y);
+                current = start;
+            }
+            PathCommand::LineTo(x, y) => {
+                lines.push((current, (x + dx, y)));
+                current = (x + dx, y);
+            }
+            PathCommand::QuadTo(x1, y1, x, y) => {
+                let (p0, p1, p2) = (current, (x1 + dx, y1), (x + dx, y));
+                flatten(&mut lines, p0, distance(p0, p1) + distance(p1, p2), |t| {
+                    let u = 1.0 - t;
+                    (u * u * p0.0 + 2.0 * u * t * p1.0 + t * t * p2.0,
+                     u * u * p0.1 + 2.0 * u * t * p1.1 + t * t * p2.1)
+                });
+                current = p2;
+            }
+            PathCommand::CurveTo(x1, y1, x2, y2, x, y) => {
+                let (p0, p1, p2, p3) = (current, (x1 + dx, y1), (x2 + dx, y2), (x + dx, y));
+                flatten(&mut lines, p0, distance(p0, p1) + distance(p1, p2) + distance(p2, p3), |t| {
+                    let u = 1.0 - t;
+                    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                    (a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0, a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1)
+                });
+                current = p3;
+            }
+            PathCommand::Close => {
+                if current != start {
+                    lines.push((current, start));
+                }
+                current = start;
+            }
+        }
+    }
+    if current != start {
+        lines.push((current, start));
+    }
+
+    let points = lines.iter().flat_map(|&(p0, p1)| [p0, p1]);
+    let (min_x, min_y, max_x, max_y) = points.fold((f32::MAX, f32::MAX, f32::MIN, f32::MIN), |(x0, y0, x1, y1), (x, y)| {
+        (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+    });
+    if lines.is_empty() || min_x >= max_x || min_y >= max_y {
+        return None;
+    }
+    let (left, top) = (min_x.floor() as i32, min_y.floor() as i32);
+    let width = (max_x.ceil() as i32 - left) as usize;
+    let height = (max_y.ceil() as i32 - top) as usize;
+    // Two spare columns for the area that lines carry past the right edge of the glyph.
+    let stride = width + 2;
+    let mut area = vec![0.0f32; stride * height];
+    for &((x0, y0), (x1, y1)) in &lines {
+        accumulate_line(&mut area, stride, (x0 - left as f32, y0 - top as f32), (x1 - left as f32, y1 - top as f32));
+    }
+
+    let mut coverage = Vec::with_capacity(width * height);
+    for row in area.chunks(stride) {
+        let mut sum = 0.0;
+        for &a in &row[..width] {
+            sum += a;
+            coverage.push((sum.abs().min(1.0) * 255.0).round() as u8);
+        }
+    }
+    Some(GlyphMask { left, top, width, height, coverage })
+}
+
+/// Approximate a curve from `p0` by lines, about one for every 2px of its control polygon.
+fn flatten(lines: &mut Vec<(Point, Point)>, p0: Point, length: f32, point: impl Fn(f32) -> Point) {
+    let segments = (length / 2.0).ceil().clamp(1.0, 64.0) as usize;
+    let mut previous = p0;
+    for i in 1..=segments {
+        let next = point(i as f32 / segments as f32);
+        lines.push((previous, next));
+        previous = next;
+    }
+}
+
+fn distance(p0: Point, p1: Point) -> f32 {
+    ((p1.0 - p0.0).powi(2) + (p1.1 - p0.1).powi(2)).sqrt()
+}
+
+/// Add the signed area a line covers to the pixels of each row it crosses. The area to the right
+/// of the line in a row is the row height it spans, so the part of it in the pixels the line
+/// crosses goes in those pixels and the rest in the pixel after them, for the row's sum to carry.
+fn accumulate_line(area: &mut [f32], stride: usize, p0: Point, p1: Point) {
+    if p0.1 == p1.1 {
+        return;
+    }
+    let (direction, p0, p1) = if p0.1 < p1.1 { (1.0, p0, p1) } else { (-1.0, p1, p0) };
+    let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+    let mut x = p0.0;
+    let rows = area.len() / stride;
+    for row in (p0.1.floor() as usize)..(p1.1.ceil() as usize).min(rows) {
+        let dy = ((row + 1) as f32).min(p1.1) - (row as f32).max(p0.1);
+        let next_x = x + dxdy * dy;
+        let d = dy * direction;
+        let (x0, x1) = if x < next_x { (x, next_x) } else { (next_x, x) };
+        let line = &mut area[row * stride..(row + 1) * stride];
+        let (x0_floor, x1_ceil) = (x0.floor(), x1.ceil());
+        let (x0i, x1i) = (x0_floor as usize, x1_ceil as usize);
+        if x1i <= x0i + 1 {
+            // Within one pixel: the part of the pixel right of the line's middle is covered.
+            let middle = (x + next_x) / 2.0 - x0_floor;
+            line[x0i] += d * (1.0 - middle);
+            line[x0i + 1] += d * middle;
+        } else {
+            // Across several pixels: each gets the area of the trapezoid the line cuts from it.
+            let s = 1.0 / (x1 - x0);
+            let x0f = x0 - x0_floor;
+            let first = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+            let x1f = x1 - x1_ceil + 1.0;
+            let last = 0.5 * s * x1f * x1f;
+            line[x0i] += d * first;
+            if x1i == x0i + 2 {
+                line[x0i + 1] += d * (1.0 - first - last);
+            } else {
+                let second = s * (1.5 - x0f);
+                line[x0i + 1] += d * (second - first);
+                for pixel in &mut line[x0i + 2..x1i - 1] {
+                    *pixel += d * s;
+                }
+                let before_last = second + (x1i - x0i - 3) as f32 * s;
+                line[x1i - 1] += d * (1.0 - before_last - last);
+            }
+            line[x1i] += d * last;
+        }
+        x = next_x;
+    }
+}
+
+/// Composite `source` over `backdrop` with the "source-over" operator.
+fn blend(source: Color, backdrop: Color) -> Color {
+    match source.a {
+        255 => source,
+        0 => backdrop,
+        _ => {
+            let alpha = source.a as f32 / 255.0;
+            let backdrop_alpha = backdrop.a as f32 / 255.0 * (1.0 - alpha);
+            let out_alpha = alpha + backdrop_alpha;
+            let channel = |s: u8, b: u8| {
+                ((s as f32 * alpha + b as f32 * backdrop_alpha) / out_alpha).round() as u8
+            };
+            Color {
+                r: channel(source.r, backdrop.r),
+                g: channel(source.g, backdrop.g),
+                b: channel(source.b, backdrop.b),
+                a: (out_alpha * 255.0).round() as u8,
+            }
+        }
+    }
 }
 
+/// Paint a layout tree, drawing its text in the bundled font.
 pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
-    let display_list = build_display_list(layout_root);
+    paint_with_fonts(layout_root, bounds, &FontContext::bundled())
+}
+
+/// Paint a layout tree, drawing its text in the fonts it was laid out with.
+pub fn paint_with_fonts(layout_root: &LayoutBox, bounds: Rect, fonts: &FontContext) -> Canvas {
+    let display_list = build_display_list(layout_root, fonts);
     let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
     for item in display_list {
-        canvas.paint_item(&item);
+        canvas.paint_item(&item, fonts);
     }
-    return canvas;
+    canvas
 }
 
 //Helper
@@ -130,4 +414,38 @@ pub trait FloatClamp: Float {
         self.max(lower).min(upper)
     }
 }
-impl<T: Float> FloatClamp for T {}
\ No newline at end of file
+impl<T: Float> FloatClamp for T {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::PathCommand::{Close, LineTo, MoveTo};
+
+    /// A square outline with its top left corner at `(x, y)`, drawn clockwise or anticlockwise.
+    fn square(x: f32, y: f32, size: f32, clockwise: bool) -> Vec<PathCommand> {
+        let (right, bottom) = (x + size, y + size);
+        if clockwise {
+            vec![MoveTo(x, y), LineTo(right, y), LineTo(right, bottom), LineTo(x, bottom), Close]
+        } else {
+            vec![MoveTo(x, y), LineTo(x, bottom), LineTo(right, bottom), LineTo(right, y), Close]
+        }
+    }
+
+    #[test]
+    fn rasterize_covers_a_square() {
+        // A square on pixel boundaries covers its pixels fully, whichever way it's drawn.
+        for clockwise in [true, false] {
+            let mask = rasterize(&square(1.0, 2.0, 2.0, clockwise), 0.0).unwrap();
+            assert_eq!((mask.left, mask.top, mask.width, mask.height), (1, 2, 2, 2));
+            assert_eq!(mask.coverage, [255; 4]);
+        }
+
+        // Moved half a pixel to the right and down, it covers half of the pixels on its edges and
+        // a quarter of those in its corners.
+        let mask = rasterize(&square(0.0, 0.5, 2.0, true), 0.5).unwrap();
+        assert_eq!((mask.left, mask.top, mask.width, mask.height), (0, 0, 3, 3));
+        assert_eq!(mask.coverage, [64, 128, 64, 128, 255, 128, 64, 128, 64]);
+
+        // An outline without area has nothing to fill.
+        assert!(rasterize(&[MoveTo(0.0, 0.0), LineTo(4.0, 0.0), Close], 0.0).is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use crate::computed;
+use crate::css::{self, Unit, Value};
+use ValueType::{Any, Color, Integer, Keyword, Length, LengthPercentage, Number, NumberLengthPercentage, NumberPercentage};
+
+/// A longhand property the style system knows about.
+pub struct Property {
+    pub name: &'static str,
+    /// Whether an element takes the property from its parent when nothing in the cascade sets it.
+    pub inherited: bool,
+    /// The initial value, as CSS source.
+    pub initial: &'static str,
+    pub value_type: ValueType,
+}
+
+/// The values a property takes besides keywords. Which keywords it takes is up to the computed
+/// style.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueType {
+    /// Only keywords.
+    Keyword,
+    Length,
+    LengthPercentage,
+    Number,
+    Integer,
+    /// A number or a percentage, like `opacity`.
+    NumberPercentage,
+    /// A number, length or percentage, like `line-height`.
+    NumberLengthPercentage,
+    Color,
+    /// Anything the parser accepts, for properties with a grammar of their own.
+    Any,
+}
+
+const fn property(name: &'static str, inherited: bool, initial: &'static str, value_type: ValueType) -> Property {
+    Property { name, inherited, initial, value_type }
+}
+
+/// Every supported longhand. Shorthands are expanded by the parser, so they don't appear here.
+static PROPERTIES: &[Property] = &[
+    // Box generation and positioning
+    property("display", false, "inline", Keyword),
+    property("position", false, "static", Keyword),
+    property("float", false, "none", Keyword),
+    property("clear", false, "none", Keyword),
+    property("top", false, "auto", LengthPercentage),
+    property("right", false, "auto", LengthPercentage),
+    property("bottom", false, "auto", LengthPercentage),
+    property("left", false, "auto", LengthPercentage),
+    property("z-index", false, "auto", Integer),
+    property("overflow", false, "visible", Keyword),
+    property("visibility", true, "visible", Keyword),
+    property("opacity", false, "1", NumberPercentage),
+
+    // Box model
+    property("box-sizing", false, "content-box", Keyword),
+    property("width", false, "auto", LengthPercentage),
+    property("height", false, "auto", LengthPercentage),
+    property("min-width", false, "auto", LengthPercentage),
+    property("min-height", false, "auto", LengthPercentage),
+    property("max-width", false, "none", LengthPercentage),
+    property("max-height", false, "none", LengthPercentage),
+    property("margin-top", false, "0px", LengthPercentage),
+    property("margin-right", false, "0px", LengthPercentage),
+    property("margin-bottom", false, "0px", LengthPercentage),
+    property("margin-left", false, "0px", LengthPercentage),
+    property("padding-top", false, "0px", LengthPercentage),
+    property("padding-right", false, "0px", LengthPercentage),
+    property("padding-bottom", false, "0px", LengthPercentage),
+    property("padding-left", false, "0px", LengthPercentage),
+    property("border-top-width", false, "medium", Length),
+    property("border-right-width", false, "medium", Length),
+    property("border-bottom-width", false, "medium", Length),
+    property("border-left-width", false, "medium", Length),
+    property("border-top-style", false, "none", Keyword),
+    property("border-right-style", false, "none", Keyword),
+    property("border-bottom-style", false, "none", Keyword),
+    property("border-left-style", false, "none", Keyword),
+    property("border-top-color", false, "currentcolor", Color),
+    property("border-right-color", false, "currentcolor", Color),
+    property("border-bottom-color", false, "currentcolor", Color),
+    property("border-left-color", false, "currentcolor", Color),
+
+    // Backgrounds
+    property("background-color", false, "transparent", Color),
+    property("background-image", false, "none", Any),
+    property("background-repeat", false, "repeat", Any),
+    property("background-attachment", false, "scroll", Keyword),
+    property("background-position", false, "0% 0%", Any),
+
+    // Fonts and text
+    property("color", true, "black", Color),
+    property("font-family", true, "serif", Any),
+    property("font-size", true, "medium", LengthPercentage),
+    property("font-style", true, "normal", Keyword),
+    property("font-variant", true, "normal", Keyword),
+    property("font-weight", true, "normal", Number),
+    property("font-stretch", true, "normal", Any),
+    property("line-height", true, "normal", NumberLengthPercentage),
+    property("text-align", true, "start", Keyword),
+    property("text-indent", true, "0px", LengthPercentage),
+    property("text-transform", true, "none", Keyword),
+    property("text-decoration", false, "none", Any),
+    property("letter-spacing", true, "normal", Length),
+    property("word-spacing", true, "normal", Length),
+    property("white-space", true, "normal", Keyword),
+    property("vertical-align", false, "baseline", LengthPercentage),
+    property("direction", true, "ltr", Keyword),
+
+    // Lists and generated content
+    property("list-style-type", true, "disc", Any),
+    property("list-style-position", true, "outside", Keyword),
+    property("list-style-image", true, "none", Any),
+    property("quotes", true, "auto", Any),
+    property("content", false, "normal", Any),
+    property("counter-reset", false, "none", Any),
+    property("counter-increment", false, "none", Any),
+    property("counter-set", false, "none", Any),
+    property("cursor", true, "auto", Any),
+];
+
+/// All supported longhand properties.
+pub fn all() -> &'static [Property] {
+    PROPERTIES
+}
+
+/// Look up a property by name.
+pub fn lookup(name: &str) -> Option<&'static Property> {
+    static BY_NAME: OnceLock<HashMap<&str, &Property>> = OnceLock::new();
+    BY_NAME.get_or_init(|| PROPERTIES.iter().map(|property| (property.name, property)).collect())
+        .get(name).copied()
+}
+
+impl Property {
+    /// The parsed initial value.
+    pub fn initial_value(&self) -> &'static Value {
+        static INITIAL: OnceLock<HashMap<&str, Value>> = OnceLock::new();
+        &INITIAL.get_or_init(|| PROPERTIES.iter()
+            .map(|property| (property.name, css::parse_value(property.initial)))
+            .collect())[self.name]
+    }
+
+    /// Whether a value is valid for the property: a CSS-wide keyword, a keyword the computed
+    /// style understands, or a value of the property's type. Values that use `var()` are checked
+    /// once their variables are substituted.
+    pub fn accepts(&self, value: &Value) -> bool {
+        let expr_type = |expr: &css::MathExpr| match (expr.is_number(), expr.has_percentage()) {
+            (true, _) => Number,
+            (false, true) => LengthPercentage,
+            (false, false) => Length,
+        };
+        match (self.value_type, value) {
+            (_, Value::Keyword(keyword)) => css::CSS_WIDE_KEYWORDS.contains(&&**keyword)
+                || computed::supports_keyword(self.name, keyword),
+            (_, Value::Unparsed(_) | Value::PendingSubstitution(..)) => true,
+            // `display` also takes its outer and inner types as separate keywords.
+            (_, Value::List(_)) if self.name == "display" => computed::display(value).is_some(),
+            (Any, _) => true,
+            (Color, Value::ColorValue(_)) => true,
+            // A unitless zero is a valid length.
+            (Length | LengthPercentage, &Value::Number(n)) => n == 0.0,
+            (Number | NumberPercentage | NumberLengthPercentage, Value::Number(_)) => true,
+            (Integer, &Value::Number(n)) => n.fract() == 0.0,
+            (Length | NumberLengthPercentage, &Value::Length(_, unit)) if unit != Unit::Percent => true,
+            (LengthPercentage | NumberPercentage | NumberLengthPercentage, Value::Length(_, Unit::Percent)) => true,
+            (LengthPercentage, Value::Length(..)) => true,
+            (value_type, Value::Math(expr)) => matches!((value_type, expr_type(expr)),
+                (Length | LengthPercentage | NumberLengthPercentage, Length)
+                | (LengthPercentage | NumberLengthPercentage, LengthPercentage)
+                | (Number | NumberPercentage | NumberLengthPercentage | Integer, Number)),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use crate::computed::ComputedStyle;
+use crate::css::Stylesheet;
+use crate::dom::{ElementData, Node, NodeType};
+use crate::invalidation::{InvalidationMap, Scope, Subjects};
+use crate::media::MediaEnvironment;
+use crate::rule_map::RuleMap;
+use crate::style::{build_style_tree, Cascade, PseudoStyle, StyleData, StyleNode, StyleStats, Traversal};
+
+/// How much of the rendering a change invalidates, least first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum Damage {
+    #[default]
+    None,
+    /// Only colors and other painted properties changed, so the layout can be kept.
+    Repaint,
+    /// Boxes may have moved or changed size.
+    Relayout,
+}
+
+/// The damage from a node's style changing from `old` to `new`.
+fn style_damage(old: &ComputedStyle, new: &ComputedStyle) -> Damage {
+    if old == new {
+        return Damage::None;
+    }
+    // Only painting reads these.
+    let repainted = ComputedStyle {
+        visibility: new.visibility,
+        opacity: new.opacity,
+        z_index: new.z_index,
+        border_color: new.border_color,
+        background_color: new.background_color,
+        color: new.color,
+        text_decoration: new.text_decoration,
+        ..old.clone()
+    };
+    if repainted == *new { Damage::Repaint } else { Damage::Relayout }
+}
+
+/// The damage from restyling a node and its descendants.
+pub(crate) fn subtree_damage(old: &StyleData, new: &StyleData) -> Damage {
+    if old.children.len() != new.children.len() || old.before.is_some() != new.before.is_some()
+        || old.after.is_some() != new.after.is_some() || old.marker.is_some() != new.marker.is_some() {
+        return Damage::Relayout;
+    }
+    let damage = style_damage(&old.style.style, &new.style.style)
+        .max(pseudo_damage(&old.before, &new.before))
+        .max(pseudo_damage(&old.after, &new.after))
+        .max(pseudo_damage(&old.marker, &new.marker));
+    old.children.iter().zip(&new.children)
+        .fold(damage, |damage, (old, new)| damage.max(subtree_damage(old, new)))
+}
+
+fn pseudo_damage(old: &Option<PseudoStyle>, new: &Option<PseudoStyle>) -> Damage {
+    match (old, new) {
+        (Some(old), Some(new)) => {
+            style_damage(&old.style.style, &new.style.style).max(style_damage(&old.text.style, &new.text.style))
+        }
+        _ => Damage::None,
+    }
+}
+
+/// A document that keeps its styles between changes, so that after a change to the tree or to
+/// a style sheet only the elements that might be affected are restyled. Nodes are addressed by
+/// their path of child indices from the root.
+pub struct Document {
+    root: Node,
+    /// None until the document is first styled.
+    styles: Option<StyleData>,
+    /// The media environment the styles were computed for.
+    media: Option<MediaEnvironment>,
+    invalidation: InvalidationMap,
+    damage: Damage,
+    stats: StyleStats,
+}
+
+impl Document {
+    pub fn new(root: Node) -> Document {
+        Document {
+            root,
+            styles: None,
+            media: None,
+            invalidation: InvalidationMap::default(),
+            damage: Damage::Relayout,
+            stats: StyleStats::default(),
+        }
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// The node at a path of child indices from the root.
+    pub fn node(&self, path: &[usize]) -> &Node {
+        path.iter().fold(&self.root, |node, &index| &node.children[index])
+    }
+
+    fn node_mut(&mut self, path: &[usize]) -> &mut Node {
+        path.iter().fold(&mut self.root, |node, &index| &mut node.children[index])
+    }
+
+    fn element_mut(&mut self, path: &[usize]) -> &mut ElementData {
+        match self.node_mut(path).node_type {
+            NodeType::Element(ref mut elem) => elem,
+            NodeType::Text(_) => panic!("No element at {:?}", path),
+        }
+    }
+
+    /// Set an attribute, restyling what depends on it if it's an `id` or `class`.
+    pub fn set_attribute(&mut self, path: &[usize], name: &str, value: &str) {
+        let elem = self.element_mut(path);
+        let old = elem.attributes.insert(name.to_string(), value.to_string());
+        self.attribute_changed(path, name, old.as_deref(), Some(value));
+    }
+
+    pub fn remove_attribute(&mut self, path: &[usize], name: &str) {
+        let old = self.element_mut(path).attributes.remove(name);
+        if old.is_some() {
+            self.attribute_changed(path, name, old.as_deref(), None);
+        }
+    }
+
+    fn attribute_changed(&mut self, path: &[usize], name: &str, old: Option<&str>, new: Option<&str>) {
+        let scope = match name {
+            "id" => old.map_or_else(Scope::default, |id| self.invalidation.id_scope(id))
+                .union(new.map_or_else(Scope::default, |id| self.invalidation.id_scope(id))),
+            "class" => {
+                let old: Vec<&str> = old.map_or(Vec::new(), |old| old.split_whitespace().collect());
+                let new: Vec<&str> = new.map_or(Vec::new(), |new| new.split_whitespace().collect());
+                old.iter().filter(|class| !new.contains(class))
+                    .chain(new.iter().filter(|class| !old.contains(class)))
+                    .fold(Scope::default(), |scope, class| scope.union(self.invalidation.class_scope(class)))
+            }
+            // Other attributes only show up through `attr()` in generated content, whose text
+            // is filled in when the style tree is built.
+            _ => {
+                let generates_content = |data: &StyleData| data.before.is_some() || data.after.is_some() || data.marker.is_some();
+                if self.styles_at(path).is_some_and(generates_content) {
+                    self.damage = self.damage.max(Damage::Relayout);
+                }
+                Scope::default()
+            }
+        };
+        self.invalidate(path, scope);
+    }
+
+    /// Replace the text of a text node.
+    pub fn set_text(&mut self, path: &[usize], text: &str) {
+        match self.node_mut(path).node_type {
+            NodeType::Text(ref mut old) => *old = text.to_string(),
+            NodeType::Element(_) => panic!("No text node at {:?}", path),
+        }
+        self.damage = Damage::Relayout;
+        // Whether text is only white space decides whether its parent is `:empty`.
+        self.tree_changed(&path[..path.len() - 1]);
+    }
+
+    /// Insert a node as the child at `index` of the element at `parent`.
+    pub fn insert_child(&mut self, parent: &[usize], index: usize, node: Node) {
+        self.node_mut(parent).children.insert(index, node);
+        if let Some(data) = self.styles_at_mut(parent) {
+            // A placeholder until the node is styled.
+            data.children.insert(index, StyleData { dirty: true, ..StyleData::new(data.style.clone()) });
+            self.mark_dirty_descendants(parent);
+        }
+        self.damage = Damage::Relayout;
+        self.tree_changed(parent);
+    }
+
+    /// Remove the child at `index` of the element at `parent`.
+    pub fn remove_child(&mut self, parent: &[usize], index: usize) -> Node {
+        let node = self.node_mut(parent).children.remove(index);
+        if let Some(data) = self.styles_at_mut(parent) {
+            data.children.remove(index);
+        }
+        self.damage = Damage::Relayout;
+        self.tree_changed(parent);
+        node
+    }
+
+    /// Restyle what a change to the children of `parent` affects.
+    fn tree_changed(&mut self, parent: &[usize]) {
+        if self.invalidation.relational {
+            self.mark_dirty(&[]);
+        } else if self.invalidation.structural {
+            self.mark_dirty(parent);
+        }
+    }
+
+    /// Restyle the elements that the rules changed by an edit to a style sheet could apply to.
+    /// For a style sheet that was added to or removed from the cascade, pass an empty style
+    /// sheet as the other version. An imported style sheet that changed is passed on its own.
+    pub fn stylesheet_changed(&mut self, old: &Stylesheet, new: &Stylesheet) {
+        // The next restyle rebuilds the map, but until then it should know about the new
+        // selectors.
+        self.invalidation.add_stylesheet(new);
+        let subjects = Subjects::changed(old, new);
+        if subjects.all() {
+            self.mark_dirty(&[]);
+            return;
+        }
+        let mut dirty = Vec::new();
+        find_subjects(&self.root, &subjects, &mut Vec::new(), &mut dirty);
+        for path in dirty {
+            self.mark_dirty(&path);
+        }
+    }
+
+    /// Mark the elements that a change within `scope` of the element at `path` affects.
+    fn invalidate(&mut self, path: &[usize], scope: Scope) {
+        if scope.document {
+            self.mark_dirty(&[]);
+            return;
+        }
+        if scope.subtree {
+            self.mark_dirty(path);
+        }
+        if let Some((&index, parent)) = path.split_last() {
+            let siblings = self.node(parent).children.len();
+            let affected = if scope.siblings {
+                0..siblings
+            } else if scope.following_siblings {
+                index + 1..siblings
+            } else {
+                0..0
+            };
+            let mut sibling = path.to_vec();
+            for other in affected.filter(|&other| other != index) {
+                *sibling.last_mut().unwrap() = other;
+                self.mark_dirty(&sibling);
+            }
+        }
+    }
+
+    fn styles_at(&self, path: &[usize]) -> Option<&StyleData> {
+        path.iter().try_fold(self.styles.as_ref()?, |data, &index| data.children.get(index))
+    }
+
+    fn styles_at_mut(&mut self, path: &[usize]) -> Option<&mut StyleData> {
+        path.iter().try_fold(self.styles.as_mut()?, |data, &index| data.children.get_mut(index))
+    }
+
+    /// Mark the node at `path` and its descendants for restyling.
+    fn mark_dirty(&mut self, path: &[usize]) {
+        if let Some(data) = self.styles_at_mut(path) {
+            data.dirty = true;
+        }
+        if let Some((_, parent)) = path.split_last() {
+            self.mark_dirty_descendants(parent);
+        }
+    }
+
+    /// Note that the node at `path`, and each of its ancestors, has a dirty descendant.
+    fn mark_dirty_descendants(&mut self, path: &[usize]) {
+        let Some(mut data) = self.styles.as_mut() else { return };
+        data.dirty_descendants = true;
+        for &index in path {
+            data = &mut data.children[index];
+            data.dirty_descendants = true;
+        }
+    }
+
+    /// Bring the styles up to date, and say how much rendering the changes since the last
+    /// restyle invalidated. Style sheets in the cascade that changed since then must have been
+    /// passed to `stylesheet_changed`. A different media environment restyles everything.
+    pub fn restyle(&mut self, cascade: &Cascade, media: &MediaEnvironment) -> Damage {
+        let active_rules = cascade.active_rules(media);
+        self.invalidation = InvalidationMap::new(active_rules.iter().map(|&(_, _, rule)| rule));
+        let rules = RuleMap::new(active_rules);
+        let mut traversal = Traversal::new(&rules, false);
+        let damage = match self.styles {
+            Some(ref mut styles) if self.media.as_ref() == Some(media) => {
+                traversal.restyle_document(&self.root, styles, media)
+            }
+            _ => {
+                self.styles = Some(traversal.style_document(&self.root, media));
+                self.media = Some(media.clone());
+                Damage::Relayout
+            }
+        };
+        self.stats = traversal.stats;
+        std::mem::take(&mut self.damage).max(damage)
+    }
+
+    /// What the last restyle did. Elements that didn't need restyling aren't counted.
+    pub fn stats(&self) -> StyleStats {
+        self.stats
+    }
+
+    /// The style tree of the document, as of the last restyle.
+    pub fn style_tree(&self) -> StyleNode<'_> {
+        match self.styles {
+            Some(ref styles) if !styles.dirty && !styles.dirty_descendants => build_style_tree(&self.root, styles),
+            _ => panic!("Document::restyle must be called before Document::style_tree"),
+        }
+    }
+}
+
+/// Find the paths of the elements a style sheet's rules could apply to, skipping the
+/// descendants of those found, which are restyled with them.
+fn find_subjects(node: &Node, subjects: &Subjects, path: &mut Vec<usize>, found: &mut Vec<Vec<usize>>) {
+    if let NodeType::Element(ref elem) = node.node_type {
+        if subjects.contains(elem) {
+            found.push(path.clone());
+            return;
+        }
+    }
+    for (index, child) in node.children.iter().enumerate() {
+        path.push(index);
+        find_subjects(child, subjects, path, found);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::{css, dom, html, style};
+
+    /// A document of two lists, with the `<body>` at `[0]` and the items at `[0, list, item]`.
+    fn lists() -> Document {
+        Document::new(html::parse("<html><body id=\"main\">\
+            <ul><li class=\"a\">1</li><li>2</li><li class=\"a\">3</li><li>4</li><li class=\"a\">5</li></ul>\
+            <ul><li>1</li><li title=\"two\">2</li><li>3</li></ul>\
+            </body></html>".to_string()))
+    }
+
+    /// Restyle incrementally and check that the result is the same as styling from scratch.
+    fn check_restyle(document: &mut Document, stylesheet: &Stylesheet, change: &str) -> Damage {
+        let cascade = Cascade::author(stylesheet);
+        let media = MediaEnvironment::screen(800.0, 600.0);
+        let damage = document.restyle(&cascade, &media);
+        let expected = style::style_tree(document.root(), &cascade, &media);
+        assert!(document.style_tree() == expected, "Incremental restyle after {} differs from a full restyle", change);
+        damage
+    }
+
+    #[test]
+    fn nth_child_of_selectors_restyle_siblings_on_both_sides() {
+        let stylesheet = css::parse("li:nth-child(odd of .a) { color: red }
+            li:nth-last-child(1 of .a) { font-weight: bold }
+            li:nth-last-child(2 of .b + .a) { margin-left: 3px }".to_string());
+        let mut document = lists();
+        check_restyle(&mut document, &stylesheet, "the first style");
+
+        document.set_attribute(&[0, 0, 3], "class", "a");
+        check_restyle(&mut document, &stylesheet, "adding a class an earlier item counts");
+        document.set_attribute(&[0, 0, 1], "class", "a");
+        check_restyle(&mut document, &stylesheet, "adding a class a later item counts");
+        document.remove_attribute(&[0, 0, 4], "class");
+        check_restyle(&mut document, &stylesheet, "removing the class of the last item");
+        document.set_attribute(&[0, 0, 0], "class", "b");
+        check_restyle(&mut document, &stylesheet, "adding a class to the first item");
+        document.set_attribute(&[0, 0, 2], "class", "a b");
+        check_restyle(&mut document, &stylesheet, "adding a class a sibling combinator uses");
+    }
+
+    #[test]
+    fn attribute_changes() {
+        let stylesheet = css::parse("#main .a { color: red }
+            .dark li { color: white }
+            li.a + li { margin-top: 4px }
+            li.a ~ li.b { margin-left: 2px }
+            li::before { content: attr(title) }".to_string());
+        let mut document = lists();
+        check_restyle(&mut document, &stylesheet, "the first style");
+
+        document.set_attribute(&[0, 1, 0], "class", "a");
+        check_restyle(&mut document, &stylesheet, "adding a class");
+        document.set_attribute(&[0, 1, 2], "class", "b");
+        check_restyle(&mut document, &stylesheet, "adding a class a later sibling uses");
+        document.set_attribute(&[0, 1], "class", "dark");
+        check_restyle(&mut document, &stylesheet, "adding a class an ancestor uses");
+        document.set_attribute(&[0], "id", "other");
+        check_restyle(&mut document, &stylesheet, "changing the id of the body");
+        document.set_attribute(&[0, 1, 1], "title", "Two");
+        assert_eq!(check_restyle(&mut document, &stylesheet, "changing generated content"), Damage::Relayout);
+        document.set_attribute(&[0, 0], "data-unused", "1");
+        assert_eq!(check_restyle(&mut document, &stylesheet, "setting an unused attribute"), Damage::None);
+    }
+
+    #[test]
+    fn text_changes() {
+        let stylesheet = css::parse("li:empty { display: none } li { color: blue }".to_string());
+        let mut document = lists();
+        check_restyle(&mut document, &stylesheet, "the first style");
+
+        document.set_text(&[0, 0, 1, 0], "");
+        assert_eq!(check_restyle(&mut document, &stylesheet, "emptying an item"), Damage::Relayout);
+        document.set_text(&[0, 0, 1, 0], "Two");
+        check_restyle(&mut document, &stylesheet, "filling an item");
+    }
+
+    #[test]
+    fn inserting_and_removing_nodes() {
+        let stylesheet = css::parse("li:first-child { color: red }
+            li:last-child { color: blue }
+            li + li { margin-top: 2px }
+            li:nth-child(2n) { font-weight: bold }".to_string());
+        let mut document = lists();
+        check_restyle(&mut document, &stylesheet, "the first style");
+
+        let item = dom::elem("li".to_string(), HashMap::new(), vec![dom::text("New".to_string())]);
+        document.insert_child(&[0, 1], 0, item);
+        check_restyle(&mut document, &stylesheet, "inserting a first item");
+        let item = dom::elem("li".to_string(), HashMap::new(), vec![dom::text("Last".to_string())]);
+        document.insert_child(&[0, 1], 4, item);
+        check_restyle(&mut document, &stylesheet, "appending an item");
+        document.remove_child(&[0, 0], 2);
+        check_restyle(&mut document, &stylesheet, "removing a middle item");
+        document.remove_child(&[0], 0);
+        check_restyle(&mut document, &stylesheet, "removing a list");
+    }
+
+    #[test]
+    fn stylesheet_changes() {
+        let source = "li { color: red } .a { margin-left: 2px } ul > li:last-child { color: green }";
+        let mut stylesheet = css::parse(source.to_string());
+        let mut document = lists();
+        check_restyle(&mut document, &stylesheet, "the first style");
+
+        for edited in [
+            source.replace("color: red", "color: blue"),
+            source.replace("margin-left: 2px", "margin-left: 4px"),
+            format!("{} #main li {{ font-size: 20px }}", source),
+            format!("@layer base {{ li {{ color: black !important }} }} {}", source),
+            String::new(),
+        ] {
+            let edited = css::parse(edited);
+            document.stylesheet_changed(&stylesheet, &edited);
+            stylesheet = edited;
+            check_restyle(&mut document, &stylesheet, "editing the style sheet");
+        }
+        assert_eq!(check_restyle(&mut document, &stylesheet, "no change"), Damage::None);
+    }
+}
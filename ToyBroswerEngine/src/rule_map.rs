@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use crate::css::{Combinator, PseudoClass, Selector, SimpleSelector, Specificity};
+use crate::dom::ElementData;
+use crate::style::ActiveRule;
+
+/// The active rules, with their selectors bucketed by the id, class or tag name of their
+/// rightmost simple selector, so that only selectors that might match an element are tested.
+pub(crate) struct RuleMap<'a> {
+    rules: Vec<ActiveRule<'a>>,
+    /// Every selector of every rule, in cascade order.
+    selectors: Vec<IndexedSelector<'a>>,
+    // Indices into `selectors`. Each selector is in exactly one bucket: its id if it has one,
+    // otherwise its first class, otherwise its tag name, otherwise the universal bucket.
+    by_id: HashMap<&'a str, Vec<usize>>,
+    by_class: HashMap<&'a str, Vec<usize>>,
+    by_tag: HashMap<&'a str, Vec<usize>>,
+    universal: Vec<usize>,
+}
+
+pub(crate) struct IndexedSelector<'a> {
+    /// The index of the selector's rule in the active rules.
+    pub rule: usize,
+    pub selector: &'a Selector,
+    pub specificity: Specificity,
+    /// Whether the selector can match one element but not a sibling with the same tag name and
+    /// classes, so two such siblings can only share a style if it matches both or neither.
+    pub revalidate: bool,
+    /// Hashes of the ids, classes and tag names that the selector requires ancestors to have.
+    ancestor_hashes: Vec<u32>,
+}
+
+impl<'a> RuleMap<'a> {
+    pub fn new(rules: Vec<ActiveRule<'a>>) -> RuleMap<'a> {
+        let mut map = RuleMap {
+            rules: Vec::new(),
+            selectors: Vec::new(),
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: Vec::new(),
+        };
+        for (index, &(_, _, rule)) in rules.iter().enumerate() {
+            for selector in &rule.selectors {
+                let position = map.selectors.len();
+                let rightmost = selector.rightmost();
+                let bucket = match (&rightmost.id, rightmost.class.first(), &rightmost.tag_name) {
+                    (Some(id), _, _) => map.by_id.entry(id).or_default(),
+                    (None, Some(class), _) => map.by_class.entry(class).or_default(),
+                    (None, None, Some(tag_name)) => map.by_tag.entry(tag_name).or_default(),
+                    (None, None, None) => &mut map.universal,
+                };
+                bucket.push(position);
+                map.selectors.push(IndexedSelector {
+                    rule: index,
+                    selector,
+                    specificity: selector.specificity(),
+                    revalidate: depends_on_element(selector),
+                    ancestor_hashes: ancestor_hashes(selector),
+                });
+            }
+        }
+        map.rules = rules;
+        map
+    }
+
+    pub fn rule(&self, index: usize) -> ActiveRule<'a> {
+        self.rules[index]
+    }
+
+    /// The selectors that might match an element whose ancestors are in `ancestors`, in
+    /// cascade order. Selectors in other buckets, or that need an ancestor the filter doesn't
+    /// have, can't match.
+    pub fn candidates(&self, elem: &ElementData, ancestors: &AncestorFilter) -> Vec<&IndexedSelector<'a>> {
+        let mut indices: Vec<usize> = Vec::new();
+        let classes = elem.classes();
+        if let Some(bucket) = elem.id().and_then(|id| self.by_id.get(&**id)) {
+            indices.extend(bucket);
+        }
+        for class in classes {
+            indices.extend(self.by_class.get(class).into_iter().flatten());
+        }
+        indices.extend(self.by_tag.get(&*elem.tag_name).into_iter().flatten());
+        indices.extend(&self.universal);
+        indices.sort_unstable();
+        indices.into_iter()
+            .map(|index| &self.selectors[index])
+            .filter(|selector| selector.ancestor_hashes.iter().all(|&hash| ancestors.might_contain(hash)))
+            .collect()
+    }
+}
+
+/// Does the selector look at more of the element than its tag name, id, classes and ancestors,
+/// which are all siblings have in common? Its position among its siblings, the siblings
+/// themselves, and its children all differ.
+fn depends_on_element(selector: &Selector) -> bool {
+    match *selector {
+        Selector::Simple(ref simple) => compound_depends_on_element(simple),
+        Selector::Complex(_, combinator, ref right) => compound_depends_on_element(right)
+            || matches!(combinator, Combinator::NextSibling | Combinator::SubsequentSibling),
+    }
+}
+
+fn compound_depends_on_element(selector: &SimpleSelector) -> bool {
+    selector.pseudo_classes.iter().any(|pseudo| match *pseudo {
+        PseudoClass::Root => false,
+        PseudoClass::Not(ref list) | PseudoClass::Is(ref list) | PseudoClass::Where(ref list) => {
+            list.iter().any(depends_on_element)
+        }
+        _ => true,
+    })
+}
+
+/// The hashes of the ids, classes and tag names of the simple selectors that have to match an
+/// ancestor of the element: those to the left of a descendant or child combinator. Those to
+/// the left of a sibling combinator match a sibling instead.
+fn ancestor_hashes(selector: &Selector) -> Vec<u32> {
+    let mut hashes = Vec::new();
+    let mut current = selector;
+    while let Selector::Complex(left, combinator, _) = current {
+        if let Combinator::Descendant | Combinator::Child = combinator {
+            let ancestor = left.rightmost();
+            hashes.extend(ancestor.id.iter().chain(&ancestor.class).chain(&ancestor.tag_name).map(|name| hash(name)));
+        }
+        current = left;
+    }
+    hashes
+}
+
+/// FNV-1a, which is quick for the short names in selectors.
+fn hash(name: &str) -> u32 {
+    name.bytes().fold(0x811c9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+const FILTER_KEY_BITS: u32 = 12;
+const FILTER_SIZE: usize = 1 << FILTER_KEY_BITS;
+const FILTER_KEY_MASK: u32 = FILTER_SIZE as u32 - 1;
+
+/// A counting Bloom filter of the ids, classes and tag names of an element's ancestors, kept up
+/// to date as the tree is walked. It can say that no ancestor has a name, but not for sure that
+/// one does.
+#[derive(Clone)]
+pub(crate) struct AncestorFilter {
+    counters: Box<[u8; FILTER_SIZE]>,
+}
+
+impl AncestorFilter {
+    pub fn new() -> AncestorFilter {
+        AncestorFilter { counters: Box::new([0; FILTER_SIZE]) }
+    }
+
+    /// Add an element whose descendants are about to be styled.
+    pub fn push(&mut self, elem: &ElementData) {
+        for_each_name(elem, |hash| {
+            for key in keys(hash) {
+                // A counter that overflows stays saturated, since it no longer knows how many
+                // names share it.
+                let counter = &mut self.counters[key];
+                *counter = counter.saturating_add(1);
+            }
+        });
+    }
+
+    /// Remove the element added by the matching `push`.
+    pub fn pop(&mut self, elem: &ElementData) {
+        for_each_name(elem, |hash| {
+            for key in keys(hash) {
+                let counter = &mut self.counters[key];
+                if *counter != u8::MAX {
+                    *counter -= 1;
+                }
+            }
+        });
+    }
+
+    fn might_contain(&self, hash: u32) -> bool {
+        keys(hash).iter().all(|&key| self.counters[key] != 0)
+    }
+}
+
+fn for_each_name(elem: &ElementData, mut f: impl FnMut(u32)) {
+    f(hash(&elem.tag_name));
+    if let Some(id) = elem.id() {
+        f(hash(id));
+    }
+    for class in elem.classes() {
+        f(hash(class));
+    }
+}
+
+/// The two counters a hash maps to.
+fn keys(hash: u32) -> [usize; 2] {
+    [(hash & FILTER_KEY_MASK) as usize, ((hash >> FILTER_KEY_BITS) & FILTER_KEY_MASK) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::NodeType;
+    use crate::media::MediaEnvironment;
+    use crate::style::{self, Cascade, ElementRef};
+    use crate::{css, html};
+
+    /// The selectors that match an element, as positions in `map.selectors`, found by testing
+    /// either every selector or only the candidates.
+    fn matching(map: &RuleMap, elem: &ElementRef, selectors: &[&IndexedSelector]) -> Vec<usize> {
+        selectors.iter()
+            .filter(|selector| style::matches(elem, selector.selector))
+            .map(|&selector| map.selectors.iter().position(|s| std::ptr::eq(s, selector)).unwrap())
+            .collect()
+    }
+
+    /// Check that the candidates of an element and its descendants match the same selectors as
+    /// the full list, and count the selectors that were never tested.
+    fn check_candidates(map: &RuleMap, elem: &ElementRef, ancestors: &mut AncestorFilter, skipped: &mut usize) {
+        let candidates = map.candidates(elem.data(), ancestors);
+        let all: Vec<&IndexedSelector> = map.selectors.iter().collect();
+        assert_eq!(matching(map, elem, &candidates), matching(map, elem, &all),
+                   "Candidates of <{}> match differently", elem.data().tag_name);
+        *skipped += all.len() - candidates.len();
+        ancestors.push(elem.data());
+        for child in elem.children() {
+            check_candidates(map, &child, ancestors, skipped);
+        }
+        ancestors.pop(elem.data());
+    }
+
+    #[test]
+    fn candidates_match_like_every_selector() {
+        let root = html::parse("<html><body id=\"main\"><div class=\"x y\"><ul>\
+            <li class=\"a\">1</li><li>2</li><li class=\"a b\" id=\"third\">3</li></ul>\
+            <p>Text <em class=\"a\">em</em></p></div><p class=\"y\">Text</p></body></html>".to_string());
+        let stylesheet = css::parse("#main li, .a, ul > .a, li:nth-child(2), body .missing li, div p, * { color: red } \
+            .x.y em, .y, li + li, :is(ul) > li, ul li.a ~ li, #nope .a, #third, p:not(.y) em { color: blue } \
+            div > ul > li.b, body > div p > em, html p::before, .missing { color: green }".to_string());
+        let cascade = Cascade { default_user_agent: false, ..Cascade::author(&stylesheet) };
+        let map = RuleMap::new(cascade.active_rules(&MediaEnvironment::screen(800.0, 600.0)));
+        let mut skipped = 0;
+        check_candidates(&map, &ElementRef::root(&root), &mut AncestorFilter::new(), &mut skipped);
+        assert!(skipped > 0);
+    }
+
+    #[test]
+    fn ancestor_filter_forgets_popped_elements() {
+        let root = html::parse("<div id=\"a\" class=\"b c\"><p class=\"c\">Text</p></div>".to_string());
+        let NodeType::Element(ref div) = root.node_type else { panic!("No root element") };
+        let NodeType::Element(ref p) = root.children[0].node_type else { panic!("No child element") };
+        let mut filter = AncestorFilter::new();
+        filter.push(div);
+        filter.push(p);
+        assert!(["div", "a", "b", "c", "p"].iter().all(|name| filter.might_contain(hash(name))));
+        filter.pop(p);
+        // `c` is still on the div.
+        assert!(["div", "a", "b", "c"].iter().all(|name| filter.might_contain(hash(name))));
+        filter.pop(div);
+        assert!(!["div", "a", "b", "c", "p"].iter().any(|name| filter.might_contain(hash(name))));
+    }
+}
@@ -0,0 +1,848 @@
+use std::fmt;
+use crate::css::{Color, Combinator, ContentItem, CssRule, Declaration, FontFaceRule, FontSource, ImportRule};
+use crate::css::{MathExpr, Nth, PseudoClass, PseudoElement, RelativeSelector, Rule, Selector};
+use crate::css::{SimpleSelector, Stylesheet, SupportsCondition, Unit, Value};
+use crate::media::{Comparison, MediaCondition, MediaFeature, MediaQuery};
+
+/// Turns parsed CSS back into text. The canonical form puts each rule and declaration on its
+/// own line; the minified form leaves out all optional whitespace and punctuation.
+///
+/// Parsing the output gives back the same structure, with two exceptions: shorthands come
+/// out as the longhands the parser expanded them to, and colors and keywords in their
+/// normalized form.
+pub trait ToCss {
+    fn to_css(&self, dest: &mut CssWriter);
+
+    fn to_css_string(&self) -> String {
+        let mut dest = CssWriter { output: String::new(), minify: false, indent: 0 };
+        self.to_css(&mut dest);
+        dest.output
+    }
+
+    fn to_minified_css(&self) -> String {
+        let mut dest = CssWriter { output: String::new(), minify: true, indent: 0 };
+        self.to_css(&mut dest);
+        dest.output
+    }
+}
+
+/// The output of a serialization in progress.
+pub struct CssWriter {
+    output: String,
+    minify: bool,
+    /// The nesting depth of the current block.
+    indent: usize,
+}
+
+impl CssWriter {
+    pub fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+
+    /// Whitespace that is only there for readability.
+    fn space(&mut self) {
+        if !self.minify {
+            self.output.push(' ');
+        }
+    }
+
+    /// A separator such as `, ` or ` > `, with the spaces around it left out when minifying.
+    /// A separator that is only a space is always written.
+    fn separator(&mut self, separator: &str) {
+        let trimmed = separator.trim();
+        self.write(if self.minify && !trimmed.is_empty() { trimmed } else { separator });
+    }
+
+    /// An empty writer in the same mode, for serializing part of the output separately.
+    fn fragment(&self) -> CssWriter {
+        CssWriter { output: String::new(), minify: self.minify, indent: 0 }
+    }
+
+    /// Start a new line at the current indentation.
+    fn newline(&mut self) {
+        if !self.minify {
+            self.output.push('\n');
+            for _ in 0..self.indent {
+                self.output.push_str("  ");
+            }
+        }
+    }
+
+    /// Write a `{ ... }` block with one line per item, separated by `separator`. The separator
+    /// after the last item is only written in the canonical form.
+    fn block<T>(&mut self, items: &[T], separator: &str, mut write_item: impl FnMut(&mut CssWriter, &T)) {
+        self.space();
+        self.write("{");
+        self.indent += 1;
+        for (i, item) in items.iter().enumerate() {
+            self.newline();
+            write_item(self, item);
+            if i + 1 < items.len() || !self.minify {
+                self.write(separator);
+            }
+        }
+        self.indent -= 1;
+        if !items.is_empty() {
+            self.newline();
+        }
+        self.write("}");
+    }
+
+    /// Write items with a separator between them.
+    fn list<T>(&mut self, items: &[T], separator: &str, mut write_item: impl FnMut(&mut CssWriter, &T)) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.separator(separator);
+            }
+            write_item(self, item);
+        }
+    }
+
+    fn number(&mut self, number: f32) {
+        // Avoid writing `-0`.
+        let text = if number == 0.0 { "0".to_string() } else { number.to_string() };
+        // The zero before the decimal point is optional.
+        match (text.strip_prefix("0."), text.strip_prefix("-0.")) {
+            (Some(fraction), _) if self.minify => self.write(&format!(".{}", fraction)),
+            (_, Some(fraction)) if self.minify => self.write(&format!("-.{}", fraction)),
+            _ => self.write(&text),
+        }
+    }
+
+    fn string(&mut self, text: &str) {
+        self.write("\"");
+        for c in text.chars() {
+            match c {
+                '"' | '\\' => { self.output.push('\\'); self.output.push(c); }
+                // Control characters are written as hex escapes, followed by a space so
+                // that a hex digit after them isn't taken as part of the escape.
+                c if c.is_control() => self.output.push_str(&format!("\\{:x} ", c as u32)),
+                c => self.output.push(c),
+            }
+        }
+        self.write("\"");
+    }
+}
+
+impl ToCss for Stylesheet {
+    fn to_css(&self, dest: &mut CssWriter) {
+        write_rules(dest, &self.rules);
+    }
+}
+
+fn write_rules(dest: &mut CssWriter, rules: &[CssRule]) {
+    for (i, rule) in rules.iter().enumerate() {
+        if i > 0 {
+            dest.newline();
+        }
+        rule.to_css(dest);
+    }
+}
+
+/// Write the rules nested in an at-rule as a block.
+fn nested_rules(dest: &mut CssWriter, rules: &[CssRule]) {
+    dest.block(rules, "", |dest, rule| rule.to_css(dest));
+}
+
+impl ToCss for CssRule {
+    fn to_css(&self, dest: &mut CssWriter) {
+        match self {
+            CssRule::Style(rule) => rule.to_css(dest),
+            CssRule::Media(media_rule) => {
+                dest.write("@media ");
+                media_query_list(dest, &media_rule.queries);
+                nested_rules(dest, &media_rule.rules);
+            }
+            CssRule::Import(import) => import.to_css(dest),
+            CssRule::Supports(supports) => {
+                dest.write("@supports ");
+                supports.condition.to_css(dest);
+                nested_rules(dest, &supports.rules);
+            }
+            CssRule::Layer(layer) => {
+                dest.write("@layer");
+                if !layer.name.is_empty() {
+                    dest.write(" ");
+                    dest.write(&layer.name);
+                }
+                nested_rules(dest, &layer.rules);
+            }
+            CssRule::LayerStatement(names) => {
+                dest.write("@layer ");
+                dest.list(names, ", ", |dest, name| dest.write(name));
+                dest.write(";");
+            }
+            CssRule::FontFace(font_face) => font_face.to_css(dest),
+        }
+    }
+}
+
+impl ToCss for ImportRule {
+    fn to_css(&self, dest: &mut CssWriter) {
+        dest.write("@import ");
+        dest.string(&self.url);
+        match self.layer.as_deref() {
+            Some("") => dest.write(" layer"),
+            Some(name) => { dest.write(" layer("); dest.write(name); dest.write(")"); }
+            None => {}
+        }
+        if !self.queries.is_empty() {
+            dest.write(" ");
+            media_query_list(dest, &self.queries);
+        }
+        dest.write(";");
+    }
+}
+
+impl ToCss for FontFaceRule {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let mut descriptors = Vec::new();
+        if let Some(family) = &self.family {
+            let mut value = dest.fragment();
+            value.string(family);
+            descriptors.push(("font-family", value.output));
+        }
+        if !self.sources.is_empty() {
+            let mut value = dest.fragment();
+            value.list(&self.sources, ", ", |dest, source| match source {
+                FontSource::Local(name) => { dest.write("local("); dest.string(name); dest.write(")") }
+                FontSource::Url(url, format) => {
+                    dest.write("url(");
+                    dest.string(url);
+                    dest.write(")");
+                    if let Some(format) = format {
+                        dest.write(" format(");
+                        dest.string(format);
+                        dest.write(")");
+                    }
+                }
+            });
+            descriptors.push(("src", value.output));
+        }
+        match self.weight {
+            (400, 400) => {}
+            (min, max) if min == max => descriptors.push(("font-weight", min.to_string())),
+            (min, max) => descriptors.push(("font-weight", format!("{} {}", min, max))),
+        }
+        if self.style != "normal" {
+            descriptors.push(("font-style", self.style.clone()));
+        }
+        if !self.unicode_range.is_empty() {
+            let mut value = dest.fragment();
+            value.list(&self.unicode_range, ", ", |dest, &(start, end)| match start == end {
+                true => dest.write(&format!("U+{:X}", start)),
+                false => dest.write(&format!("U+{:X}-{:X}", start, end)),
+            });
+            descriptors.push(("unicode-range", value.output));
+        }
+        dest.write("@font-face");
+        dest.block(&descriptors, ";", |dest, (name, value)| {
+            dest.write(name);
+            dest.write(":");
+            dest.space();
+            dest.write(value);
+        });
+    }
+}
+
+impl ToCss for Rule {
+    fn to_css(&self, dest: &mut CssWriter) {
+        dest.list(&self.selectors, ", ", |dest, selector| selector.to_css(dest));
+        declaration_block(dest, &self.declarations);
+    }
+}
+
+/// Write declarations as a block. Longhands that a shorthand with `var()` was expanded to are
+/// written as the shorthand, once.
+fn declaration_block(dest: &mut CssWriter, declarations: &[Declaration]) {
+    let mut written: Vec<&Declaration> = Vec::new();
+    for declaration in declarations {
+        let same_shorthand = match (&declaration.value, written.last().map(|last| (&last.value, last.important))) {
+            (Value::PendingSubstitution(..), Some((previous, important))) => {
+                *previous == declaration.value && important == declaration.important
+            }
+            _ => false
+        };
+        if !same_shorthand {
+            written.push(declaration);
+        }
+    }
+    dest.block(&written, ";", |dest, declaration| declaration.to_css(dest));
+}
+
+impl ToCss for Declaration {
+    fn to_css(&self, dest: &mut CssWriter) {
+        match &self.value {
+            Value::PendingSubstitution(shorthand, source) => {
+                dest.write(shorthand);
+                dest.write(":");
+                dest.space();
+                dest.write(source);
+            }
+            value => {
+                dest.write(&self.name);
+                dest.write(":");
+                dest.space();
+                value.to_css(dest);
+            }
+        }
+        if self.important {
+            dest.space();
+            dest.write("!important");
+        }
+    }
+}
+
+impl ToCss for Selector {
+    fn to_css(&self, dest: &mut CssWriter) {
+        match self {
+            Selector::Simple(simple) => simple.to_css(dest),
+            Selector::Complex(left, combinator, right) => {
+                left.to_css(dest);
+                combinator.to_css(dest);
+                right.to_css(dest);
+            }
+        }
+    }
+}
+
+impl ToCss for Combinator {
+    fn to_css(&self, dest: &mut CssWriter) {
+        match self {
+            Combinator::Descendant => dest.write(" "),
+            Combinator::Child => dest.separator(" > "),
+            Combinator::NextSibling => dest.separator(" + "),
+            Combinator::SubsequentSibling => dest.separator(" ~ "),
+        }
+    }
+}
+
+impl ToCss for SimpleSelector {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let start = dest.output.len();
+        if let Some(tag_name) = &self.tag_name {
+            dest.write(tag_name);
+        }
+        if let Some(id) = &self.id {
+            dest.write("#");
+            dest.write(id);
+        }
+        for class in &self.class {
+            dest.write(".");
+            dest.write(class);
+        }
+        for pseudo_class in &self.pseudo_classes {
+            pseudo_class.to_css(dest);
+        }
+        match self.pseudo_element {
+            Some(PseudoElement::Before) => dest.write("::before"),
+            Some(PseudoElement::After) => dest.write("::after"),
+            Some(PseudoElement::Marker) => dest.write("::marker"),
+            None => {}
+        }
+        if dest.output.len() == start {
+            dest.write("*");
+        }
+    }
+}
+
+impl ToCss for PseudoClass {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let selector_list = |dest: &mut CssWriter, name: &str, selectors: &[Selector]| {
+            dest.write(name);
+            dest.write("(");
+            dest.list(selectors, ", ", |dest, selector| selector.to_css(dest));
+            dest.write(")");
+        };
+        let nth = |dest: &mut CssWriter, name: &str, nth: &Nth, of: &[Selector]| {
+            dest.write(name);
+            dest.write("(");
+            nth.to_css(dest);
+            if !of.is_empty() {
+                dest.write(" of ");
+                dest.list(of, ", ", |dest, selector| selector.to_css(dest));
+            }
+            dest.write(")");
+        };
+        match self {
+            PseudoClass::Root => dest.write(":root"),
+            PseudoClass::Empty => dest.write(":empty"),
+            PseudoClass::FirstChild => dest.write(":first-child"),
+            PseudoClass::LastChild => dest.write(":last-child"),
+            PseudoClass::OnlyChild => dest.write(":only-child"),
+            PseudoClass::FirstOfType => dest.write(":first-of-type"),
+            PseudoClass::LastOfType => dest.write(":last-of-type"),
+            PseudoClass::OnlyOfType => dest.write(":only-of-type"),
+            PseudoClass::NthChild(n, of) => nth(dest, ":nth-child", n, of),
+            PseudoClass::NthLastChild(n, of) => nth(dest, ":nth-last-child", n, of),
+            PseudoClass::NthOfType(n) => nth(dest, ":nth-of-type", n, &[]),
+            PseudoClass::NthLastOfType(n) => nth(dest, ":nth-last-of-type", n, &[]),
+            PseudoClass::Not(selectors) => selector_list(dest, ":not", selectors),
+            PseudoClass::Is(selectors) => selector_list(dest, ":is", selectors),
+            PseudoClass::Where(selectors) => selector_list(dest, ":where", selectors),
+            PseudoClass::Has(selectors) => {
+                dest.write(":has(");
+                dest.list(selectors, ", ", |dest, selector| selector.to_css(dest));
+                dest.write(")");
+            }
+        }
+    }
+}
+
+impl ToCss for RelativeSelector {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let symbol = match self.combinator {
+            Combinator::Descendant => None,
+            Combinator::Child => Some(">"),
+            Combinator::NextSibling => Some("+"),
+            Combinator::SubsequentSibling => Some("~"),
+        };
+        if let Some(symbol) = symbol {
+            dest.write(symbol);
+            dest.space();
+        }
+        self.selector.to_css(dest);
+    }
+}
+
+impl ToCss for Nth {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let text = match (self.a, self.b) {
+            (0, b) => b.to_string(),
+            (a, b) => {
+                let a = match a {
+                    1 => "n".to_string(),
+                    -1 => "-n".to_string(),
+                    a => format!("{}n", a),
+                };
+                match b {
+                    0 => a,
+                    b if b > 0 => format!("{}+{}", a, b),
+                    b => format!("{}{}", a, b),
+                }
+            }
+        };
+        dest.write(&text);
+    }
+}
+
+impl ToCss for Value {
+    fn to_css(&self, dest: &mut CssWriter) {
+        match self {
+            Value::Keyword(keyword) => dest.write(keyword),
+            Value::Length(f, unit) => { dest.number(*f); dest.write(unit_name(*unit)) }
+            Value::Number(n) => dest.number(*n),
+            Value::ColorValue(color) => color.to_css(dest),
+            Value::String(text) => dest.string(text),
+            Value::List(values) => dest.list(values, " ", |dest, value| value.to_css(dest)),
+            Value::CommaList(values) => dest.list(values, ", ", |dest, value| value.to_css(dest)),
+            Value::Content(items) => dest.list(items, " ", |dest, item| item.to_css(dest)),
+            Value::Counters(counters) if counters.is_empty() => dest.write("none"),
+            Value::Counters(counters) => dest.list(counters, " ", |dest, (name, value)| {
+                dest.write(name);
+                dest.write(" ");
+                dest.write(&value.to_string());
+            }),
+            Value::Unparsed(source) | Value::PendingSubstitution(_, source) => dest.write(source),
+            Value::Math(expr @ (MathExpr::Min(_) | MathExpr::Max(_) | MathExpr::Clamp(..))) => expr.to_css(dest),
+            Value::Math(expr) => {
+                dest.write("calc(");
+                expr.to_css(dest);
+                dest.write(")");
+            }
+        }
+    }
+}
+
+impl ToCss for ContentItem {
+    fn to_css(&self, dest: &mut CssWriter) {
+        match self {
+            ContentItem::String(text) => dest.string(text),
+            ContentItem::Attr(name) => { dest.write("attr("); dest.write(name); dest.write(")") }
+            ContentItem::Counter(name, style) => {
+                dest.write("counter(");
+                dest.write(name);
+                if style != "decimal" {
+                    dest.separator(", ");
+                    dest.write(style);
+                }
+                dest.write(")");
+            }
+            ContentItem::Counters(name, separator, style) => {
+                dest.write("counters(");
+                dest.write(name);
+                dest.separator(", ");
+                dest.string(separator);
+                if style != "decimal" {
+                    dest.separator(", ");
+                    dest.write(style);
+                }
+                dest.write(")");
+            }
+            ContentItem::OpenQuote => dest.write("open-quote"),
+            ContentItem::CloseQuote => dest.write("close-quote"),
+            ContentItem::NoOpenQuote => dest.write("no-open-quote"),
+            ContentItem::NoCloseQuote => dest.write("no-close-quote"),
+        }
+    }
+}
+
+impl ToCss for MathExpr {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let is_sum = |expr: &MathExpr| matches!(expr, MathExpr::Add(..) | MathExpr::Sub(..));
+        let is_product = |expr: &MathExpr| matches!(expr, MathExpr::Mul(..) | MathExpr::Div(..));
+        // Operands are parenthesized where the parser would otherwise group them differently.
+        let operand = |dest: &mut CssWriter, expr: &MathExpr, parenthesize: bool| {
+            if parenthesize {
+                dest.write("(");
+                expr.to_css(dest);
+                dest.write(")");
+            } else {
+                expr.to_css(dest);
+            }
+        };
+        let function = |dest: &mut CssWriter, name: &str, arguments: &[&MathExpr]| {
+            dest.write(name);
+            dest.write("(");
+            dest.list(arguments, ", ", |dest, argument| argument.to_css(dest));
+            dest.write(")");
+        };
+        match self {
+            MathExpr::Length(f, unit) => { dest.number(*f); dest.write(unit_name(*unit)) }
+            MathExpr::Number(n) => dest.number(*n),
+            // The spaces around `+` and `-` are required.
+            MathExpr::Add(left, right) | MathExpr::Sub(left, right) => {
+                operand(dest, left, false);
+                dest.write(if matches!(self, MathExpr::Add(..)) { " + " } else { " - " });
+                operand(dest, right, is_sum(right));
+            }
+            MathExpr::Mul(left, right) | MathExpr::Div(left, right) => {
+                operand(dest, left, is_sum(left));
+                dest.separator(if matches!(self, MathExpr::Mul(..)) { " * " } else { " / " });
+                operand(dest, right, is_sum(right) || is_product(right));
+            }
+            MathExpr::Min(arguments) => function(dest, "min", &arguments.iter().collect::<Vec<_>>()),
+            MathExpr::Max(arguments) => function(dest, "max", &arguments.iter().collect::<Vec<_>>()),
+            MathExpr::Clamp(min, value, max) => function(dest, "clamp", &[min, value, max]),
+        }
+    }
+}
+
+fn unit_name(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Px => "px",
+        Unit::Cm => "cm",
+        Unit::Mm => "mm",
+        Unit::Q => "q",
+        Unit::In => "in",
+        Unit::Pt => "pt",
+        Unit::Pc => "pc",
+        Unit::Em => "em",
+        Unit::Rem => "rem",
+        Unit::Ex => "ex",
+        Unit::Ch => "ch",
+        Unit::Vw => "vw",
+        Unit::Vh => "vh",
+        Unit::Vmin => "vmin",
+        Unit::Vmax => "vmax",
+        Unit::Percent => "%",
+    }
+}
+
+impl ToCss for Color {
+    /// `rgb()` or `rgba()` in the canonical form, like the CSSOM, and the shortest hex
+    /// notation when minifying.
+    fn to_css(&self, dest: &mut CssWriter) {
+        if dest.minify {
+            let channels = [self.r, self.g, self.b, self.a];
+            let channels = if self.a == 255 { &channels[..3] } else { &channels[..] };
+            let short = channels.iter().all(|c| c % 17 == 0);
+            dest.write("#");
+            for c in channels {
+                let hex = if short { format!("{:x}", c / 17) } else { format!("{:02x}", c) };
+                dest.write(&hex);
+            }
+        } else if self.a == 255 {
+            dest.write(&format!("rgb({}, {}, {})", self.r, self.g, self.b));
+        } else {
+            dest.write(&format!("rgba({}, {}, {}, {})", self.r, self.g, self.b, alpha(self.a)));
+        }
+    }
+}
+
+/// An alpha channel as a number from 0 to 1, with two decimals if that's enough to get the
+/// same channel value back, or three otherwise.
+fn alpha(a: u8) -> f32 {
+    let two_decimals = (a as f32 / 255.0 * 100.0).round() / 100.0;
+    if (two_decimals * 255.0).round() as u8 == a {
+        two_decimals
+    } else {
+        (a as f32 / 255.0 * 1000.0).round() / 1000.0
+    }
+}
+
+fn media_query_list(dest: &mut CssWriter, queries: &[MediaQuery]) {
+    dest.list(queries, ", ", |dest, query| query.to_css(dest));
+}
+
+impl ToCss for MediaQuery {
+    fn to_css(&self, dest: &mut CssWriter) {
+        if let Some(media_type) = &self.media_type {
+            if self.negated {
+                dest.write("not ");
+            }
+            dest.write(media_type);
+            if self.condition.is_some() {
+                dest.write(" and ");
+            }
+        }
+        if let Some(condition) = &self.condition {
+            condition.to_css(dest);
+        }
+    }
+}
+
+impl ToCss for MediaCondition {
+    fn to_css(&self, dest: &mut CssWriter) {
+        // Nested conditions are parenthesized; features already are.
+        let in_parens = |dest: &mut CssWriter, condition: &MediaCondition| match condition {
+            MediaCondition::Feature(feature) => feature.to_css(dest),
+            condition => {
+                dest.write("(");
+                condition.to_css(dest);
+                dest.write(")");
+            }
+        };
+        match self {
+            MediaCondition::Feature(feature) => feature.to_css(dest),
+            MediaCondition::Not(condition) => { dest.write("not "); in_parens(dest, condition) }
+            MediaCondition::And(conditions) => join(dest, conditions, " and ", in_parens),
+            MediaCondition::Or(conditions) => join(dest, conditions, " or ", in_parens),
+        }
+    }
+}
+
+impl ToCss for MediaFeature {
+    /// `min-` and `max-` prefixes are used where they can be, and range syntax otherwise.
+    fn to_css(&self, dest: &mut CssWriter) {
+        let value = |dest: &mut CssWriter, value: &Value| match value {
+            // The parser converts resolutions to dppx.
+            Value::Number(n) if self.name == "resolution" => { dest.number(*n); dest.write("dppx") }
+            value => value.to_css(dest),
+        };
+        dest.write("(");
+        match &self.comparisons[..] {
+            [] => dest.write(&self.name),
+            [(comparison @ (Comparison::Equal | Comparison::GreaterOrEqual | Comparison::LessOrEqual), v)] => {
+                dest.write(match comparison {
+                    Comparison::GreaterOrEqual => "min-",
+                    Comparison::LessOrEqual => "max-",
+                    _ => "",
+                });
+                dest.write(&self.name);
+                dest.write(":");
+                dest.space();
+                value(dest, v);
+            }
+            [(comparison, v)] => {
+                dest.write(&self.name);
+                dest.separator(comparison_operator(*comparison));
+                value(dest, v);
+            }
+            // `a < name < b`: the first comparison is written the other way around.
+            [(first, low), (second, high)] => {
+                value(dest, low);
+                dest.separator(comparison_operator(match first {
+                    Comparison::Greater => Comparison::Less,
+                    Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+                    Comparison::Less => Comparison::Greater,
+                    Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+                    Comparison::Equal => Comparison::Equal,
+                }));
+                dest.write(&self.name);
+                dest.separator(comparison_operator(*second));
+                value(dest, high);
+            }
+            _ => panic!("A media feature can have at most two comparisons")
+        }
+        dest.write(")");
+    }
+}
+
+fn comparison_operator(comparison: Comparison) -> &'static str {
+    match comparison {
+        Comparison::Less => " < ",
+        Comparison::LessOrEqual => " <= ",
+        Comparison::Equal => " = ",
+        Comparison::GreaterOrEqual => " >= ",
+        Comparison::Greater => " > ",
+    }
+}
+
+impl ToCss for SupportsCondition {
+    fn to_css(&self, dest: &mut CssWriter) {
+        let in_parens = |dest: &mut CssWriter, condition: &SupportsCondition| match condition {
+            SupportsCondition::Not(_) | SupportsCondition::And(_) | SupportsCondition::Or(_) => {
+                dest.write("(");
+                condition.to_css(dest);
+                dest.write(")");
+            }
+            condition => condition.to_css(dest),
+        };
+        match self {
+            SupportsCondition::Declaration(name, value) => {
+                dest.write("(");
+                dest.write(name);
+                dest.write(":");
+                dest.space();
+                dest.write(value);
+                dest.write(")");
+            }
+            SupportsCondition::Selector(selectors) => { dest.write("selector("); dest.write(selectors); dest.write(")") }
+            SupportsCondition::Unknown(text) => { dest.write("("); dest.write(text); dest.write(")") }
+            SupportsCondition::Not(condition) => { dest.write("not "); in_parens(dest, condition) }
+            SupportsCondition::And(conditions) => join(dest, conditions, " and ", in_parens),
+            SupportsCondition::Or(conditions) => join(dest, conditions, " or ", in_parens),
+        }
+    }
+}
+
+/// Write conditions joined by `and` or `or`, which need spaces around them even when minified.
+fn join<T>(dest: &mut CssWriter, conditions: &[T], joiner: &str, write_condition: impl Fn(&mut CssWriter, &T)) {
+    for (i, condition) in conditions.iter().enumerate() {
+        if i > 0 {
+            dest.write(joiner);
+        }
+        write_condition(dest, condition);
+    }
+}
+
+// `Display` writes the canonical form, so that parsed CSS can be printed with `{}`.
+macro_rules! display_as_css {
+    ($($type:ty),*) => {$(
+        impl fmt::Display for $type {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.to_css_string())
+            }
+        }
+    )*};
+}
+
+display_as_css!(Stylesheet, CssRule, Rule, Declaration, Selector, SimpleSelector, Value, Color, MathExpr,
+                MediaQuery, SupportsCondition);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+
+    /// Serialize `source` in both forms, checking that parsing either form again gives back the
+    /// same style sheet. Returns the canonical and the minified form.
+    fn round_trip(source: &str) -> (String, String) {
+        let stylesheet = css::parse(source.to_string());
+        assert!(stylesheet.warnings.is_empty(), "{:?}", stylesheet.warnings);
+        let canonical = stylesheet.to_css_string();
+        let minified = stylesheet.to_minified_css();
+        for output in [&canonical, &minified] {
+            let reparsed = css::parse(output.clone());
+            assert!(reparsed.warnings.is_empty(), "{}: {:?}", output, reparsed.warnings);
+            assert_eq!(reparsed.to_css_string(), canonical, "reparsing {}", output);
+            assert_eq!(reparsed.to_minified_css(), minified, "reparsing {}", output);
+        }
+        (canonical, minified)
+    }
+
+    #[test]
+    fn rules_and_declarations() {
+        let (canonical, minified) = round_trip("h1, .a > p#b { color: red; margin: 0 auto !important }");
+        assert_eq!(canonical, ".a > p#b, h1 {\n  color: rgb(255, 0, 0);\n  margin-top: 0 !important;\n  \
+            margin-right: auto !important;\n  margin-bottom: 0 !important;\n  margin-left: auto !important;\n}");
+        assert_eq!(minified, ".a>p#b,h1{color:#f00;margin-top:0!important;margin-right:auto!important;\
+            margin-bottom:0!important;margin-left:auto!important}");
+    }
+
+    #[test]
+    fn media_rules() {
+        let (canonical, minified) = round_trip(
+            "@media screen and (min-width: 600px), print and (orientation: landscape) { p { color: blue } }");
+        assert_eq!(canonical, "@media screen and (min-width: 600px), print and (orientation: landscape) {\n  \
+            p {\n    color: rgb(0, 0, 255);\n  }\n}");
+        assert_eq!(minified, "@media screen and (min-width:600px),print and (orientation:landscape){p{color:#00f}}");
+        let (_, minified) = round_trip("@media (400px <= width < 800px) and (not (hover)) { p { color: blue } }");
+        assert_eq!(minified, "@media (400px<=width<800px) and (not (hover)){p{color:#00f}}");
+    }
+
+    #[test]
+    fn supports_rules() {
+        let (_, minified) = round_trip("@supports (display: grid) and (not (display: ruby)) { p { display: grid } }");
+        assert_eq!(minified, "@supports (display:grid) and (not (display:ruby)){p{display:grid}}");
+        let (canonical, _) = round_trip(
+            "@supports selector(li:nth-child(2n of .a)) or (color: rgb(1 2 3)) { p { color: red } }");
+        assert!(canonical.starts_with("@supports selector(li:nth-child(2n of .a)) or (color: rgb(1 2 3)) {\n"));
+    }
+
+    #[test]
+    fn layer_rules() {
+        let (canonical, minified) = round_trip(
+            "@layer base, theme; @layer base { p { color: red } } @layer { p { color: blue } }");
+        assert_eq!(canonical, "@layer base, theme;\n@layer base {\n  p {\n    color: rgb(255, 0, 0);\n  }\n}\n\
+            @layer {\n  p {\n    color: rgb(0, 0, 255);\n  }\n}");
+        assert_eq!(minified, "@layer base,theme;@layer base{p{color:#f00}}@layer{p{color:#00f}}");
+    }
+
+    #[test]
+    fn font_face_rules() {
+        let (canonical, minified) = round_trip("@font-face { font-family: \"My Font\"; \
+            src: local(\"My Font\"), url(\"a.woff2\") format(\"woff2\"); font-weight: 300 700; \
+            font-style: italic; unicode-range: U+0-7F, U+4??; }");
+        assert_eq!(canonical, "@font-face {\n  font-family: \"My Font\";\n  \
+            src: local(\"My Font\"), url(\"a.woff2\") format(\"woff2\");\n  font-weight: 300 700;\n  \
+            font-style: italic;\n  unicode-range: U+0-7F, U+400-4FF;\n}");
+        assert_eq!(minified, "@font-face{font-family:\"My Font\";src:local(\"My Font\"),url(\"a.woff2\") \
+            format(\"woff2\");font-weight:300 700;font-style:italic;unicode-range:U+0-7F,U+400-4FF}");
+    }
+
+    #[test]
+    fn import_rules() {
+        let (canonical, minified) = round_trip(
+            "@import url(\"a.css\") layer(base) screen; @import 'b.css' layer; p { color: red }");
+        assert_eq!(canonical, "@import \"a.css\" layer(base) screen;\n@import \"b.css\" layer;\n\
+            p {\n  color: rgb(255, 0, 0);\n}");
+        assert_eq!(minified, "@import \"a.css\" layer(base) screen;@import \"b.css\" layer;p{color:#f00}");
+    }
+
+    #[test]
+    fn math_functions() {
+        let (canonical, minified) = round_trip("p { width: calc(100% - 2 * (1em + 4px)); \
+            height: clamp(10px, 50% / 3, 200px); margin-left: calc(-0.5px * 2) }");
+        assert_eq!(canonical, "p {\n  width: calc(100% - 2 * (1em + 4px));\n  \
+            height: clamp(10px, 50% / 3, 200px);\n  margin-left: calc(-0.5px * 2);\n}");
+        assert_eq!(minified, "p{width:calc(100% - 2*(1em + 4px));height:clamp(10px,50%/3,200px);\
+            margin-left:calc(-.5px*2)}");
+    }
+
+    #[test]
+    fn custom_properties_and_var() {
+        let (canonical, minified) = round_trip(
+            "p { --gap: 4px 8px; margin: var(--gap); padding-left: var(--missing, 2px) }");
+        assert_eq!(canonical, "p {\n  --gap: 4px 8px;\n  margin: var(--gap);\n  padding-left: var(--missing, 2px);\n}");
+        assert_eq!(minified, "p{--gap:4px 8px;margin:var(--gap);padding-left:var(--missing, 2px)}");
+    }
+
+    #[test]
+    fn nth_selectors() {
+        let (_, minified) = round_trip(
+            "li:nth-child(2n+1 of .a, #b), li:nth-last-child(-n+3), li:nth-of-type(odd) { color: red }");
+        assert_eq!(minified, "li:nth-child(2n+1 of .a,#b),li:nth-last-child(-n+3),li:nth-of-type(2n+1){color:#f00}");
+    }
+
+    #[test]
+    fn strings() {
+        let (canonical, _) = round_trip("p::before { content: \"a \\\"quoted\\\" \\\\ string\\A\" attr(title) \
+            counter(item, upper-roman) counters(item, \".\") open-quote }");
+        assert_eq!(canonical, "p::before {\n  content: \"a \\\"quoted\\\" \\\\ string\\a \" attr(title) \
+            counter(item, upper-roman) counters(item, \".\") open-quote;\n}");
+        let (_, minified) = round_trip("p { font-family: \"Times New Roman\", serif; quotes: '«' \"»\" }");
+        assert_eq!(minified, "p{font-family:\"Times New Roman\",serif;quotes:\"«\" \"»\"}");
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use crate::computed::ComputedStyle;
+use crate::dom::ElementData;
+use crate::style::PropertyMap;
+
+/// How many styles the cache keeps for the children of one element. Siblings that could share
+/// usually come in long runs, so a few recent styles catch most of them.
+const CACHE_SIZE: usize = 16;
+
+/// An element's computed values, which its children inherit from, and its typed style.
+#[derive(Clone)]
+pub(crate) struct SharedStyle {
+    pub values: Arc<PropertyMap>,
+    pub style: Arc<ComputedStyle>,
+}
+
+impl SharedStyle {
+    pub fn new(values: PropertyMap) -> SharedStyle {
+        let style = Arc::new(ComputedStyle::from_values(&values));
+        SharedStyle { values: Arc::new(values), style }
+    }
+}
+
+/// The styles of recently styled children of one element, so that siblings with the same style
+/// inputs can skip the cascade. Since the siblings have the same parent, ancestors and parent
+/// style, the inputs left are those of the element itself: its tag name and classes, and the
+/// results of the selectors that look at its position, siblings or children. Elements with an
+/// id don't share, since an id is meant to pick out a single element.
+#[derive(Default)]
+pub(crate) struct StyleSharingCache<'a> {
+    entries: VecDeque<Entry<'a>>,
+    /// The style of text, which inherits everything from the parent.
+    text: Option<SharedStyle>,
+}
+
+struct Entry<'a> {
+    key: SharingKey<'a>,
+    style: SharedStyle,
+}
+
+#[derive(PartialEq)]
+pub(crate) struct SharingKey<'a> {
+    tag_name: &'a str,
+    /// Sorted, without duplicates.
+    classes: Vec<&'a str>,
+    /// Which of the candidate selectors that need revalidating match the element.
+    revalidation: Vec<bool>,
+}
+
+impl<'a> SharingKey<'a> {
+    /// The key of an element that can share a style, or None if it has an id. `revalidation`
+    /// matches the selectors that need revalidating against it.
+    pub fn new(elem: &'a ElementData, revalidation: impl FnOnce() -> Vec<bool>) -> Option<SharingKey<'a>> {
+        if elem.id().is_some() {
+            return None;
+        }
+        let mut classes: Vec<&str> = elem.classes().into_iter().collect();
+        classes.sort_unstable();
+        Some(SharingKey { tag_name: &elem.tag_name, classes, revalidation: revalidation() })
+    }
+}
+
+impl<'a> StyleSharingCache<'a> {
+    /// The style of an earlier sibling with the same key. The entry moves to the front, so the
+    /// least recently used one is evicted first.
+    pub fn get(&mut self, key: &SharingKey<'a>) -> Option<SharedStyle> {
+        let index = self.entries.iter().position(|entry| entry.key == *key)?;
+        let entry = self.entries.remove(index)?;
+        let style = entry.style.clone();
+        self.entries.push_front(entry);
+        Some(style)
+    }
+
+    pub fn insert(&mut self, key: SharingKey<'a>, style: SharedStyle) {
+        if self.entries.len() == CACHE_SIZE {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(Entry { key, style });
+    }
+
+    /// The style of a text child, computed by `compute` the first time it's needed.
+    pub fn text_style(&mut self, compute: impl FnOnce() -> PropertyMap) -> SharedStyle {
+        self.text.get_or_insert_with(|| SharedStyle::new(compute())).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::css::{self, Color};
+    use crate::dom::NodeType;
+    use crate::html;
+    use crate::media::MediaEnvironment;
+    use crate::style::{style_tree_with_stats, Cascade, StyleNode, StyleStats};
+
+    /// Style a list, and check the styles of its items and the statistics.
+    fn style_list(html: &str, css: &str, check: impl FnOnce(&[&StyleNode], StyleStats)) {
+        let root = html::parse(html.to_string());
+        let stylesheet = css::parse(css.to_string());
+        let (styles, stats) = style_tree_with_stats(&root, &Cascade::author(&stylesheet), &MediaEnvironment::screen(800.0, 600.0));
+        let items: Vec<&StyleNode> = styles.children.iter()
+            .filter(|child| matches!(child.node.node_type, NodeType::Element(ref elem) if elem.tag_name == "li"))
+            .collect();
+        check(&items, stats);
+    }
+
+    #[test]
+    fn siblings_that_match_different_selectors_dont_share() {
+        style_list("<ul><li class=\"a\">1</li><li class=\"a\">2</li><li class=\"a\">3</li>\
+            <li class=\"b\">4</li><li class=\"a\">5</li><li class=\"a\">6</li></ul>", "li:first-child { color: red } li:nth-child(3) { color: green } \
+            .b + li { color: blue }", |items, stats| {
+            let colors: Vec<Color> = items.iter().map(|item| item.style.color).collect();
+            let (black, red) = (Color { r: 0, g: 0, b: 0, a: 255 }, Color { r: 255, g: 0, b: 0, a: 255 });
+            let (green, blue) = (Color { r: 0, g: 128, b: 0, a: 255 }, Color { r: 0, g: 0, b: 255, a: 255 });
+            assert_eq!(colors, [red, black, green, black, blue, black]);
+            // Only the second and last items have the same style inputs.
+            assert!(Arc::ptr_eq(&items[1].style, &items[5].style));
+            assert_eq!(stats.shared, 1);
+        });
+    }
+
+    #[test]
+    fn identical_siblings_share() {
+        let html = format!("<ul>{}</ul>", "<li class=\"item\">Item</li>".repeat(10));
+        style_list(&html, "li { color: red } .item:not(:empty) { margin-left: 1px }", |items, stats| {
+            assert!(items.iter().all(|item| Arc::ptr_eq(&item.style, &items[0].style)));
+            assert_eq!(stats.elements, 11);
+            assert_eq!(stats.sharing_candidates, 11);
+            assert_eq!(stats.shared, 9);
+        });
+    }
+}
@@ -1,111 +1,1534 @@
 use std::collections::HashMap;
-use crate::css::{Rule, Stylesheet};
-use crate::css::{Selector, SimpleSelector, Specificity, Value};
-use crate::css::Selector::Simple;
+use std::sync::{Arc, OnceLock};
+use crate::css::{Combinator, ContentItem, Nth, PseudoClass, PseudoElement, RelativeSelector, Rule};
+use crate::css::{Color, Selector, SimpleSelector, Specificity, Stylesheet, Unit, Value};
 use crate::css::Value::Keyword;
+use crate::computed::{self, ComputedStyle, Content, Display, DisplayInside};
 use crate::dom::{ElementData, Node};
+use crate::css::is_custom_property;
+use crate::{media, properties, variables};
+use crate::css::{CssRule, Declaration, FontFaceRule};
+use crate::media::MediaEnvironment;
+use crate::rule_map::{AncestorFilter, IndexedSelector, RuleMap};
+use crate::restyle::{subtree_damage, Damage};
+use crate::sharing::{SharedStyle, SharingKey, StyleSharingCache};
 use crate::dom::NodeType::{Element, Text};
 
-/// todo
-/// Cascading
-/// Initial and/or computed values
-/// Inheritance
-/// The style attribute
+// todo
+// The style attribute
 
 // Map of css property names to values
-type PropertyMap = HashMap<String, Value>;
+pub(crate) type PropertyMap = HashMap<String, Value>;
 
 // A node with associated style data
 pub struct StyleNode<'a> {
-    node: &'a Node,
-    specified_values: PropertyMap,
+    pub node: &'a Node,
+    /// Set for boxes generated by `::before`, `::after` and `::marker`, and for the text inside
+    /// them.
+    /// `node` is then the originating element.
+    pub pseudo: Option<PseudoElement>,
+    /// The text of a generated content box.
+    pub generated_text: Option<String>,
+    /// Shared between nodes whose style is the same, such as siblings that match the same rules.
+    pub style: Arc<ComputedStyle>,
+    /// The computed value of every property, including custom properties.
+    values: Arc<PropertyMap>,
     pub(crate) children: Vec<StyleNode<'a>>,
 }
 
-pub enum Display {
-    Inline,
-    Block,
-    None,
+/// Two style trees are equal if they style the same nodes the same way.
+impl PartialEq for StyleNode<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.node, other.node)
+            && self.pseudo == other.pseudo
+            && self.generated_text == other.generated_text
+            && self.style == other.style
+            && self.children == other.children
+    }
+}
+
+/// The size of the viewport, which `vw`, `vh`, `vmin` and `vmax` are relative to.
+#[derive(Clone, Copy, Debug)]
+struct Viewport {
+    width: f32,
+    height: f32,
+}
+
+/// The font size of the `medium` keyword, and of the root element by default.
+const MEDIUM_FONT_SIZE: f32 = 16.0;
+
+/// What an element's relative lengths are resolved against, besides its parent's values.
+#[derive(Clone, Copy)]
+struct ComputeContext {
+    /// The root element's computed font size, for `rem`.
+    root_font_size: f32,
+    viewport: Viewport,
+}
+
+/// The color of the root element if nothing sets it.
+const BLACK: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+
+impl<'a> StyleNode<'a> {
+    fn shared(node: &'a Node, style: &SharedStyle) -> StyleNode<'a> {
+        StyleNode {
+            node,
+            pseudo: None,
+            generated_text: None,
+            style: style.style.clone(),
+            values: style.values.clone(),
+            children: Vec::new(),
+        }
+    }
+
+    /// The computed value of a property, or of a custom property that is set.
+    pub fn computed_value(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// The computed value of every property, and of every custom property that is set, sorted by
+    /// name.
+    pub fn computed_values(&self) -> Vec<(&str, &Value)> {
+        let mut values: Vec<(&str, &Value)> = self.values.iter().map(|(name, value)| (&**name, value)).collect();
+        values.sort_by_key(|&(name, _)| name);
+        values
+    }
+
+    /// The style node of `node`, or of one of its pseudo-element boxes, in this subtree.
+    pub fn find(&self, node: &Node, pseudo: Option<PseudoElement>) -> Option<&StyleNode<'a>> {
+        if std::ptr::eq(self.node, node) && self.pseudo == pseudo && self.generated_text.is_none() {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(node, pseudo))
+    }
+
+    /// The text of a text node or of generated content.
+    pub fn text(&self) -> Option<&str> {
+        match (&self.generated_text, &self.node.node_type) {
+            (Some(text), _) => Some(text),
+            (None, Text(text)) if self.pseudo.is_none() => Some(text),
+            _ => None
+        }
+    }
+}
+
+/// An element together with its place in the document, so that selectors can look at its
+/// parent and siblings. Parents are borrowed from the caller's stack as the tree is walked.
+#[derive(Clone, Copy)]
+pub struct ElementRef<'a, 'p> {
+    pub node: &'a Node,
+    pub parent: Option<&'p ElementRef<'a, 'p>>,
+    /// Index of `node` in its parent's `children`.
+    pub index: usize,
 }
-impl StyleNode<'_> {
-    pub(crate) fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).map(|v| v.clone())
+
+impl<'a, 'p> ElementRef<'a, 'p> {
+    pub fn root(node: &'a Node) -> ElementRef<'a, 'p> {
+        ElementRef { node, parent: None, index: 0 }
     }
-    pub(crate) fn display(&self) -> Display {
-        match self.value("display") {
-            Some(Keyword(s)) => match &*s {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline
-            },
-            _ => Display::Inline
+
+    pub fn data(&self) -> &'a ElementData {
+        match self.node.node_type {
+            Element(ref data) => data,
+            Text(_) => panic!("ElementRef must point at an element node")
         }
     }
 
-    /// Return the specified value of property `name`, or property `fallback_name` if that doesn't
-    /// exist, or value `default` if neither does.
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name).unwrap_or_else(|| self.value(fallback_name)
-            .unwrap_or_else(|| default.clone()))
+    /// The element children of this element.
+    pub fn children<'q>(&'q self) -> impl Iterator<Item=ElementRef<'a, 'q>> + 'q {
+        self.node.children.iter().enumerate()
+            .filter(|(_, child)| matches!(child.node_type, Element(_)))
+            .map(move |(index, node)| ElementRef { node, parent: Some(self), index })
+    }
+
+    /// The element siblings before this one, nearest first.
+    fn preceding_siblings(&self) -> impl Iterator<Item=ElementRef<'a, 'p>> + '_ {
+        let siblings = self.parent.map_or(&[][..], |parent| &parent.node.children[..self.index]);
+        siblings.iter().enumerate().rev()
+            .filter(|(_, node)| matches!(node.node_type, Element(_)))
+            .map(move |(index, node)| ElementRef { node, parent: self.parent, index })
+    }
+
+    /// The element siblings after this one, nearest first.
+    fn following_siblings(&self) -> impl Iterator<Item=ElementRef<'a, 'p>> + '_ {
+        let siblings = self.parent.map_or(&[][..], |parent| &parent.node.children[self.index + 1..]);
+        siblings.iter().enumerate()
+            .filter(|(_, node)| matches!(node.node_type, Element(_)))
+            .map(move |(offset, node)| ElementRef { node, parent: self.parent, index: self.index + 1 + offset })
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item=&'p ElementRef<'a, 'p>> {
+        std::iter::successors(self.parent, |elem| elem.parent)
     }
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+pub(crate) fn matches(elem: &ElementRef, selector: &Selector) -> bool {
     match *selector {
-        Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector)
+        Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector),
+        Selector::Complex(ref left, combinator, ref right) => {
+            matches_simple_selector(elem, right) && match combinator {
+                Combinator::Descendant => elem.ancestors().any(|a| matches(a, left)),
+                Combinator::Child => elem.parent.is_some_and(|p| matches(p, left)),
+                Combinator::NextSibling => elem.preceding_siblings().next().is_some_and(|s| matches(&s, left)),
+                Combinator::SubsequentSibling => elem.preceding_siblings().any(|s| matches(&s, left)),
+            }
+        }
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
+fn matches_simple_selector(elem: &ElementRef, selector: &SimpleSelector) -> bool {
+    let data = elem.data();
     // check type selector
-    if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
+    if selector.tag_name.iter().any(|name| data.tag_name != *name) {
         return false;
     }
     // check ID Selector
-    if selector.id.iter().any(|id| elem.id() != Some(id)){
+    if selector.id.iter().any(|id| data.id() != Some(id)){
         return false;
     }
     // check class selector
-    let elem_classes = elem.classes();
+    let elem_classes = data.classes();
     if selector.class.iter().any(|class| !elem_classes.contains(&**class)) {
         return false;
     }
+    // check pseudo-classes
+    selector.pseudo_classes.iter().all(|pseudo| matches_pseudo_class(elem, pseudo))
+}
+
+fn matches_pseudo_class(elem: &ElementRef, pseudo: &PseudoClass) -> bool {
+    let same_type = |sibling: &ElementRef| sibling.data().tag_name == elem.data().tag_name;
+    let any_of = |sibling: &ElementRef, of: &[Selector]| of.is_empty() || matches_any(sibling, of);
+    match *pseudo {
+        PseudoClass::Root => elem.parent.is_none(),
+        PseudoClass::Empty => elem.node.children.iter().all(|child| match child.node_type {
+            Text(ref text) => text.trim().is_empty(),
+            Element(_) => false,
+        }),
+        PseudoClass::FirstChild => elem.preceding_siblings().next().is_none(),
+        PseudoClass::LastChild => elem.following_siblings().next().is_none(),
+        PseudoClass::OnlyChild => {
+            elem.preceding_siblings().next().is_none() && elem.following_siblings().next().is_none()
+        }
+        PseudoClass::FirstOfType => !elem.preceding_siblings().any(|s| same_type(&s)),
+        PseudoClass::LastOfType => !elem.following_siblings().any(|s| same_type(&s)),
+        PseudoClass::OnlyOfType => {
+            !elem.preceding_siblings().any(|s| same_type(&s)) && !elem.following_siblings().any(|s| same_type(&s))
+        }
+        PseudoClass::NthChild(nth, ref of) => {
+            any_of(elem, of) && matches_nth(nth, elem.preceding_siblings().filter(|s| any_of(s, of)).count())
+        }
+        PseudoClass::NthLastChild(nth, ref of) => {
+            any_of(elem, of) && matches_nth(nth, elem.following_siblings().filter(|s| any_of(s, of)).count())
+        }
+        PseudoClass::NthOfType(nth) => matches_nth(nth, elem.preceding_siblings().filter(same_type).count()),
+        PseudoClass::NthLastOfType(nth) => matches_nth(nth, elem.following_siblings().filter(same_type).count()),
+        PseudoClass::Not(ref list) => !matches_any(elem, list),
+        PseudoClass::Is(ref list) | PseudoClass::Where(ref list) => matches_any(elem, list),
+        PseudoClass::Has(ref list) => list.iter().any(|relative| matches_relative(elem, relative)),
+    }
+}
+
+/// Match an `An+B` pseudo-class given the number of qualifying siblings before the element.
+fn matches_nth(nth: Nth, siblings_before: usize) -> bool {
+    nth.matches(siblings_before as i32 + 1)
+}
+
+fn matches_any(elem: &ElementRef, selectors: &[Selector]) -> bool {
+    selectors.iter().any(|selector| matches(elem, selector))
+}
+
+/// Does any element related to `anchor` by the relative selector's combinator match it?
+fn matches_relative(anchor: &ElementRef, relative: &RelativeSelector) -> bool {
+    match relative.combinator {
+        Combinator::Descendant => matches_descendant(anchor, &relative.selector),
+        Combinator::Child => anchor.children().any(|child| matches(&child, &relative.selector)),
+        Combinator::NextSibling => anchor.following_siblings().next()
+            .is_some_and(|sibling| matches(&sibling, &relative.selector)),
+        Combinator::SubsequentSibling => anchor.following_siblings()
+            .any(|sibling| matches(&sibling, &relative.selector)),
+    }
+}
 
-    return true;
+fn matches_descendant(elem: &ElementRef, selector: &Selector) -> bool {
+    elem.children().any(|child| matches(&child, selector) || matches_descendant(&child, selector))
 }
 
-type MatchedRule<'a> = (Specificity, &'a Rule);
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
-    rule.selectors.iter()
-        .find(|selector| matches(elem, *selector))
-        .map(|selector| (selector.specificity(), rule))
+/// Where a style sheet comes from, which decides how its declarations cascade against others.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
 }
 
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
-    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect()
+/// The style sheets that apply to a document, grouped by origin, each in source order.
+pub struct Cascade<'a> {
+    /// Apply the built-in HTML style sheet before the other user-agent sheets. Tests can turn
+    /// this off to see the effect of their own rules only.
+    pub default_user_agent: bool,
+    pub user_agent: Vec<&'a Stylesheet>,
+    pub user: Vec<&'a Stylesheet>,
+    pub author: Vec<&'a Stylesheet>,
+}
+
+impl<'a> Cascade<'a> {
+    /// A cascade with a single author style sheet.
+    pub fn author(stylesheet: &'a Stylesheet) -> Cascade<'a> {
+        Cascade { author: vec![stylesheet], ..Default::default() }
+    }
+
+    /// Every style sheet with its origin, in source order.
+    fn stylesheets(&self) -> impl Iterator<Item=(Origin, &'a Stylesheet)> + '_ {
+        fn with_origin<'s, 'a>(origin: Origin, sheets: &'s [&'a Stylesheet]) -> impl Iterator<Item=(Origin, &'a Stylesheet)> + 's {
+            sheets.iter().map(move |&sheet| (origin, sheet))
+        }
+        let default_sheet = self.default_user_agent.then(default_user_agent_stylesheet);
+        default_sheet.into_iter().map(|sheet| (Origin::UserAgent, sheet))
+            .chain(with_origin(Origin::UserAgent, &self.user_agent))
+            .chain(with_origin(Origin::User, &self.user))
+            .chain(with_origin(Origin::Author, &self.author))
+    }
+
+    /// The style rules of every style sheet, including imported ones, in cascade order, leaving
+    /// out those inside `@media` rules, imports and `@supports` rules that don't apply.
+    pub(crate) fn active_rules(&self, media: &MediaEnvironment) -> Vec<ActiveRule<'a>> {
+        let collector = self.collect(media);
+        // Rank the layers: sublayers in the order they were declared, and each layer's own
+        // rules after its sublayers. Rules outside any layer come last.
+        let layer_key = |path: &[u32]| path.iter().copied().chain([u32::MAX]).collect::<Vec<_>>();
+        let mut keys: Vec<Vec<u32>> = collector.rules.iter().map(|(_, path, _)| layer_key(path)).collect();
+        keys.sort();
+        keys.dedup();
+        collector.rules.into_iter()
+            .map(|(origin, path, rule)| (origin, keys.binary_search(&layer_key(&path)).unwrap() as u32, rule))
+            .collect()
+    }
+
+    /// The `@font-face` rules that apply in the media environment, in cascade order.
+    pub fn font_faces(&self, media: &MediaEnvironment) -> Vec<&'a FontFaceRule> {
+        self.collect(media).font_faces
+    }
+
+    fn collect<'m>(&self, media: &'m MediaEnvironment) -> RuleCollector<'a, 'm> {
+        let mut collector = RuleCollector { media, layers: LayerOrder::default(), rules: Vec::new(), font_faces: Vec::new() };
+        for (origin, stylesheet) in self.stylesheets() {
+            collector.collect(origin, &stylesheet.rules, &Layer::default());
+        }
+        collector
+    }
 }
 
-fn specified_values(elem: &ElementData, style_sheet: &Stylesheet) -> PropertyMap {
-    let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, style_sheet);
+/// A style rule that applies in the current media environment, with the origin of its sheet
+/// and the rank of its cascade layer, which is higher for layers that take precedence.
+pub(crate) type ActiveRule<'a> = (Origin, u32, &'a Rule);
+
+/// Gathers the rules of style sheets that apply in a media environment.
+struct RuleCollector<'a, 'm> {
+    media: &'m MediaEnvironment,
+    layers: LayerOrder,
+    /// Style rules with their origin and the path to their layer.
+    rules: Vec<(Origin, Vec<u32>, &'a Rule)>,
+    font_faces: Vec<&'a FontFaceRule>,
+}
 
-    //go through rules from low to hi specificity.
-    rules.sort_by(|&(a,_), &(b, _)| a.cmp(&b));
-    for(_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+impl<'a> RuleCollector<'a, '_> {
+    /// Collect rules that are inside `layer`.
+    fn collect(&mut self, origin: Origin, rules: &'a [CssRule], layer: &Layer) {
+        for rule in rules {
+            match rule {
+                CssRule::Style(rule) => self.rules.push((origin, layer.path.clone(), rule)),
+                CssRule::Media(media_rule) => if media::matches(&media_rule.queries, self.media) {
+                    self.collect(origin, &media_rule.rules, layer);
+                },
+                CssRule::Supports(supports) => if supports.supported {
+                    self.collect(origin, &supports.rules, layer);
+                },
+                // Imported rules take the place of the `@import`.
+                CssRule::Import(import) => match &import.stylesheet {
+                    Some(imported) if media::matches(&import.queries, self.media) => {
+                        let layer = match &import.layer {
+                            Some(name) => self.layers.declare(origin, layer, name),
+                            None => layer.clone(),
+                        };
+                        self.collect(origin, &imported.rules, &layer);
+                    }
+                    _ => {}
+                },
+                CssRule::Layer(layer_rule) => {
+                    let sublayer = self.layers.declare(origin, layer, &layer_rule.name);
+                    self.collect(origin, &layer_rule.rules, &sublayer);
+                }
+                CssRule::LayerStatement(names) => for name in names {
+                    self.layers.declare(origin, layer, name);
+                },
+                CssRule::FontFace(font_face) => self.font_faces.push(font_face),
+            }
         }
     }
-    return values;
 }
 
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyleNode<'a> {
-    StyleNode {
-        node: root,
-        specified_values: match root.node_type {
-            Element(ref elem) => specified_values(elem, stylesheet),
-            Text(_) => HashMap::new()
-        },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect()
+/// A cascade layer: its full name, and the index of each layer on the way to it among its
+/// siblings, in the order they were declared. The implicit outermost layer has an empty path.
+#[derive(Clone, Default)]
+struct Layer {
+    name: String,
+    path: Vec<u32>,
+}
+
+/// The cascade layers declared so far, which are separate for each origin.
+#[derive(Default)]
+struct LayerOrder {
+    /// The path to each named layer, by origin and full name.
+    named: HashMap<(Origin, String), Vec<u32>>,
+    /// The number of sublayers declared in each layer.
+    sublayers: HashMap<(Origin, Vec<u32>), u32>,
+}
+
+impl LayerOrder {
+    /// Find the layer `name` inside `parent`, declaring it and the layers on the way to it if
+    /// they're new. An empty name declares a new anonymous layer.
+    fn declare(&mut self, origin: Origin, parent: &Layer, name: &str) -> Layer {
+        if name.is_empty() {
+            return self.sublayer(origin, parent, None);
+        }
+        name.split('.').fold(parent.clone(), |layer, part| self.sublayer(origin, &layer, Some(part)))
+    }
+
+    fn sublayer(&mut self, origin: Origin, parent: &Layer, name: Option<&str>) -> Layer {
+        let full_name = name.map(|name| match &*parent.name {
+            "" => name.to_string(),
+            parent => format!("{}.{}", parent, name),
+        });
+        if let Some(path) = full_name.as_ref().and_then(|name| self.named.get(&(origin, name.clone()))) {
+            return Layer { name: full_name.unwrap(), path: path.clone() };
+        }
+        let count = self.sublayers.entry((origin, parent.path.clone())).or_default();
+        let mut path = parent.path.clone();
+        path.push(*count);
+        *count += 1;
+        match full_name {
+            Some(name) => {
+                self.named.insert((origin, name.clone()), path.clone());
+                Layer { name, path }
+            }
+            // Anonymous layers can't be named again, but their sublayers still need unique names.
+            None => Layer { name: format!("<anonymous {:?}>", path), path },
+        }
+    }
+}
+
+impl Default for Cascade<'_> {
+    fn default() -> Self {
+        Cascade { default_user_agent: true, user_agent: Vec::new(), user: Vec::new(), author: Vec::new() }
+    }
+}
+
+/// The built-in style sheet that gives HTML elements their default display types, margins and
+/// fonts.
+pub fn default_user_agent_stylesheet() -> &'static Stylesheet {
+    static STYLESHEET: OnceLock<Stylesheet> = OnceLock::new();
+    STYLESHEET.get_or_init(|| crate::css::parse(include_str!("user_agent.css").to_string()))
+}
+
+/// The precedence of a declaration's origin and importance, lowest first:
+/// normal user-agent, normal user, normal author, important author, important user,
+/// important user-agent.
+fn cascade_level(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, origin) => origin as u8,
+        (true, origin) => 5 - origin as u8,
+    }
+}
+
+/// A rule that matches an element, with the selector that matched.
+struct MatchedRule<'a> {
+    origin: Origin,
+    layer: u32,
+    selector: &'a Selector,
+    specificity: Specificity,
+    rule: &'a Rule,
+}
+
+/// The rules that match an element, or one of its pseudo-elements, in cascade order.
+/// `candidates` are the element's candidate selectors from `rules`.
+fn matching_rules<'a>(elem: &ElementRef, pseudo: Option<PseudoElement>, rules: &RuleMap<'a>,
+                      candidates: &[&IndexedSelector<'a>]) -> Vec<MatchedRule<'a>> {
+    let mut matched: Vec<MatchedRule<'a>> = Vec::new();
+    let mut matched_rule = None;
+    for &candidate in candidates {
+        // A rule's selectors come most specific first, so the first one that matches is the
+        // one whose specificity counts.
+        if matched_rule == Some(candidate.rule) || candidate.selector.pseudo_element() != pseudo
+            || !matches(elem, candidate.selector) {
+            continue;
+        }
+        let (origin, layer, rule) = rules.rule(candidate.rule);
+        matched.push(MatchedRule { origin, layer, selector: candidate.selector, specificity: candidate.specificity, rule });
+        matched_rule = Some(candidate.rule);
+    }
+    matched
+}
+
+/// The cascaded values for an element, or for one of its pseudo-elements: the winning
+/// declaration for each property that any matching rule sets.
+fn specified_values(elem: &ElementRef, pseudo: Option<PseudoElement>, rules: &RuleMap,
+                    candidates: &[&IndexedSelector]) -> PropertyMap {
+    specified_values_from(matching_rules(elem, pseudo, rules, candidates))
+}
+
+fn specified_values_from(matched: Vec<MatchedRule>) -> PropertyMap {
+    cascade_declarations(matched).into_iter()
+        .filter_map(|(name, candidates)| Some((name.to_string(), winning_declaration(&candidates)?.declaration.value.clone())))
+        .collect()
+}
+
+/// The declarations that apply to an element, or to one of its pseudo-elements, grouped by
+/// property in cascade order. `ancestors` holds the element's ancestors.
+pub(crate) fn matched_declarations<'a>(elem: &ElementRef, pseudo: Option<PseudoElement>, rules: &RuleMap<'a>,
+                                       ancestors: &AncestorFilter) -> HashMap<&'a str, Vec<MatchedDeclaration<'a>>> {
+    let candidates = rules.candidates(elem.data(), ancestors);
+    cascade_declarations(matching_rules(elem, pseudo, rules, &candidates))
+}
+
+/// A declaration from a rule that matches an element, with where it came from.
+#[derive(Clone, Copy, Debug)]
+pub struct MatchedDeclaration<'a> {
+    pub declaration: &'a Declaration,
+    /// The selector of the rule that matched the element.
+    pub selector: &'a Selector,
+    pub specificity: Specificity,
+    pub origin: Origin,
+    /// The rank of the rule's cascade layer, where later layers rank higher and rules outside
+    /// any layer rank highest.
+    pub layer: u32,
+}
+
+/// The declarations of the matching rules, grouped by property, each from lowest to highest
+/// precedence: by origin and importance, then cascade layer, then specificity. Layer order is
+/// reversed for important declarations. The sort is stable, so later declarations still win
+/// ties.
+fn cascade_declarations<'a>(matched: Vec<MatchedRule<'a>>) -> HashMap<&'a str, Vec<MatchedDeclaration<'a>>> {
+    let mut declarations: Vec<MatchedDeclaration> = matched.into_iter()
+        .flat_map(|matched| matched.rule.declarations.iter().map(move |declaration| MatchedDeclaration {
+            declaration,
+            selector: matched.selector,
+            specificity: matched.specificity,
+            origin: matched.origin,
+            layer: matched.layer,
+        }))
+        .collect();
+    declarations.sort_by_key(|matched| {
+        let important = matched.declaration.important;
+        let layer = if important { u32::MAX - matched.layer } else { matched.layer };
+        (cascade_level(matched.origin, important), layer, matched.specificity)
+    });
+    let mut by_property: HashMap<&str, Vec<MatchedDeclaration>> = HashMap::new();
+    for matched in declarations {
+        by_property.entry(&matched.declaration.name).or_default().push(matched);
+    }
+    by_property
+}
+
+/// The declaration that sets a property, from its declarations in cascade order. `revert` rolls
+/// back to the value the property would have without its origin's declarations, and
+/// `revert-layer` to the value without its layer's declarations. If that leaves nothing, the
+/// property isn't set.
+pub(crate) fn winning_declaration<'d, 'a>(candidates: &'d [MatchedDeclaration<'a>]) -> Option<&'d MatchedDeclaration<'a>> {
+    let mut candidates: Vec<&MatchedDeclaration> = candidates.iter().collect();
+    loop {
+        let last = *candidates.last()?;
+        match last.declaration.value {
+            Keyword(ref keyword) if keyword == "revert" => candidates.retain(|c| c.origin != last.origin),
+            Keyword(ref keyword) if keyword == "revert-layer" => {
+                candidates.retain(|c| (c.origin, c.layer) != (last.origin, last.layer))
+            }
+            _ => return Some(last),
+        }
+    }
+}
+
+/// Counts from styling a document, for looking into its performance.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct StyleStats {
+    pub elements: usize,
+    /// Elements that could share a style with a sibling, because they have no id.
+    pub sharing_candidates: usize,
+    /// Elements that took their style from the style sharing cache.
+    pub shared: usize,
+}
+
+impl StyleStats {
+    #[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+    fn add(&mut self, other: StyleStats) {
+        self.elements += other.elements;
+        self.sharing_candidates += other.sharing_candidates;
+        self.shared += other.shared;
+    }
+
+    /// The fraction of candidates that found a style in the cache.
+    pub fn hit_rate(&self) -> f32 {
+        if self.sharing_candidates == 0 { 0.0 } else { self.shared as f32 / self.sharing_candidates as f32 }
+    }
+}
+
+/// Style a document for the given media environment, whose viewport size is also what
+/// viewport-relative lengths refer to.
+pub fn style_tree<'a>(root: &'a Node, cascade: &Cascade<'a>, media: &MediaEnvironment) -> StyleNode<'a> {
+    style_tree_with_stats(root, cascade, media).0
+}
+
+/// Style a document, and count how the work went.
+pub fn style_tree_with_stats<'a>(root: &'a Node, cascade: &Cascade<'a>, media: &MediaEnvironment)
+                                 -> (StyleNode<'a>, StyleStats) {
+    traverse(root, cascade, media, false)
+}
+
+/// Style a document like `style_tree`, styling sibling subtrees in parallel. The tree is the
+/// same as `style_tree` gives.
+#[cfg(feature = "parallel")]
+pub fn parallel_style_tree<'a>(root: &'a Node, cascade: &Cascade<'a>, media: &MediaEnvironment) -> StyleNode<'a> {
+    parallel_style_tree_with_stats(root, cascade, media).0
+}
+
+/// Style a document in parallel, and count how the work went. Fewer styles may be shared
+/// than with `style_tree_with_stats`, since siblings styled on different threads don't see
+/// each other's styles.
+#[cfg(feature = "parallel")]
+pub fn parallel_style_tree_with_stats<'a>(root: &'a Node, cascade: &Cascade<'a>, media: &MediaEnvironment)
+                                          -> (StyleNode<'a>, StyleStats) {
+    traverse(root, cascade, media, true)
+}
+
+fn traverse<'a>(root: &'a Node, cascade: &Cascade<'a>, media: &MediaEnvironment, parallel: bool)
+                -> (StyleNode<'a>, StyleStats) {
+    let rules = RuleMap::new(cascade.active_rules(media));
+    let mut traversal = Traversal::new(&rules, parallel);
+    let data = traversal.style_document(root, media);
+    (build_style_tree(root, &data), traversal.stats)
+}
+
+/// Turn the styles of a document into its style tree.
+pub(crate) fn build_style_tree<'a>(root: &'a Node, data: &StyleData) -> StyleNode<'a> {
+    let mut root = build_style_node(root, data);
+    ContentGenerator::default().generate(&mut root, 0);
+    root
+}
+
+fn build_style_node<'a>(node: &'a Node, data: &StyleData) -> StyleNode<'a> {
+    let pseudo_node = |pseudo: PseudoElement, style: &PseudoStyle| {
+        let text = StyleNode { pseudo: Some(pseudo), ..StyleNode::shared(node, &style.text) };
+        StyleNode { pseudo: Some(pseudo), children: vec![text], ..StyleNode::shared(node, &style.style) }
+    };
+    let mut children = Vec::new();
+    children.extend(data.marker.as_ref().map(|style| pseudo_node(PseudoElement::Marker, style)));
+    children.extend(data.before.as_ref().map(|style| pseudo_node(PseudoElement::Before, style)));
+    children.extend(node.children.iter().zip(&data.children).map(|(child, data)| build_style_node(child, data)));
+    children.extend(data.after.as_ref().map(|style| pseudo_node(PseudoElement::After, style)));
+    StyleNode { children, ..StyleNode::shared(node, &data.style) }
+}
+
+/// The styles of a node and its descendants, kept apart from the nodes themselves so that they
+/// can be updated as the document changes.
+#[derive(Clone)]
+pub(crate) struct StyleData {
+    pub style: SharedStyle,
+    pub before: Option<PseudoStyle>,
+    pub after: Option<PseudoStyle>,
+    /// Set for list items.
+    pub marker: Option<PseudoStyle>,
+    /// One for each child node.
+    pub children: Vec<StyleData>,
+    /// The node and its descendants need restyling.
+    pub dirty: bool,
+    /// Some descendant needs restyling.
+    pub dirty_descendants: bool,
+}
+
+/// The style of a pseudo-element's box, and of the text inside it.
+#[derive(Clone)]
+pub(crate) struct PseudoStyle {
+    pub style: SharedStyle,
+    pub text: SharedStyle,
+}
+
+impl StyleData {
+    pub fn new(style: SharedStyle) -> StyleData {
+        StyleData {
+            style, before: None, after: None, marker: None, children: Vec::new(), dirty: false, dirty_descendants: false,
+        }
+    }
+}
+
+/// The state of a walk down the document that styles it.
+pub(crate) struct Traversal<'r, 'a> {
+    rules: &'r RuleMap<'a>,
+    /// The ancestors of the element being styled.
+    ancestors: AncestorFilter,
+    pub stats: StyleStats,
+    /// Style the children of each element in parallel.
+    #[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+    parallel: bool,
+}
+
+impl<'r, 'a> Traversal<'r, 'a> {
+    pub fn new(rules: &'r RuleMap<'a>, parallel: bool) -> Traversal<'r, 'a> {
+        Traversal { rules, ancestors: AncestorFilter::new(), stats: StyleStats::default(), parallel }
+    }
+
+    /// Style a whole document.
+    pub fn style_document(&mut self, root: &'a Node, media: &MediaEnvironment) -> StyleData {
+        self.style_subtree(&ElementRef::root(root), None, &mut StyleSharingCache::default(), root_context(media))
+    }
+
+    /// Restyle the dirty parts of a document that has been styled before, and say how much the
+    /// styles changed.
+    pub fn restyle_document(&mut self, root: &'a Node, data: &mut StyleData, media: &MediaEnvironment) -> Damage {
+        if data.dirty {
+            let restyled = self.style_document(root, media);
+            let damage = subtree_damage(data, &restyled);
+            *data = restyled;
+            damage
+        } else if data.dirty_descendants {
+            self.restyle_children(&ElementRef::root(root), data, root_context(media))
+        } else {
+            Damage::None
+        }
+    }
+
+    /// Style an element and its descendants. `parent` is the computed values of the parent
+    /// element, and `sharing` holds the styles of its children so far.
+    fn style_subtree(&mut self, elem: &ElementRef<'a, '_>, parent: Option<&PropertyMap>,
+                     sharing: &mut StyleSharingCache<'a>, context: ComputeContext) -> StyleData {
+        if let Text(_) = elem.node.node_type {
+            // Text has no declarations of its own, so it inherits from its parent.
+            return StyleData::new(sharing.text_style(|| compute_values(HashMap::new(), parent, &context)));
+        }
+        self.stats.elements += 1;
+        let candidates = self.rules.candidates(elem.data(), &self.ancestors);
+        let key = SharingKey::new(elem.data(), || revalidation_matches(elem, &candidates));
+        let cached = key.as_ref().and_then(|key| sharing.get(key));
+        self.stats.sharing_candidates += key.is_some() as usize;
+        self.stats.shared += cached.is_some() as usize;
+        let style = cached.unwrap_or_else(|| {
+            let specified = specified_values(elem, None, self.rules, &candidates);
+            let mut values = compute_values(specified, parent, &context);
+            blockify(&mut values, parent);
+            let style = SharedStyle::new(values);
+            if let Some(key) = key {
+                sharing.insert(key, style.clone());
+            }
+            style
+        });
+        let values = &*style.values;
+        let child_context = child_context(elem, values, context);
+
+        let rules = self.rules;
+        let marker = marker_style(elem, &style.style, values, rules, &candidates, child_context);
+        let before = pseudo_element_style(elem, PseudoElement::Before, values, rules, &candidates, child_context);
+        self.ancestors.push(elem.data());
+        let children = self.style_children(elem, values, child_context);
+        self.ancestors.pop(elem.data());
+        let after = pseudo_element_style(elem, PseudoElement::After, values, rules, &candidates, child_context);
+        StyleData { before, after, marker, children, ..StyleData::new(style) }
     }
-}
\ No newline at end of file
+
+    /// Style the children of an element whose computed values are `values`.
+    fn style_children(&mut self, elem: &ElementRef<'a, '_>, values: &PropertyMap,
+                      context: ComputeContext) -> Vec<StyleData> {
+        #[cfg(feature = "parallel")]
+        if self.parallel && elem.node.children.len() > 1 {
+            return self.style_children_in_parallel(elem, values, context);
+        }
+        let mut sharing = StyleSharingCache::default();
+        elem.node.children.iter().enumerate().map(|(index, child)| {
+            let child = ElementRef { node: child, parent: Some(elem), index };
+            self.style_subtree(&child, Some(values), &mut sharing, context)
+        }).collect()
+    }
+
+    /// Style the children of an element on rayon's thread pool. Each task gets its own copy of
+    /// the ancestor filter and its own style sharing cache, and the children come back in order.
+    #[cfg(feature = "parallel")]
+    fn style_children_in_parallel(&mut self, elem: &ElementRef<'a, '_>, values: &PropertyMap,
+                                  context: ComputeContext) -> Vec<StyleData> {
+        use rayon::prelude::*;
+        let (rules, ancestors) = (self.rules, &self.ancestors);
+        let styled: Vec<(StyleData, StyleStats)> = elem.node.children.par_iter().enumerate()
+            .map_init(|| {
+                let traversal = Traversal { rules, ancestors: ancestors.clone(), stats: StyleStats::default(), parallel: true };
+                (traversal, StyleSharingCache::default())
+            }, |(traversal, sharing), (index, child)| {
+                let child = ElementRef { node: child, parent: Some(elem), index };
+                let data = traversal.style_subtree(&child, Some(values), sharing, context);
+                (data, std::mem::take(&mut traversal.stats))
+            })
+            .collect();
+        styled.into_iter().map(|(data, stats)| {
+            self.stats.add(stats);
+            data
+        }).collect()
+    }
+
+    /// Restyle the dirty descendants of an element whose own style is up to date.
+    fn restyle_children(&mut self, elem: &ElementRef<'a, '_>, data: &mut StyleData, context: ComputeContext) -> Damage {
+        let values = data.style.values.clone();
+        let child_context = child_context(elem, &values, context);
+        let mut damage = Damage::None;
+        let mut sharing = StyleSharingCache::default();
+        self.ancestors.push(elem.data());
+        for (index, (child, child_data)) in elem.node.children.iter().zip(&mut data.children).enumerate() {
+            let child = ElementRef { node: child, parent: Some(elem), index };
+            if child_data.dirty {
+                let restyled = self.style_subtree(&child, Some(&values), &mut sharing, child_context);
+                damage = damage.max(subtree_damage(child_data, &restyled));
+                *child_data = restyled;
+            } else if child_data.dirty_descendants {
+                damage = damage.max(self.restyle_children(&child, child_data, child_context));
+            }
+        }
+        self.ancestors.pop(elem.data());
+        data.dirty_descendants = false;
+        damage
+    }
+}
+
+/// What an element's children resolve their relative lengths against.
+fn root_context(media: &MediaEnvironment) -> ComputeContext {
+    let viewport = Viewport { width: media.width, height: media.height };
+    ComputeContext { root_font_size: MEDIUM_FONT_SIZE, viewport }
+}
+
+/// Descendants of the root resolve `rem` against the root's font size.
+fn child_context(elem: &ElementRef, values: &PropertyMap, context: ComputeContext) -> ComputeContext {
+    match elem.parent {
+        None => ComputeContext { root_font_size: values["font-size"].to_px(), ..context },
+        Some(_) => context
+    }
+}
+
+/// Which of the element's candidate selectors that need revalidation match it, for comparing it
+/// with its siblings.
+fn revalidation_matches(elem: &ElementRef, candidates: &[&IndexedSelector]) -> Vec<bool> {
+    candidates.iter()
+        .filter(|candidate| candidate.revalidate && candidate.selector.pseudo_element().is_none())
+        .map(|candidate| matches(elem, candidate.selector))
+        .collect()
+}
+
+/// Give every supported property a value: properties the cascade didn't set, or set to
+/// `inherit`, `initial` or `unset`, take their parent's computed value or their initial value.
+/// Other properties are passed through, except that they have no initial value to fall back on.
+fn default_values(mut cascaded: PropertyMap, parent: Option<&PropertyMap>) -> PropertyMap {
+    let inherit = |name: &str| parent.and_then(|parent| parent.get(name)).cloned();
+    let mut values = PropertyMap::new();
+    for property in properties::all() {
+        let value = match cascaded.remove(property.name) {
+            Some(Keyword(ref keyword)) if keyword == "initial" => None,
+            Some(Keyword(ref keyword)) if keyword == "inherit" => inherit(property.name),
+            Some(Keyword(ref keyword)) if keyword == "unset" && !property.inherited => None,
+            Some(Keyword(ref keyword)) if keyword == "unset" => inherit(property.name),
+            Some(value) => Some(value),
+            None if property.inherited => inherit(property.name),
+            None => None
+        };
+        let value = value.unwrap_or_else(|| property.initial_value().clone());
+        values.insert(property.name.to_string(), value);
+    }
+
+    // Custom properties are inherited, and their initial value is no value at all.
+    if let Some(parent) = parent {
+        values.extend(parent.iter()
+            .filter(|(name, _)| is_custom_property(name))
+            .map(|(name, value)| (name.clone(), value.clone())));
+    }
+    for (name, value) in cascaded {
+        let custom = is_custom_property(&name);
+        let value = match value {
+            Keyword(ref keyword) if keyword == "inherit" => inherit(&name),
+            Keyword(ref keyword) if keyword == "unset" && custom => inherit(&name),
+            Keyword(ref keyword) if keyword == "initial" || keyword == "unset" => None,
+            value => Some(value)
+        };
+        match value {
+            Some(value) => values.insert(name, value),
+            None => values.remove(&name),
+        };
+    }
+    values
+}
+
+/// Substitute `var()` references, first in custom properties and then in the properties that
+/// use them. A value that can't be substituted is invalid at computed-value time, and the
+/// property behaves as if it were `unset`.
+fn substitute_variables(values: &mut PropertyMap, parent: Option<&PropertyMap>) {
+    variables::resolve_custom_properties(values);
+    let pending: Vec<String> = values.iter()
+        .filter(|(name, value)| !is_custom_property(name)
+            && matches!(value, Value::Unparsed(_) | Value::PendingSubstitution(..)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in pending {
+        let value = variables::substitute(&name, &values[&name], values);
+        match value.or_else(|| unset_value(&name, parent)) {
+            Some(value) => values.insert(name, value),
+            None => values.remove(&name),
+        };
+    }
+}
+
+/// The value of a property set to `unset`: its parent's value if it's inherited, and its initial
+/// value otherwise.
+fn unset_value(name: &str, parent: Option<&PropertyMap>) -> Option<Value> {
+    let property = properties::lookup(name)?;
+    let inherited = match parent {
+        Some(parent) if property.inherited => parent.get(name).cloned(),
+        _ => None
+    };
+    Some(inherited.unwrap_or_else(|| property.initial_value().clone()))
+}
+
+/// Turn cascaded values into computed values by filling in defaults, substituting variables and
+/// converting lengths to px. Percentages are left for layout to resolve, except where the computed value needs them
+/// (`font-size`, `line-height`).
+fn compute_values(cascaded: PropertyMap, parent: Option<&PropertyMap>, context: &ComputeContext) -> PropertyMap {
+    let mut values = default_values(cascaded, parent);
+    substitute_variables(&mut values, parent);
+
+    // `em` in font-size itself refers to the parent's font size.
+    let parent_font_size = parent.and_then(|parent| parent.get("font-size"))
+        .map_or(MEDIUM_FONT_SIZE, Value::to_px);
+    let font_size = match values.get("font-size") {
+        Some(&Value::Length(f, Unit::Percent)) => Some(f / 100.0 * parent_font_size),
+        Some(&Value::Length(f, unit)) => Some(resolve_length(f, unit, parent_font_size, context)),
+        Some(Keyword(keyword)) => font_size_keyword(keyword, parent_font_size),
+        Some(Value::Math(expr)) if !expr.is_number() => {
+            let expr = expr.absolutize(&|f, unit| resolve_length(f, unit, parent_font_size, context));
+            Some(expr.resolve(parent_font_size))
+        }
+        _ => None
+    };
+    // An unsupported value leaves the size unchanged.
+    let font_size = font_size.unwrap_or(parent_font_size);
+    values.insert("font-size".to_string(), Value::Length(font_size, Unit::Px));
+
+    // `currentcolor` is the computed `color`, which for `color` itself means the parent's.
+    let color = match values.get("color") {
+        Some(&Value::ColorValue(color)) => color,
+        _ => match parent.and_then(|parent| parent.get("color")) {
+            Some(&Value::ColorValue(color)) => color,
+            _ => BLACK
+        }
+    };
+    if values.get("color").is_some_and(|value| *value == Keyword("currentcolor".to_string())) {
+        values.insert("color".to_string(), Value::ColorValue(color));
+    }
+
+    // `bolder` and `lighter` are relative to the parent's weight.
+    let parent_weight = match parent.and_then(|parent| parent.get("font-weight")) {
+        Some(&Value::Number(weight)) => weight,
+        _ => 400.0
+    };
+    if let Some(weight) = values.get("font-weight").and_then(|value| font_weight_keyword(value, parent_weight)) {
+        values.insert("font-weight".to_string(), Value::Number(weight));
+    }
+
+    for (name, value) in values.iter_mut() {
+        match *value {
+            Keyword(ref keyword) if keyword == "currentcolor" => *value = Value::ColorValue(color),
+            Keyword(ref keyword) if name.starts_with("border-") && name.ends_with("-width") => {
+                if let Some(width) = border_width_keyword(keyword) {
+                    *value = Value::Length(width, Unit::Px);
+                }
+            }
+            Value::Length(f, Unit::Percent) if name == "line-height" => {
+                *value = Value::Length(f / 100.0 * font_size, Unit::Px);
+            }
+            Value::Length(_, Unit::Px) | Value::Length(_, Unit::Percent) => {}
+            Value::Length(f, unit) => *value = Value::Length(resolve_length(f, unit, font_size, context), Unit::Px),
+            // Math expressions are evaluated now unless they have percentages, which are left for
+            // layout.
+            Value::Math(ref expr) => {
+                let expr = expr.absolutize(&|f, unit| resolve_length(f, unit, font_size, context));
+                *value = match (expr.is_number(), expr.has_percentage()) {
+                    (true, _) => Value::Number(expr.resolve(0.0)),
+                    (false, true) if name == "line-height" => Value::Length(expr.resolve(font_size), Unit::Px),
+                    (false, true) => Value::Math(expr),
+                    (false, false) => Value::Length(expr.resolve(0.0), Unit::Px),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    // A border without a style has no width.
+    for side in ["top", "right", "bottom", "left"] {
+        let style = values.get(&format!("border-{}-style", side));
+        if matches!(style, Some(Keyword(style)) if style == "none" || style == "hidden") {
+            values.insert(format!("border-{}-width", side), Value::Length(0.0, Unit::Px));
+        }
+    }
+    values
+}
+
+/// Make an element's box block-level where it can't be inline: for the root element, floats,
+/// absolutely positioned elements, and the children of flex and grid containers. The root
+/// element always generates a box, so `display: contents` on it computes to `block`.
+fn blockify(values: &mut PropertyMap, parent: Option<&PropertyMap>) {
+    let keyword = |name: &str| match values.get(name) {
+        Some(Keyword(keyword)) => Some(keyword.as_str()),
+        _ => None
+    };
+    let Some(display) = values.get("display").and_then(computed::display) else { return };
+    let parent_inside = parent.and_then(|parent| parent.get("display"))
+        .and_then(computed::display)
+        .and_then(Display::inside);
+    let blockified = match display {
+        Display::Contents if parent.is_none() => Display::BLOCK,
+        _ if parent.is_none() || keyword("float").is_some_and(|float| float != "none")
+            || matches!(keyword("position"), Some("absolute" | "fixed"))
+            || matches!(parent_inside, Some(DisplayInside::Flex | DisplayInside::Grid)) => display.blockify(),
+        _ => return
+    };
+    if blockified != display {
+        values.insert("display".to_string(), blockified.to_value());
+    }
+}
+
+/// Convert a non-percentage length to px.
+fn resolve_length(f: f32, unit: Unit, font_size: f32, context: &ComputeContext) -> f32 {
+    let viewport = context.viewport;
+    f * match unit {
+        Unit::Em => font_size,
+        Unit::Rem => context.root_font_size,
+        // Without font metrics, approximate the x-height and the width of "0" as half an em.
+        Unit::Ex | Unit::Ch => font_size / 2.0,
+        Unit::Vw => viewport.width / 100.0,
+        Unit::Vh => viewport.height / 100.0,
+        Unit::Vmin => viewport.width.min(viewport.height) / 100.0,
+        Unit::Vmax => viewport.width.max(viewport.height) / 100.0,
+        Unit::Percent => panic!("percentages need a basis to resolve against"),
+        absolute => absolute.absolute_px().unwrap(),
+    }
+}
+
+/// The width of a `thin`, `medium` or `thick` border.
+fn border_width_keyword(keyword: &str) -> Option<f32> {
+    match keyword {
+        "thin" => Some(1.0),
+        "medium" => Some(3.0),
+        "thick" => Some(5.0),
+        _ => None
+    }
+}
+
+/// The numeric weight of a font-weight keyword, using the relative weights table for `bolder`
+/// and `lighter`.
+fn font_weight_keyword(value: &Value, parent_weight: f32) -> Option<f32> {
+    let Keyword(keyword) = value else { return None };
+    Some(match &**keyword {
+        "normal" => 400.0,
+        "bold" => 700.0,
+        "bolder" if parent_weight < 350.0 => 400.0,
+        "bolder" if parent_weight < 550.0 => 700.0,
+        "bolder" => parent_weight.max(900.0),
+        "lighter" if parent_weight < 100.0 => parent_weight,
+        "lighter" if parent_weight < 550.0 => 100.0,
+        "lighter" if parent_weight < 750.0 => 400.0,
+        "lighter" => 700.0,
+        _ => return None
+    })
+}
+
+/// The size of an absolute or relative font-size keyword.
+fn font_size_keyword(keyword: &str, parent_font_size: f32) -> Option<f32> {
+    Some(match keyword {
+        "xx-small" => 9.0,
+        "x-small" => 10.0,
+        "small" => 13.0,
+        "medium" => MEDIUM_FONT_SIZE,
+        "large" => 18.0,
+        "x-large" => 24.0,
+        "xx-large" => 32.0,
+        "xxx-large" => 48.0,
+        "smaller" => parent_font_size / 1.2,
+        "larger" => parent_font_size * 1.2,
+        _ => return None
+    })
+}
+
+/// Style a `::before` or `::after` pseudo-element, which only generates a box if it has content.
+/// The box gets a single text child, whose text is filled in later by `ContentGenerator`.
+fn pseudo_element_style<'a>(elem: &ElementRef<'a, '_>, pseudo: PseudoElement, elem_values: &PropertyMap,
+                            rules: &RuleMap<'a>, candidates: &[&IndexedSelector<'a>], context: ComputeContext)
+                            -> Option<PseudoStyle> {
+    // Without rules of its own, a pseudo-element has no content.
+    let matched = matching_rules(elem, Some(pseudo), rules, candidates);
+    if matched.is_empty() {
+        return None;
+    }
+    let mut values = compute_values(specified_values_from(matched), Some(elem_values), &context);
+    blockify(&mut values, Some(elem_values));
+    let Some(Value::Content(_)) = values.get("content") else { return None };
+    let text = SharedStyle::new(compute_values(HashMap::new(), Some(&values), &context));
+    Some(PseudoStyle { style: SharedStyle::new(values), text })
+}
+
+/// Style the `::marker` of a list item. Markers are always inline boxes, and their text is filled in
+/// later by `ContentGenerator`, from `content` or else the `list-item` counter.
+fn marker_style<'a>(elem: &ElementRef<'a, '_>, elem_style: &ComputedStyle, elem_values: &PropertyMap,
+                    rules: &RuleMap<'a>, candidates: &[&IndexedSelector<'a>], context: ComputeContext)
+                    -> Option<PseudoStyle> {
+    if !matches!(elem_style.display, Display::Box { list_item: true, .. }) {
+        return None;
+    }
+    let mut specified = specified_values_from(matching_rules(elem, Some(PseudoElement::Marker), rules, candidates));
+    specified.insert("display".to_string(), Keyword("inline".to_string()));
+    let values = compute_values(specified, Some(elem_values), &context);
+    let text = SharedStyle::new(compute_values(HashMap::new(), Some(&values), &context));
+    let style = SharedStyle::new(values);
+    // Without a bullet or number, a marker only has a box for its `content`.
+    if style.style.list_style_type == "none" && !matches!(style.style.content, Content::Items(_)) {
+        return None;
+    }
+    Some(PseudoStyle { style, text })
+}
+
+/// Resolves `content` into text, in document order, because counters and quote nesting depend
+/// on everything that came before.
+#[derive(Default)]
+struct ContentGenerator {
+    /// The nested instances of each counter, innermost last, with the tree depth that created them.
+    counters: HashMap<String, Vec<(i32, usize)>>,
+    quote_depth: usize,
+}
+
+impl ContentGenerator {
+    fn generate(&mut self, node: &mut StyleNode, depth: usize) {
+        for (name, value) in &node.style.counter_reset {
+            self.reset(name, *value, depth);
+        }
+        for (name, value) in &node.style.counter_set {
+            self.update(name, depth, |_| *value);
+        }
+        for (name, value) in &node.style.counter_increment {
+            self.update(name, depth, |old| old.saturating_add(*value));
+        }
+        // List items count themselves, unless they say how.
+        if node.pseudo.is_none() && matches!(node.style.display, Display::Box { list_item: true, .. })
+            && !node.style.counter_increment.iter().any(|(name, _)| name == "list-item") {
+            self.update("list-item", depth, |old| old.saturating_add(1));
+        }
+        match (node.pseudo, &node.style.content) {
+            (Some(_), Content::Items(items)) => {
+                let text = self.content_text(node.node, items);
+                node.children[0].generated_text = Some(text);
+                return;
+            }
+            (Some(PseudoElement::Marker), _) => {
+                node.children[0].generated_text = Some(self.marker_text(&node.style.list_style_type));
+                return;
+            }
+            _ => {}
+        }
+        for child in &mut node.children {
+            self.generate(child, depth + 1);
+        }
+        // Counters created by the children go out of scope with their parent.
+        for instances in self.counters.values_mut() {
+            while instances.last().is_some_and(|&(_, created)| created > depth) {
+                instances.pop();
+            }
+        }
+    }
+
+    /// Create a new counter instance, unless a preceding sibling already created one, in which
+    /// case that instance is reset.
+    fn reset(&mut self, name: &str, value: i32, depth: usize) {
+        let instances = self.counters.entry(name.to_string()).or_default();
+        match instances.last_mut() {
+            Some(instance) if instance.1 == depth => instance.0 = value,
+            _ => instances.push((value, depth)),
+        }
+    }
+
+    /// Change the innermost instance of a counter, creating one if the counter isn't in scope.
+    fn update<F: Fn(i32) -> i32>(&mut self, name: &str, depth: usize, f: F) {
+        if self.counters.get(name).is_none_or(|instances| instances.is_empty()) {
+            self.reset(name, 0, depth);
+        }
+        let instance = self.counters.get_mut(name).unwrap().last_mut().unwrap();
+        instance.0 = f(instance.0);
+    }
+
+    /// The text of a marker without `content`: its list item's number, or a bullet.
+    fn marker_text(&self, list_style_type: &str) -> String {
+        let value = self.counters.get("list-item").and_then(|c| c.last()).map_or(0, |c| c.0);
+        let marker = format_counter(value, list_style_type);
+        match list_style_type {
+            "disc" | "circle" | "square" => format!("{} ", marker),
+            _ => format!("{}. ", marker),
+        }
+    }
+
+    fn content_text(&mut self, node: &Node, items: &[ContentItem]) -> String {
+        let mut text = String::new();
+        for item in items {
+            match *item {
+                ContentItem::String(ref s) => text.push_str(s),
+                ContentItem::Attr(ref name) => if let Element(ref data) = node.node_type {
+                    text.push_str(data.attributes.get(name).map_or("", |v| v))
+                },
+                ContentItem::Counter(ref name, ref style) => {
+                    let value = self.counters.get(name).and_then(|c| c.last()).map_or(0, |c| c.0);
+                    text.push_str(&format_counter(value, style));
+                }
+                ContentItem::Counters(ref name, ref separator, ref style) => {
+                    let values: Vec<String> = match self.counters.get(name) {
+                        Some(instances) if !instances.is_empty() => {
+                            instances.iter().map(|&(value, _)| format_counter(value, style)).collect()
+                        }
+                        _ => vec![format_counter(0, style)],
+                    };
+                    text.push_str(&values.join(separator));
+                }
+                ContentItem::OpenQuote => {
+                    text.push(if self.quote_depth.is_multiple_of(2) { '\u{201C}' } else { '\u{2018}' });
+                    self.quote_depth += 1;
+                }
+                ContentItem::CloseQuote => if self.quote_depth > 0 {
+                    self.quote_depth -= 1;
+                    text.push(if self.quote_depth.is_multiple_of(2) { '\u{201D}' } else { '\u{2019}' });
+                },
+                ContentItem::NoOpenQuote => self.quote_depth += 1,
+                ContentItem::NoCloseQuote => self.quote_depth = self.quote_depth.saturating_sub(1),
+            }
+        }
+        text
+    }
+}
+
+/// Format a counter value in one of the predefined counter styles.
+fn format_counter(value: i32, style: &str) -> String {
+    let alphabetic = |alphabet: &[char]| {
+        if value < 1 {
+            return value.to_string();
+        }
+        let mut n = value as usize;
+        let mut result = Vec::new();
+        while n > 0 {
+            n -= 1;
+            result.push(alphabet[n % alphabet.len()]);
+            n /= alphabet.len();
+        }
+        result.iter().rev().collect()
+    };
+    let latin: Vec<char> = ('a'..='z').collect();
+    let greek: Vec<char> = ('\u{3B1}'..='\u{3C9}').filter(|&c| c != '\u{3C2}').collect();
+    match style {
+        "none" => String::new(),
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25E6}".to_string(),
+        "square" => "\u{25AA}".to_string(),
+        "decimal-leading-zero" if (0..10).contains(&value) => format!("0{}", value),
+        "lower-alpha" | "lower-latin" => alphabetic(&latin),
+        "upper-alpha" | "upper-latin" => alphabetic(&latin).to_uppercase(),
+        "lower-greek" => alphabetic(&greek),
+        "lower-roman" => roman(value).to_lowercase(),
+        "upper-roman" => roman(value),
+        _ => value.to_string(),
+    }
+}
+
+fn roman(value: i32) -> String {
+    if !(1..4000).contains(&value) {
+        return value.to_string();
+    }
+    let numerals = [(1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")];
+    let mut n = value;
+    let mut result = String::new();
+    for &(size, numeral) in &numerals {
+        while n >= size {
+            result.push_str(numeral);
+            n -= size;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computed::{LengthPercentage, LengthPercentageOrAuto};
+    use crate::html;
+
+    /// The first element in a document with the given tag name.
+    fn element<'a>(node: &'a Node, tag_name: &str) -> Option<&'a Node> {
+        match node.node_type {
+            Element(ref elem) if elem.tag_name == tag_name => Some(node),
+            _ => node.children.iter().find_map(|child| element(child, tag_name)),
+        }
+    }
+
+    /// The computed style of the first element with each tag name, in a document styled by
+    /// `cascade` in an 800x600 viewport.
+    fn computed_styles(html: &str, cascade: &Cascade, tag_names: &[&str]) -> Vec<Arc<ComputedStyle>> {
+        let root = html::parse(html.to_string());
+        let styles = style_tree(&root, cascade, &MediaEnvironment::screen(800.0, 600.0));
+        tag_names.iter()
+            .map(|tag_name| styles.find(element(&root, tag_name).unwrap(), None).unwrap().style.clone())
+            .collect()
+    }
+
+    /// The text of every `::before` and `::after` box in a document styled with `css`, in order.
+    fn generated_text(html: &str, css: &str) -> Vec<String> {
+        fn collect(node: &StyleNode, texts: &mut Vec<String>) {
+            if node.pseudo != Some(PseudoElement::Marker) {
+                texts.extend(node.generated_text.clone());
+            }
+            for child in &node.children {
+                collect(child, texts);
+            }
+        }
+        let root = html::parse(html.to_string());
+        let stylesheet = crate::css::parse(css.to_string());
+        let styles = style_tree(&root, &Cascade::author(&stylesheet), &MediaEnvironment::screen(800.0, 600.0));
+        let mut texts = Vec::new();
+        collect(&styles, &mut texts);
+        texts
+    }
+
+    #[test]
+    fn user_agent_stylesheet_parses_without_warnings() {
+        assert_eq!(default_user_agent_stylesheet().warnings, Vec::<String>::new());
+    }
+
+    #[test]
+    fn user_agent_defaults() {
+        let root = html::parse("<html><head><title>Title</title></head>\
+            <body><h1>Heading</h1><p>Text <b>bold</b></p></body></html>".to_string());
+        let styles = style_tree(&root, &Cascade::default(), &MediaEnvironment::screen(800.0, 600.0));
+        let style = |tag_name: &str| styles.find(element(&root, tag_name).unwrap(), None).unwrap().style.clone();
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+
+        assert_eq!(style("head").display, computed::Display::None);
+        assert_eq!(style("title").display, computed::Display::None);
+        assert!(matches!(style("body").display, computed::Display::Box { .. }));
+        assert_eq!(style("body").margin.left, px(8.0));
+
+        let h1 = style("h1");
+        assert_eq!(h1.font_size, 32.0);
+        assert_eq!(h1.font_weight, 700);
+        assert_eq!(h1.margin.top, px(32.0 * 0.67));
+        assert_eq!(style("p").margin.bottom, px(16.0));
+        assert_eq!(style("b").font_weight, 700);
+    }
+
+    #[test]
+    fn important_declarations_reverse_the_origin_order() {
+        let user_agent = crate::css::parse("p { color: red !important; background-color: red; width: 1px; \
+            margin-top: 1px; padding-top: 1px !important }".to_string());
+        let user = crate::css::parse("p { color: green !important; background-color: green !important; \
+            width: 2px; margin-top: 2px }".to_string());
+        let author = crate::css::parse("p { color: blue !important; background-color: blue !important; \
+            width: 3px; margin-top: 3px !important; padding-top: 3px }".to_string());
+        let cascade = Cascade {
+            default_user_agent: false,
+            user_agent: vec![&user_agent],
+            user: vec![&user],
+            author: vec![&author],
+        };
+        let p = &computed_styles("<p>Text</p>", &cascade, &["p"])[0];
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+        assert_eq!(p.color, Color { r: 255, g: 0, b: 0, a: 255 });
+        assert_eq!(p.background_color, Color { r: 0, g: 128, b: 0, a: 255 });
+        assert_eq!(p.width, px(3.0));
+        assert_eq!(p.margin.top, px(3.0));
+        assert_eq!(p.padding.top, LengthPercentage::Px(1.0));
+    }
+
+    #[test]
+    fn important_declarations_reverse_the_layer_order() {
+        let stylesheet = crate::css::parse("@layer a, b; \
+            p { width: 1px !important; height: 1px } \
+            @layer b { p { color: green; background-color: green !important; width: 2px !important; height: 2px } } \
+            @layer a { p { color: red; background-color: red !important } }".to_string());
+        let p = &computed_styles("<p>Text</p>", &Cascade::author(&stylesheet), &["p"])[0];
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+        // Later layers win for normal declarations, and rules outside any layer beat both.
+        assert_eq!(p.color, Color { r: 0, g: 128, b: 0, a: 255 });
+        assert_eq!(p.height, px(1.0));
+        // Earlier layers win for important ones, and beat rules outside any layer.
+        assert_eq!(p.background_color, Color { r: 255, g: 0, b: 0, a: 255 });
+        assert_eq!(p.width, px(2.0));
+    }
+
+    #[test]
+    fn source_order_breaks_specificity_ties() {
+        let stylesheet = crate::css::parse(".a { color: red; width: 1px } p { color: blue } \
+            .b { color: green } p.b { width: 2px } .a { width: 3px } p { height: 1px; height: 2px }".to_string());
+        let p = &computed_styles("<p class=\"a b\">Text</p>", &Cascade::author(&stylesheet), &["p"])[0];
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+        assert_eq!(p.color, Color { r: 0, g: 128, b: 0, a: 255 });
+        assert_eq!(p.width, px(2.0));
+        assert_eq!(p.height, px(2.0));
+    }
+
+    #[test]
+    fn revert_rolls_back_an_origin_or_layer() {
+        let user_agent = crate::css::parse("p { color: red; width: 1px; height: 1px }".to_string());
+        let user = crate::css::parse("p { color: green }".to_string());
+        let author = crate::css::parse("@layer base, top; \
+            @layer base { p { width: 2px; height: 2px } } \
+            @layer top { p { width: revert-layer; height: revert } } \
+            p { color: revert; margin-top: revert; margin-bottom: 4px; margin-bottom: revert-layer }".to_string());
+        let cascade = Cascade {
+            default_user_agent: false,
+            user_agent: vec![&user_agent],
+            user: vec![&user],
+            author: vec![&author],
+        };
+        let p = &computed_styles("<p>Text</p>", &cascade, &["p"])[0];
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+        // `revert` rolls back to the user's value, then the user agent's.
+        assert_eq!(p.color, Color { r: 0, g: 128, b: 0, a: 255 });
+        assert_eq!(p.height, px(1.0));
+        // `revert-layer` rolls back to the previous layer.
+        assert_eq!(p.width, px(2.0));
+        // With nothing to roll back to, the property isn't set.
+        assert_eq!(p.margin.top, px(0.0));
+        assert_eq!(p.margin.bottom, px(0.0));
+    }
+
+    #[test]
+    fn css_wide_keywords() {
+        let stylesheet = crate::css::parse("div { color: green; font: bold 20px serif; margin-left: 5px; width: 50%; \
+                padding-left: 3px } \
+            p { color: initial; font-size: unset; margin-left: inherit; width: inherit; padding-left: unset; \
+                font-weight: initial } \
+            em { color: unset; margin-left: unset; font-size: inherit; padding-left: inherit }".to_string());
+        let styles = computed_styles("<div><p><em>Text</em></p></div>", &Cascade::author(&stylesheet),
+            &["p", "em"]);
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+        let (p, em) = (&styles[0], &styles[1]);
+        // `initial` and `unset` on a property that isn't inherited give the initial value; `unset`
+        // on an inherited property, and `inherit` on any property, the parent's value.
+        assert_eq!(p.color, BLACK);
+        assert_eq!(p.font_size, 20.0);
+        assert_eq!(p.font_weight, 400);
+        assert_eq!(p.margin.left, px(5.0));
+        assert_eq!(p.width, LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Percent(50.0)));
+        assert_eq!(p.padding.left, LengthPercentage::Px(0.0));
+        assert_eq!(em.color, BLACK);
+        assert_eq!(em.margin.left, px(0.0));
+        assert_eq!(em.font_size, 20.0);
+        assert_eq!(em.padding.left, LengthPercentage::Px(0.0));
+    }
+
+    #[test]
+    fn relative_lengths_compute_to_px() {
+        let stylesheet = crate::css::parse("html { font-size: 20px } \
+            body { font-size: 1.5em; margin-left: 2em; width: 50%; padding-left: 10vw; height: 5vh } \
+            p { font-size: 50%; margin-top: 1rem; text-indent: 2em; line-height: 150%; \
+                max-width: 10vmin; border-left: 1vmax solid }".to_string());
+        let styles = computed_styles("<html><body><p>Text</p></body></html>", &Cascade::author(&stylesheet),
+            &["html", "body", "p"]);
+        let px = |px: f32| LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Px(px));
+        let (html, body, p) = (&styles[0], &styles[1], &styles[2]);
+        assert_eq!(html.font_size, 20.0);
+
+        // `em` in font-size is relative to the parent's font size, and elsewhere to the element's.
+        assert_eq!(body.font_size, 30.0);
+        assert_eq!(body.margin.left, px(60.0));
+        assert_eq!(body.padding.left, LengthPercentage::Px(80.0));
+        assert_eq!(body.height, px(30.0));
+        // Percentages are left for layout, except where the computed value needs them.
+        assert_eq!(body.width, LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Percent(50.0)));
+        assert_eq!(p.font_size, 15.0);
+        assert_eq!(p.line_height, computed::LineHeight::Px(22.5));
+
+        // `rem` is relative to the root element's font size.
+        assert_eq!(p.margin.top, px(20.0));
+        assert_eq!(p.text_indent, LengthPercentage::Px(30.0));
+        assert_eq!(p.max_width, Some(LengthPercentage::Px(60.0)));
+        assert_eq!(p.border_width.left, 8.0);
+    }
+
+    #[test]
+    fn counters_number_generated_content() {
+        let texts = generated_text("<ol><li>a</li><li>b</li><li>c</li></ol>",
+            "ol { counter-reset: item 4 } li { counter-increment: item } li:last-child { counter-increment: item 10 } \
+             li::before { content: counter(item) \". \" counter(item, upper-roman) }");
+        assert_eq!(texts, ["5. V", "6. VI", "16. XVI"]);
+
+        let texts = generated_text("<p>a</p>", "p { counter-reset: n 2147483647; counter-increment: n 5 } \
+            p::before { content: counter(n) }");
+        assert_eq!(texts, ["2147483647"]);
+
+        // List items count themselves in `list-item`, unless they increment it another way.
+        let texts = generated_text("<ol><li>a</li><li>b</li><li>c</li></ol>",
+            "li:last-child { counter-increment: list-item 5 } li::before { content: counter(list-item) }");
+        assert_eq!(texts, ["1", "2", "7"]);
+    }
+
+    #[test]
+    fn nested_counters_go_out_of_scope_with_their_element() {
+        let texts = generated_text("<ol><li>a</li><li>b<ol><li>c</li><li>d</li></ol></li><li>e</li></ol>",
+            "ol { counter-reset: item } li { counter-increment: item } \
+             li::before { content: counters(item, \".\") }");
+        assert_eq!(texts, ["1", "2", "2.1", "2.2", "3"]);
+
+        // Incrementing a counter that isn't in scope creates one, which its following siblings see.
+        let texts = generated_text("<div><p>a</p></div><div><p>b</p></div><p>c</p>",
+            "p::before { content: counter(c) } div { counter-increment: c }");
+        assert_eq!(texts, ["1", "2", "2"]);
+    }
+
+    #[test]
+    fn quotes_alternate_with_nesting() {
+        let texts = generated_text("<p><q>a<q>b</q></q><q>c</q></p>",
+            "q::before { content: open-quote } q::after { content: close-quote }");
+        assert_eq!(texts, ["\u{201C}", "\u{2018}", "\u{2019}", "\u{201D}", "\u{201C}", "\u{201D}"]);
+
+        // no-open-quote still counts as a level of nesting, and unmatched close quotes are dropped.
+        let texts = generated_text("<p><q>a</q></p>",
+            "p::before { content: no-open-quote close-quote close-quote }");
+        assert_eq!(texts, ["\u{201D}", "\u{201C}", "\u{201D}"]);
+    }
+
+    #[test]
+    fn attr_inserts_attribute_values() {
+        let texts = generated_text("<p><a href=\"x.html\" title=\"X\">x</a><a>y</a></p>",
+            "a::after { content: \" (\" attr(href) \")\" attr(title) }");
+        assert_eq!(texts, [" (x.html)X", " ()"]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_style_tree_matches_serial() {
+        let stylesheet = crate::css::parse("body { counter-reset: section; font-size: 20px; --gap: 3px }
+            .section { counter-increment: section; margin: var(--gap) 1em; }
+            .section::before { content: counter(section) \". \"; }
+            .section:nth-child(3n of .section) > h2 { font-size: 1.5em; color: red }
+            .section + .section { border-top: 1px solid; }
+            li ~ li:last-child { color: blue; }
+            ul li.tag1 { padding-left: 2px; }
+            #s7 li { font-weight: bold; }".to_string());
+        let mut source = String::from("<html><body>");
+        for i in 0..40 {
+            source.push_str(&format!("<div class=\"section\" id=\"s{}\"><h2>Section</h2><ul>", i));
+            for j in 0..4 {
+                source.push_str(&format!("<li class=\"tag{}\">Item</li>", (i + j) % 3));
+            }
+            source.push_str("</ul></div><p>Between</p>");
+        }
+        source.push_str("</body></html>");
+        let root = html::parse(source);
+        let cascade = Cascade::author(&stylesheet);
+        let media = MediaEnvironment::screen(800.0, 600.0);
+
+        let (serial, serial_stats) = style_tree_with_stats(&root, &cascade, &media);
+        let (parallel, parallel_stats) = parallel_style_tree_with_stats(&root, &cascade, &media);
+        assert!(serial == parallel, "The parallel style tree differs from the serial one");
+        assert_eq!(serial_stats.elements, parallel_stats.elements);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use crate::css::{self, css_wide_keyword, is_custom_property, Value};
+
+type PropertyMap = HashMap<String, Value>;
+
+/// Substitute `var()` references inside custom properties, so that each one holds plain text.
+/// Custom properties that reference each other in a cycle, or that reference a missing
+/// variable without a fallback, are invalid and removed.
+pub(crate) fn resolve_custom_properties(values: &mut PropertyMap) {
+    let names: Vec<String> = values.iter()
+        .filter(|(name, value)| is_custom_property(name) && matches!(value, Value::Unparsed(source) if uses_var(source)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut resolver = Resolver { values, stack: Vec::new(), in_cycle: HashSet::new(), done: HashSet::new() };
+    for name in names {
+        resolver.resolve(&name);
+    }
+}
+
+/// Substitute the `var()` references in the value of a standard property and parse the result.
+/// Returns `None` if the value is invalid at computed-value time, in which case the property
+/// behaves as if it were `unset`.
+pub(crate) fn substitute(name: &str, value: &Value, values: &PropertyMap) -> Option<Value> {
+    let (property, source) = match value {
+        Value::Unparsed(source) => (name, source),
+        Value::PendingSubstitution(shorthand, source) => (&**shorthand, source),
+        _ => return Some(value.clone()),
+    };
+    let source = substitute_text(source, &mut |variable| match values.get(variable) {
+        Some(Value::Unparsed(text)) => Some(text.clone()),
+        _ => None
+    })?;
+    if source.trim().is_empty() {
+        return None;
+    }
+    let value = css::parse_declaration(property, &source).into_iter()
+        .find(|declaration| declaration.name == name)?.value;
+    // A CSS-wide keyword can't come from a variable.
+    match css_wide_keyword(&value) {
+        Some(_) => None,
+        None => Some(value),
+    }
+}
+
+/// Resolves custom properties depth-first, so that a variable's references are resolved before
+/// it is substituted anywhere.
+struct Resolver<'v> {
+    values: &'v mut PropertyMap,
+    /// The custom properties being resolved, outermost first.
+    stack: Vec<String>,
+    /// Custom properties found to be part of a reference cycle.
+    in_cycle: HashSet<String>,
+    done: HashSet<String>,
+}
+
+impl Resolver<'_> {
+    /// The resolved text of a custom property, or `None` if it's invalid or not set.
+    fn resolve(&mut self, name: &str) -> Option<String> {
+        if let Some(start) = self.stack.iter().position(|n| n == name) {
+            // Every property from the first visit of `name` onwards references itself.
+            self.in_cycle.extend(self.stack[start..].iter().cloned());
+            return None;
+        }
+        let source = match self.values.get(name) {
+            Some(Value::Unparsed(source)) => source.clone(),
+            _ => return None,
+        };
+        if self.done.contains(name) || !uses_var(&source) {
+            return Some(source);
+        }
+
+        self.stack.push(name.to_string());
+        let resolved = substitute_text(&source, &mut |variable| self.resolve(variable));
+        self.stack.pop();
+        self.done.insert(name.to_string());
+
+        match resolved {
+            Some(text) if !self.in_cycle.contains(name) => {
+                self.values.insert(name.to_string(), Value::Unparsed(text.clone()));
+                Some(text)
+            }
+            _ => {
+                self.values.remove(name);
+                None
+            }
+        }
+    }
+}
+
+fn uses_var(source: &str) -> bool {
+    source.to_ascii_lowercase().contains("var(")
+}
+
+/// Replace each `var(--name)` or `var(--name, fallback)` in `source` with the text `lookup`
+/// returns for the variable, or with the fallback if it returns `None`. Returns `None` if a
+/// variable has no value and no fallback.
+fn substitute_text(source: &str, lookup: &mut dyn FnMut(&str) -> Option<String>) -> Option<String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            quote @ ('"' | '\'') => {
+                let end = string_end(&chars, i, quote);
+                result.extend(&chars[i..end]);
+                i = end;
+            }
+            _ if starts_var_function(&chars, i) => {
+                let arguments_start = i + "var(".len();
+                let end = closing_paren(&chars, arguments_start);
+                let arguments: String = chars[arguments_start..end].iter().collect();
+                let (name, fallback) = match top_level_comma(&arguments) {
+                    Some(comma) => (&arguments[..comma], Some(&arguments[comma + 1..])),
+                    None => (&*arguments, None),
+                };
+                let text = match (lookup(name.trim()), fallback) {
+                    (Some(text), _) => text,
+                    (None, Some(fallback)) => substitute_text(fallback.trim(), lookup)?,
+                    (None, None) => return None,
+                };
+                result.push_str(&text);
+                i = (end + 1).min(chars.len());
+            }
+            c => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+    Some(result)
+}
+
+/// Whether a `var(` function starts at `i`, rather than in the middle of another identifier.
+fn starts_var_function(chars: &[char], i: usize) -> bool {
+    let is_ident = |c: &char| c.is_alphanumeric() || *c == '-' || *c == '_';
+    chars.len() >= i + 4
+        && chars[i..i + 4].iter().collect::<String>().eq_ignore_ascii_case("var(")
+        && (i == 0 || !is_ident(&chars[i - 1]))
+}
+
+/// The index just past the string that starts at `start`.
+fn string_end(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// The index of the `)` that closes a function whose arguments start at `start`.
+fn closing_paren(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            quote @ ('"' | '\'') => { i = string_end(chars, i, quote); continue }
+            '(' => depth += 1,
+            ')' if depth == 0 => return i,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+/// The byte offset of the first comma outside brackets and strings.
+fn top_level_comma(arguments: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (offset, c) in arguments.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 => return Some(offset),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::{Color, Unit};
+    use crate::media::MediaEnvironment;
+    use crate::dom::{Node, NodeType};
+    use crate::html;
+    use crate::style::{style_tree, Cascade};
+
+    /// The first element in a document with the given tag name.
+    fn element<'a>(node: &'a Node, tag_name: &str) -> Option<&'a Node> {
+        match node.node_type {
+            NodeType::Element(ref elem) if elem.tag_name == tag_name => Some(node),
+            _ => node.children.iter().find_map(|child| element(child, tag_name)),
+        }
+    }
+
+    /// Custom properties with the given unparsed values.
+    fn custom_properties(properties: &[(&str, &str)]) -> PropertyMap {
+        properties.iter().map(|&(name, source)| (name.to_string(), Value::Unparsed(source.to_string()))).collect()
+    }
+
+    fn text<'v>(values: &'v PropertyMap, name: &str) -> Option<&'v str> {
+        match values.get(name) {
+            Some(Value::Unparsed(text)) => Some(text),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn cycles_make_custom_properties_invalid() {
+        let mut values = custom_properties(&[
+            ("--a", "var(--b)"),
+            ("--b", "var(--a) 1px"),
+            ("--self", "var(--self, 2px)"),
+            ("--uses-cycle", "var(--a)"),
+            ("--falls-back", "var(--b, 3px)"),
+            ("--plain", "4px"),
+            ("--uses-plain", "var(--plain) var(--plain)"),
+        ]);
+        resolve_custom_properties(&mut values);
+        // Both properties in a cycle, and one that refers to itself, are invalid even with a
+        // fallback.
+        assert_eq!(text(&values, "--a"), None);
+        assert_eq!(text(&values, "--b"), None);
+        assert_eq!(text(&values, "--self"), None);
+        // A property that depends on a cycle without being part of it uses its fallback, if any.
+        assert_eq!(text(&values, "--uses-cycle"), None);
+        assert_eq!(text(&values, "--falls-back"), Some("3px"));
+        assert_eq!(text(&values, "--uses-plain"), Some("4px 4px"));
+    }
+
+    #[test]
+    fn fallbacks_nest() {
+        let mut values = custom_properties(&[
+            ("--plain", "1px"),
+            ("--nested", "var(--missing, var(--also-missing, var(--plain)))"),
+            ("--commas", "var(--missing, a, b)"),
+            ("--unresolved", "var(--missing, var(--also-missing))"),
+        ]);
+        resolve_custom_properties(&mut values);
+        assert_eq!(text(&values, "--nested"), Some("1px"));
+        assert_eq!(text(&values, "--commas"), Some("a, b"));
+        assert_eq!(text(&values, "--unresolved"), None);
+    }
+
+    #[test]
+    fn substitute_parses_the_substituted_value() {
+        let values = custom_properties(&[("--width", "10px"), ("--keyword", "inherit"), ("--color", "red")]);
+        let unparsed = |source: &str| Value::Unparsed(source.to_string());
+        assert_eq!(substitute("width", &unparsed("var(--width)"), &values), Some(Value::Length(10.0, Unit::Px)));
+        assert_eq!(substitute("width", &unparsed("var(--missing, 2em)"), &values), Some(Value::Length(2.0, Unit::Em)));
+        assert_eq!(substitute("width", &unparsed("var(--missing)"), &values), None);
+        assert_eq!(substitute("width", &unparsed("var(--color)"), &values), None);
+        // A CSS-wide keyword can't come from a variable.
+        assert_eq!(substitute("width", &unparsed("var(--keyword)"), &values), None);
+    }
+
+    #[test]
+    fn invalid_substitutions_behave_as_unset() {
+        let stylesheet = crate::css::parse("div { color: green; margin-left: 5px; --keyword: initial } \
+            p { color: var(--missing); margin-left: var(--missing) } \
+            em { color: var(--keyword); margin-left: var(--keyword) }".to_string());
+        let root = html::parse("<div><p>Text</p><em>Text</em></div>".to_string());
+        let styles = style_tree(&root, &Cascade::author(&stylesheet), &MediaEnvironment::screen(800.0, 600.0));
+        let value = |tag_name: &str, name: &str| {
+            styles.find(element(&root, tag_name).unwrap(), None).unwrap().computed_value(name).cloned()
+        };
+        let green = Value::ColorValue(Color { r: 0, g: 128, b: 0, a: 255 });
+        // An inherited property takes its parent's value, and others their initial value.
+        for tag_name in ["p", "em"] {
+            assert_eq!(value(tag_name, "color"), Some(green.clone()));
+            assert_eq!(value(tag_name, "margin-left"), Some(Value::Length(0.0, Unit::Px)));
+        }
+    }
+}